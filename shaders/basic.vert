@@ -0,0 +1,13 @@
+#version 110
+
+uniform mat4 matrix;
+
+attribute vec3 position;
+attribute vec3 color;
+
+varying vec3 v_color;
+
+void main() {
+    gl_Position = matrix * vec4(position, 1.0);
+    v_color = color;
+}
@@ -0,0 +1,40 @@
+// Lambert diffuse and Blinn-Phong specular lighting. A light is packed into the columns of a
+// matrix (see `Light::pack` in lighting.rs):
+//
+//   light[0]   position, kind (0 directional, 1 point, 2 spot)
+//   light[1]   direction the light travels in, range (0 for no fading)
+//   light[2]   color times intensity
+//   light[3]   cosine of the inner angle, cosine of the outer angle
+
+// Return the light reflected towards the eye by a point at `p` with the normal `n`, `v` being
+// the direction from the point to the eye.
+vec3 shade(mat4 light, vec3 p, vec3 n, vec3 v, vec3 diffuse, vec3 specular, float shininess) {
+    vec3 l;
+    float attenuation = 1.0;
+
+    if (light[0].w < 0.5) {
+        l = -normalize(light[1].xyz);
+    } else {
+        vec3 d = light[0].xyz - p;
+        float distance = length(d);
+        l = d / max(distance, 0.0001);
+
+        if (light[1].w > 0.0) {
+            float f = clamp(1.0 - distance / light[1].w, 0.0, 1.0);
+            attenuation = f * f;
+        }
+
+        if (light[0].w > 1.5) {
+            float c = dot(-l, normalize(light[1].xyz));
+            attenuation *= smoothstep(light[3].y, light[3].x, c);
+        }
+    }
+
+    float lambert = max(dot(n, l), 0.0);
+    float highlight = 0.0;
+    if (lambert > 0.0) {
+        highlight = pow(max(dot(n, normalize(l + v)), 0.0), shininess);
+    }
+
+    return light[2].rgb * attenuation * (diffuse * lambert + specular * highlight);
+}
@@ -0,0 +1,40 @@
+#version 110
+
+#include "lighting.glsl"
+
+uniform vec3 camera_position;
+uniform vec3 ambient;
+uniform vec3 diffuse;
+uniform vec3 specular;
+uniform float shininess;
+uniform sampler2D texture;
+
+uniform int light_count;
+uniform mat4 light0;
+uniform mat4 light1;
+uniform mat4 light2;
+uniform mat4 light3;
+
+varying vec3 v_position;
+varying vec3 v_normal;
+varying vec3 v_color;
+varying vec2 v_uv;
+
+void main() {
+    vec3 n = normalize(v_normal);
+    vec3 v = normalize(camera_position - v_position);
+    vec3 base = diffuse * v_color * texture2D(texture, v_uv).rgb;
+
+    // the back of a face is lit like its front
+    if (dot(n, v) < 0.0) {
+        n = -n;
+    }
+
+    vec3 color = ambient * base;
+    if (light_count > 0) color += shade(light0, v_position, n, v, base, specular, shininess);
+    if (light_count > 1) color += shade(light1, v_position, n, v, base, specular, shininess);
+    if (light_count > 2) color += shade(light2, v_position, n, v, base, specular, shininess);
+    if (light_count > 3) color += shade(light3, v_position, n, v, base, specular, shininess);
+
+    gl_FragColor = vec4(color, 1.0);
+}
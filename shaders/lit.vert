@@ -0,0 +1,23 @@
+#version 110
+
+uniform mat4 matrix;
+uniform mat4 model;
+uniform mat3 normal_matrix;
+
+attribute vec3 position;
+attribute vec3 color;
+attribute vec3 normal;
+attribute vec2 uv;
+
+varying vec3 v_position;
+varying vec3 v_normal;
+varying vec3 v_color;
+varying vec2 v_uv;
+
+void main() {
+    v_position = (model * vec4(position, 1.0)).xyz;
+    v_normal = normal_matrix * normal;
+    v_color = color;
+    v_uv = uv;
+    gl_Position = matrix * vec4(position, 1.0);
+}
@@ -0,0 +1,178 @@
+//! Static batching: objects that never move and share a material are merged into one mesh with
+//! their world transforms baked into the vertices, so that a whole level can be drawn with a draw
+//! call per material instead of one per object.
+//!
+//! Every batch remembers which of its triangles came from which object, so that a triangle hit
+//! when picking can still be traced back to the object's name. The triangles are kept in the order
+//! they were added, which is why a batch must not be uploaded with `UploadOptions::optimize`.
+//!
+//! The indices are 16-bit, so a material with more than 65536 vertices worth of objects is split
+//! over several batches. Skinned objects are never batched.
+
+use std::collections::HashMap;
+
+use cgmath;
+use cgmath::{Vector, EuclideanVector, Matrix};
+
+use mesh::Mesh;
+use simplescene::{SimpleSceneFile, SimpleSceneObject};
+
+/// The group objects are put in, usually in Blender, to be batched.
+pub const STATIC_GROUP: &'static str = "Static";
+
+/// The triangles and vertices of a batch that came from one object.
+#[derive(Clone)]
+pub struct BatchRange {
+    pub name:               String,
+    pub first_triangle:     usize,
+    pub triangle_count:     usize,
+    pub first_vertex:       usize,
+    pub vertex_count:       usize,
+}
+
+/// Objects merged into one mesh. The mesh only has triangles.
+pub struct StaticBatch {
+    /// the material every object in the batch uses
+    pub material:           Option<String>,
+    pub mesh:               Mesh,
+    pub ranges:             Vec<BatchRange>,
+}
+
+impl StaticBatch {
+    pub fn new(material: Option<String>) -> StaticBatch {
+        StaticBatch {
+            material:       material,
+            mesh:           Mesh::new(),
+            ranges:         Vec::new(),
+        }
+    }
+
+    /// Return true if an object with `vertices` vertices still fits in the batch.
+    pub fn fits(&self, vertices: usize) -> bool {
+        self.mesh.positions.len() + vertices <= 65536
+    }
+
+    /// Add an object to the batch, moving its vertices by `matrix`.
+    pub fn add(&mut self, object: &SimpleSceneObject, matrix: &cgmath::Matrix4<f32>) {
+        let mut source = Mesh::from_simplescene(object);
+        let count = source.positions.len();
+        if !self.fits(count) {
+            panic!("batch: {} does not fit in the batch", object.name);
+        }
+
+        // The batch keeps a kind of vertex data only while every object in it has it, the first
+        // object decides. Missing normals are generated, other missing data gets defaults.
+        let first_vertex = self.mesh.positions.len();
+        let keep = |len: usize, has: bool| if first_vertex == 0 { has } else { len == first_vertex };
+        let normals = keep(self.mesh.normals.len(), source.normals.len() == count);
+        let uvs = keep(self.mesh.uvs.len(), source.uvs.len() == count);
+        let colors = keep(self.mesh.colors.len(), source.colors.len() == count);
+
+        if normals && source.normals.len() != count {
+            source.smooth_normals();
+        }
+
+        // normals go through the inverse transpose, so that scaling does not bend them
+        let normal_matrix = matrix.invert().map(|m| m.transpose()).unwrap_or(*matrix);
+        let mirrored = matrix.x.truncate().cross(&matrix.y.truncate()).dot(&matrix.z.truncate()) < 0.0;
+
+        for p in source.positions.iter() {
+            self.mesh.positions.push(matrix.mul_v(&cgmath::Vector4::new(p.x, p.y, p.z, 1.0)).truncate());
+        }
+        if normals {
+            for n in source.normals.iter() {
+                let n = normal_matrix.mul_v(&cgmath::Vector4::new(n.x, n.y, n.z, 0.0)).truncate();
+                self.mesh.normals.push(if n.length2() > 0.0 { n.normalize() } else { n });
+            }
+        }
+        for i in 0..count {
+            if uvs {
+                self.mesh.uvs.push(if source.uvs.len() == count { source.uvs[i] } else { cgmath::Vector2::new(0.0, 0.0) });
+            }
+            if colors {
+                self.mesh.colors.push(if source.colors.len() == count { source.colors[i] } else { cgmath::Vector3::new(1.0, 1.0, 1.0) });
+            }
+        }
+
+        let first_triangle = self.mesh.triangles.len();
+        let offset = first_vertex as u16;
+        let mut push = |a: u16, b: u16, c: u16| {
+            let t = if mirrored { [a, c, b] } else { [a, b, c] };
+            self.mesh.triangles.push([t[0] + offset, t[1] + offset, t[2] + offset]);
+        };
+        for t in source.triangles.iter() {
+            push(t[0], t[1], t[2]);
+        }
+        for q in source.quads.iter() {
+            push(q[0], q[1], q[2]);
+            push(q[0], q[2], q[3]);
+        }
+
+        self.ranges.push(BatchRange {
+            name:               object.name.clone(),
+            first_triangle:     first_triangle,
+            triangle_count:     self.mesh.triangles.len() - first_triangle,
+            first_vertex:       first_vertex,
+            vertex_count:       count,
+        });
+    }
+
+    /// Return the range of the object a triangle of the batch came from.
+    pub fn range_of(&self, triangle: usize) -> Option<&BatchRange> {
+        // the ranges are in triangle order
+        let i = match self.ranges.binary_search_by(|r| {
+            if triangle < r.first_triangle {
+                ::std::cmp::Ordering::Greater
+            } else if triangle >= r.first_triangle + r.triangle_count {
+                ::std::cmp::Ordering::Less
+            } else {
+                ::std::cmp::Ordering::Equal
+            }
+        }) {
+            Ok(i) => i,
+            Err(_) => return Option::None,
+        };
+        Option::Some(&self.ranges[i])
+    }
+
+    /// Return the name of the object a triangle of the batch came from.
+    pub fn name_of(&self, triangle: usize) -> Option<&str> {
+        self.range_of(triangle).map(|r| r.name.as_slice())
+    }
+}
+
+/// Merge the mesh objects of a group, normally `STATIC_GROUP`, into batches by material. The
+/// batches are in the order their materials are first used in the scene.
+pub fn static_batches(scene: &SimpleSceneFile, group: &str) -> Vec<StaticBatch> {
+    let mut batches: Vec<StaticBatch> = Vec::new();
+    // the batch currently being filled for every material
+    let mut open: HashMap<Option<String>, usize> = HashMap::new();
+
+    for object in scene.objects.iter() {
+        let object = object.lock().unwrap();
+        if !object.groups.iter().any(|g| g.as_slice() == group) {
+            continue;
+        }
+        if object.typ.as_slice() != "MESH" || object.joints.len() > 0 {
+            continue;
+        }
+        if object.triangles.len() + object.quads.len() == 0 {
+            continue;
+        }
+
+        let count = object.vertices.len();
+        let current = open.get(&object.material).map(|i| *i);
+        let b = match current {
+            Some(i) if batches[i].fits(count) => i,
+            _ => {
+                batches.push(StaticBatch::new(object.material.clone()));
+                open.insert(object.material.clone(), batches.len() - 1);
+                batches.len() - 1
+            },
+        };
+
+        batches[b].add(&*object, &object.world_matrix());
+    }
+
+    batches
+}
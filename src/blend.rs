@@ -0,0 +1,596 @@
+//! Reads Blender `.blend` files directly so that our build machines can import assets without
+//! having Blender (and my export addon) installed. The objects, their meshes and the parenting
+//! are turned into the same `SimpleSceneFile` that loading the simple scene format gives you.
+//!
+//! A `.blend` file is pretty much a memory dump. It is a list of blocks, each holding one or more
+//! structures exactly as they were laid out in memory along with the address they lived at, and
+//! the pointers between them are left as those old addresses. To make sense of the structures the
+//! file carries a description of every one of them (the SDNA), so by decoding that first we can
+//! find fields by name instead of hard coding offsets. This is what lets us read files written by
+//! different versions of Blender on machines with 32-bit or 64-bit pointers and either byte order.
+//!
+//! Meshes are read from the old `mvert`/`mpoly`/`mloop` arrays, the even older `mface` array,
+//! or the generic attribute layers that newer versions use. Compressed files and the new file
+//! header introduced with Blender 5.0 are not supported.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::collections::HashMap;
+use std::old_io::{File, BufReader, Reader};
+
+use cgmath;
+
+use simplescene::{SimpleSceneFile, SimpleSceneObject};
+
+/// One block of the file, the data follows the block header at `offset`.
+struct Block {
+    code:           [u8;4],
+    size:           usize,
+    old:            u64,
+    sdna:           usize,
+    count:          usize,
+    offset:         usize,
+}
+
+/// A field of a structure as described by the SDNA.
+struct DnaField {
+    typ:            String,
+    /// the name with the pointer and array decorations removed
+    name:           String,
+    offset:         usize,
+    pointer:        bool,
+    /// number of elements for arrays (multi-dimensional arrays are flattened)
+    count:          usize,
+}
+
+/// A structure as described by the SDNA.
+struct DnaStruct {
+    typ:            String,
+    size:           usize,
+    fields:         Vec<DnaField>,
+}
+
+/// A decoded `.blend` file.
+struct BlendFile {
+    data:           Vec<u8>,
+    ptrsize:        usize,
+    big:            bool,
+    blocks:         Vec<Block>,
+    structs:        Vec<DnaStruct>,
+    tlen:           Vec<usize>,
+    /// struct index by type name
+    by_type:        HashMap<String, usize>,
+    /// block index by old memory address
+    by_address:     HashMap<u64, usize>,
+}
+
+impl BlendFile {
+    fn open(source: &str) -> BlendFile {
+        let data = File::open(&Path::new(source)).unwrap().read_to_end().unwrap();
+
+        if data.len() >= 2 && data[0] == 0x1f && data[1] == 0x8b {
+            panic!("blend: gzip compressed files are not supported");
+        }
+
+        if data.len() >= 4 && data[0] == 0x28 && data[1] == 0xb5 && data[2] == 0x2f && data[3] == 0xfd {
+            panic!("blend: zstd compressed files are not supported");
+        }
+
+        if data.len() < 12 || &data[..7] != &b"BLENDER"[..] {
+            panic!("blend: not a blend file");
+        }
+
+        let ptrsize = match data[7] {
+            b'_' => 4,
+            b'-' => 8,
+            _ => panic!("blend: unsupported file header"),
+        };
+
+        let big = match data[8] {
+            b'v' => false,
+            b'V' => true,
+            _ => panic!("blend: unsupported file header"),
+        };
+
+        let mut blend = BlendFile {
+            data:           data,
+            ptrsize:        ptrsize,
+            big:            big,
+            blocks:         Vec::new(),
+            structs:        Vec::new(),
+            tlen:           Vec::new(),
+            by_type:        HashMap::new(),
+            by_address:     HashMap::new(),
+        };
+
+        let mut offset = 12;
+        let mut dna: Option<usize> = Option::None;
+
+        while offset + 16 + ptrsize <= blend.data.len() {
+            let code = [blend.data[offset], blend.data[offset + 1], blend.data[offset + 2], blend.data[offset + 3]];
+            let size = blend.u32_at(offset + 4) as usize;
+            let old = blend.ptr_at(offset + 8);
+            let sdna = blend.u32_at(offset + 8 + ptrsize) as usize;
+            let count = blend.u32_at(offset + 12 + ptrsize) as usize;
+
+            if &code == b"ENDB" {
+                break;
+            }
+
+            if &code == b"DNA1" {
+                dna = Option::Some(offset + 16 + ptrsize);
+            }
+
+            blend.by_address.insert(old, blend.blocks.len());
+            blend.blocks.push(Block {
+                code:       code,
+                size:       size,
+                old:        old,
+                sdna:       sdna,
+                count:      count,
+                offset:     offset + 16 + ptrsize,
+            });
+
+            offset += 16 + ptrsize + size;
+        }
+
+        match dna {
+            Some(dna) => blend.read_dna(dna),
+            None => panic!("blend: file has no DNA1 block"),
+        }
+
+        blend
+    }
+
+    /// Decode the SDNA which describes every structure stored in the file.
+    fn read_dna(&mut self, offset: usize) {
+        let mut at = offset;
+
+        self.expect_tag(at, b"SDNA");
+        at += 4;
+
+        self.expect_tag(at, b"NAME");
+        let mut names: Vec<String> = Vec::new();
+        let count = self.u32_at(at + 4) as usize;
+        at += 8;
+        for _ in 0..count {
+            let name = self.cstr_at(at);
+            at += name.len() + 1;
+            names.push(name);
+        }
+        at = align4(at);
+
+        self.expect_tag(at, b"TYPE");
+        let mut types: Vec<String> = Vec::new();
+        let count = self.u32_at(at + 4) as usize;
+        at += 8;
+        for _ in 0..count {
+            let name = self.cstr_at(at);
+            at += name.len() + 1;
+            types.push(name);
+        }
+        at = align4(at);
+
+        self.expect_tag(at, b"TLEN");
+        at += 4;
+        for _ in 0..types.len() {
+            let len = self.u16_at(at) as usize;
+            self.tlen.push(len);
+            at += 2;
+        }
+        at = align4(at);
+
+        self.expect_tag(at, b"STRC");
+        let count = self.u32_at(at + 4) as usize;
+        at += 8;
+        for _ in 0..count {
+            let typ = self.u16_at(at) as usize;
+            let nfields = self.u16_at(at + 2) as usize;
+            at += 4;
+
+            let mut fields: Vec<DnaField> = Vec::new();
+            let mut foffset = 0;
+            for _ in 0..nfields {
+                let ftyp = self.u16_at(at) as usize;
+                let fname = self.u16_at(at + 2) as usize;
+                at += 4;
+
+                let (name, pointer, count) = parse_name(names[fname].as_slice());
+                let size = if pointer { self.ptrsize } else { self.tlen[ftyp] };
+
+                fields.push(DnaField {
+                    typ:        types[ftyp].clone(),
+                    name:       name,
+                    offset:     foffset,
+                    pointer:    pointer,
+                    count:      count,
+                });
+
+                foffset += size * count;
+            }
+
+            self.by_type.insert(types[typ].clone(), self.structs.len());
+            self.structs.push(DnaStruct {
+                typ:        types[typ].clone(),
+                size:       self.tlen[typ],
+                fields:     fields,
+            });
+        }
+    }
+
+    fn expect_tag(&self, at: usize, tag: &[u8]) {
+        if &self.data[at..at + 4] != tag {
+            panic!("blend: corrupt DNA1 block");
+        }
+    }
+
+    fn u16_at(&self, at: usize) -> u16 {
+        let mut r = BufReader::new(&self.data[at..at + 2]);
+        if self.big { r.read_be_u16().unwrap() } else { r.read_le_u16().unwrap() }
+    }
+
+    fn u32_at(&self, at: usize) -> u32 {
+        let mut r = BufReader::new(&self.data[at..at + 4]);
+        if self.big { r.read_be_u32().unwrap() } else { r.read_le_u32().unwrap() }
+    }
+
+    fn u64_at(&self, at: usize) -> u64 {
+        let mut r = BufReader::new(&self.data[at..at + 8]);
+        if self.big { r.read_be_u64().unwrap() } else { r.read_le_u64().unwrap() }
+    }
+
+    fn ptr_at(&self, at: usize) -> u64 {
+        if self.ptrsize == 8 { self.u64_at(at) } else { self.u32_at(at) as u64 }
+    }
+
+    fn cstr_at(&self, at: usize) -> String {
+        let end = self.data[at..].iter().position(|b| *b == 0).map(|p| at + p).unwrap_or(self.data.len());
+        String::from_utf8_lossy(&self.data[at..end]).into_owned()
+    }
+
+    /// Read element `i` of a numeric field stored at `at`, whatever its storage type.
+    fn number_at(&self, typ: &str, at: usize, i: usize) -> f64 {
+        let size = match typ {
+            "char" | "uchar" => 1,
+            "short" | "ushort" => 2,
+            "int" | "float" => 4,
+            "double" => 8,
+            _ => panic!("blend: unsupported field type {}", typ),
+        };
+        let at = at + i * size;
+        let mut r = BufReader::new(&self.data[at..at + size]);
+        match (typ, self.big) {
+            ("char", _) => r.read_i8().unwrap() as f64,
+            ("uchar", _) => r.read_u8().unwrap() as f64,
+            ("short", false) => r.read_le_i16().unwrap() as f64,
+            ("short", true) => r.read_be_i16().unwrap() as f64,
+            ("ushort", false) => r.read_le_u16().unwrap() as f64,
+            ("ushort", true) => r.read_be_u16().unwrap() as f64,
+            ("int", false) => r.read_le_i32().unwrap() as f64,
+            ("int", true) => r.read_be_i32().unwrap() as f64,
+            ("float", false) => r.read_le_f32().unwrap() as f64,
+            ("float", true) => r.read_be_f32().unwrap() as f64,
+            ("double", false) => r.read_le_f64().unwrap(),
+            _ => r.read_be_f64().unwrap(),
+        }
+    }
+
+    fn field(&self, sdna: usize, name: &str) -> Option<&DnaField> {
+        self.structs[sdna].fields.iter().find(|f| f.name.as_slice().eq(name))
+    }
+
+    /// Return all elements of a numeric field, or nothing if the structure does not have it.
+    fn numbers(&self, sdna: usize, base: usize, name: &str) -> Option<Vec<f64>> {
+        self.field(sdna, name).map(|f| {
+            (0..f.count).map(|i| self.number_at(f.typ.as_slice(), base + f.offset, i)).collect()
+        })
+    }
+
+    fn number(&self, sdna: usize, base: usize, name: &str) -> Option<f64> {
+        self.numbers(sdna, base, name).map(|v| v[0])
+    }
+
+    fn pointer(&self, sdna: usize, base: usize, name: &str) -> Option<u64> {
+        match self.field(sdna, name) {
+            Some(f) if f.pointer => Option::Some(self.ptr_at(base + f.offset)),
+            _ => Option::None,
+        }
+    }
+
+    fn string(&self, sdna: usize, base: usize, name: &str) -> Option<String> {
+        self.field(sdna, name).map(|f| self.cstr_at(base + f.offset))
+    }
+
+    /// Return the structure index and location of an embedded structure field.
+    fn nested(&self, sdna: usize, base: usize, name: &str) -> Option<(usize, usize)> {
+        self.field(sdna, name).and_then(|f| {
+            self.by_type.get(&f.typ).map(|s| (*s, base + f.offset))
+        })
+    }
+
+    /// Return the block that lived at `address` when the file was saved.
+    fn block_at(&self, address: u64) -> Option<&Block> {
+        if address == 0 {
+            return Option::None;
+        }
+        self.by_address.get(&address).map(|b| &self.blocks[*b])
+    }
+
+    /// Return the name of an ID block (objects, meshes and so on) without the two letter prefix.
+    fn id_name(&self, sdna: usize, base: usize) -> String {
+        match self.nested(sdna, base, "id").and_then(|(s, b)| self.string(s, b, "name")) {
+            Some(name) => String::from_str(if name.len() > 2 { &name[2..] } else { "" }),
+            None => String::new(),
+        }
+    }
+
+    /// Return the raw data of a custom data layer by name from a `CustomData` field.
+    fn layer(&self, sdna: usize, base: usize, cdname: &str, layer: &str) -> Option<&Block> {
+        let (cd, cdbase) = match self.nested(sdna, base, cdname) {
+            Some(v) => v,
+            None => return Option::None,
+        };
+        let layers = match self.pointer(cd, cdbase, "layers").and_then(|p| self.block_at(p)) {
+            Some(b) => b,
+            None => return Option::None,
+        };
+        let total = self.number(cd, cdbase, "totlayer").unwrap_or(0.0) as usize;
+        let lsdna = match self.by_type.get("CustomDataLayer") {
+            Some(s) => *s,
+            None => return Option::None,
+        };
+
+        for i in 0..total {
+            let lbase = layers.offset + i * self.structs[lsdna].size;
+            if self.string(lsdna, lbase, "name").map(|n| n.as_slice().eq(layer)).unwrap_or(false) {
+                return self.pointer(lsdna, lbase, "data").and_then(|p| self.block_at(p));
+            }
+        }
+
+        Option::None
+    }
+}
+
+fn align4(at: usize) -> usize {
+    (at + 3) & !3
+}
+
+/// Split a field name like `*next`, `name[66]` or `(*func)()` into the bare name, whether it is
+/// a pointer and the number of array elements.
+fn parse_name(name: &str) -> (String, bool, usize) {
+    let pointer = name.contains("*");
+    let bare: String = name.chars()
+        .take_while(|c| *c != '[')
+        .filter(|c| *c != '*' && *c != '(' && *c != ')')
+        .collect();
+
+    let mut count = 1;
+    for dim in name.split('[').skip(1) {
+        count *= dim.trim_right_matches(']').parse::<usize>().unwrap();
+    }
+
+    (bare, pointer, count)
+}
+
+/// Return a simple scene instance by reading the objects, meshes and parenting out of a
+/// `.blend` file.
+pub fn load(source: &str) -> SimpleSceneFile {
+    let blend = BlendFile::open(source);
+
+    let mut objects: Vec<Arc<Mutex<SimpleSceneObject>>> = Vec::new();
+    let mut by_address: HashMap<u64, Arc<Mutex<SimpleSceneObject>>> = HashMap::new();
+    let mut relocs: Vec<(Arc<Mutex<SimpleSceneObject>>, u64)> = Vec::new();
+
+    for block in blend.blocks.iter() {
+        if &block.code != b"OB\0\0" {
+            continue;
+        }
+
+        let (sdna, base) = (block.sdna, block.offset);
+        let mut object = SimpleSceneObject::new();
+
+        object.name = blend.id_name(sdna, base);
+
+        if let Some(loc) = blend.numbers(sdna, base, "loc") {
+            object.location = cgmath::Vector3::new(loc[0] as f32, loc[1] as f32, loc[2] as f32);
+        }
+
+        // renamed at some point, older files only have `size`
+        if let Some(s) = blend.numbers(sdna, base, "size").or(blend.numbers(sdna, base, "scale")) {
+            object.scale = cgmath::Vector3::new(s[0] as f32, s[1] as f32, s[2] as f32);
+        }
+
+        object.rotation = rotation(&blend, sdna, base);
+
+        let typ = blend.number(sdna, base, "type").unwrap_or(0.0) as i32;
+        object.typ = String::from_str(match typ {
+            1 => "MESH",
+            10 => "LAMP",
+            11 => "CAMERA",
+            25 => "ARMATURE",
+            0 => "EMPTY",
+            _ => "OTHER",
+        });
+
+        if typ == 1 {
+            if let Some(mesh) = blend.pointer(sdna, base, "data").and_then(|p| blend.block_at(p)) {
+                load_mesh(&blend, mesh, &mut object);
+            }
+        }
+
+        let object = Arc::new(Mutex::new(object));
+
+        if let Some(parent) = blend.pointer(sdna, base, "parent") {
+            if parent != 0 {
+                relocs.push((object.clone(), parent));
+            }
+        }
+
+        by_address.insert(block.old, object.clone());
+        objects.push(object);
+    }
+
+    for pair in relocs.iter() {
+        let ref object = pair.0;
+        match by_address.get(&pair.1) {
+            Some(pobject) => {
+                object.lock().unwrap().parent = Option::Some(pobject.clone());
+                pobject.lock().unwrap().child.push(object.clone());
+            },
+            None => panic!("could not find parent"),
+        }
+    }
+
+    let scene = SimpleSceneFile {
+        path:           Path::new(source),
+        objects:        objects,
+        materials:      Vec::new(),
+        animations:     Vec::new(),
+    };
+
+    scene.update_bounds();
+    scene
+}
+
+/// Return the rotation of an object using whichever rotation mode it is set to.
+fn rotation(blend: &BlendFile, sdna: usize, base: usize) -> cgmath::Quaternion<f32> {
+    use cgmath::Rotation3;
+
+    let mode = blend.number(sdna, base, "rotmode").unwrap_or(1.0) as i32;
+
+    match mode {
+        0 => {
+            let q = blend.numbers(sdna, base, "quat").unwrap();
+            cgmath::Quaternion::new(q[0] as f32, q[1] as f32, q[2] as f32, q[3] as f32)
+        },
+        -1 => {
+            let axis = blend.numbers(sdna, base, "rotAxis").unwrap();
+            let angle = blend.number(sdna, base, "rotAngle").unwrap();
+            let q: cgmath::Quaternion<f32> = Rotation3::from_axis_angle(
+                &cgmath::Vector3::new(axis[0] as f32, axis[1] as f32, axis[2] as f32),
+                cgmath::Rad { s: angle as f32 }
+            );
+            q
+        },
+        _ => {
+            let rot = blend.numbers(sdna, base, "rot").unwrap();
+            // the euler orders in the order Blender numbers them starting at one
+            let order: [usize; 3] = match mode {
+                2 => [0, 2, 1],
+                3 => [1, 0, 2],
+                4 => [1, 2, 0],
+                5 => [2, 0, 1],
+                6 => [2, 1, 0],
+                _ => [0, 1, 2],
+            };
+            let axes = [
+                cgmath::Vector3::new(1.0f32, 0.0, 0.0),
+                cgmath::Vector3::new(0.0f32, 1.0, 0.0),
+                cgmath::Vector3::new(0.0f32, 0.0, 1.0),
+            ];
+
+            // The first axis in the order is applied first, so it goes on the right.
+            let mut q: cgmath::Quaternion<f32> = cgmath::Quaternion::from_sv(1.0, cgmath::Vector3::new(0.0, 0.0, 0.0));
+            for a in order.iter() {
+                let r: cgmath::Quaternion<f32> = Rotation3::from_axis_angle(&axes[*a], cgmath::Rad { s: rot[*a] as f32 });
+                q = r.mul_q(&q);
+            }
+            q
+        },
+    }
+}
+
+/// Read the vertices and faces of the `Mesh` stored in `block` into the object.
+fn load_mesh(blend: &BlendFile, block: &Block, object: &mut SimpleSceneObject) {
+    let (sdna, base) = (block.sdna, block.offset);
+    let totvert = blend.number(sdna, base, "totvert").unwrap_or(0.0) as usize;
+
+    if totvert > 65536 {
+        panic!("blend: mesh has {} vertices which is too many for 16-bit indices", totvert);
+    }
+
+    match blend.pointer(sdna, base, "mvert").and_then(|p| blend.block_at(p)) {
+        Some(mvert) => {
+            let vsdna = mvert.sdna;
+            let size = blend.structs[vsdna].size;
+            let has_normals = blend.field(vsdna, "no").is_some();
+            for i in 0..mvert.count {
+                let at = mvert.offset + i * size;
+                let co = blend.numbers(vsdna, at, "co").unwrap();
+                object.vertices.push(cgmath::Vector3::new(co[0] as f32, co[1] as f32, co[2] as f32));
+                // normals were stored as shorts scaled to the full range
+                if has_normals {
+                    let no = blend.numbers(vsdna, at, "no").unwrap();
+                    object.normals.push(cgmath::Vector3::new(
+                        (no[0] / 32767.0) as f32, (no[1] / 32767.0) as f32, (no[2] / 32767.0) as f32
+                    ));
+                }
+            }
+        },
+        None => {
+            // newer versions keep the positions as a generic attribute
+            if let Some(layer) = blend.layer(sdna, base, "vdata", "position") {
+                for i in 0..totvert {
+                    let at = layer.offset + i * 12;
+                    object.vertices.push(cgmath::Vector3::new(
+                        blend.number_at("float", at, 0) as f32,
+                        blend.number_at("float", at, 1) as f32,
+                        blend.number_at("float", at, 2) as f32
+                    ));
+                }
+            }
+        },
+    }
+
+    let mut faces: Vec<Vec<u16>> = Vec::new();
+    let mpoly = blend.pointer(sdna, base, "mpoly").and_then(|p| blend.block_at(p));
+    let mloop = blend.pointer(sdna, base, "mloop").and_then(|p| blend.block_at(p));
+    let offsets = blend.pointer(sdna, base, "poly_offset_indices")
+        .or(blend.pointer(sdna, base, "face_offset_indices"))
+        .and_then(|p| blend.block_at(p));
+    let corners = blend.layer(sdna, base, "ldata", ".corner_vert");
+
+    if let (Some(mpoly), Some(mloop)) = (mpoly, mloop) {
+        let psdna = mpoly.sdna;
+        let lsdna = mloop.sdna;
+        let lsize = blend.structs[lsdna].size;
+        for i in 0..mpoly.count {
+            let at = mpoly.offset + i * blend.structs[psdna].size;
+            let start = blend.number(psdna, at, "loopstart").unwrap() as usize;
+            let total = blend.number(psdna, at, "totloop").unwrap() as usize;
+            faces.push((start..start + total).map(|l| {
+                blend.number(lsdna, mloop.offset + l * lsize, "v").unwrap() as u16
+            }).collect());
+        }
+    } else if let (Some(offsets), Some(corners)) = (offsets, corners) {
+        let totpoly = blend.number(sdna, base, "totpoly").unwrap_or(0.0) as usize;
+        for i in 0..totpoly {
+            let start = blend.number_at("int", offsets.offset, i) as usize;
+            let end = blend.number_at("int", offsets.offset, i + 1) as usize;
+            faces.push((start..end).map(|l| blend.number_at("int", corners.offset, l) as u16).collect());
+        }
+    } else if let Some(mface) = blend.pointer(sdna, base, "mface").and_then(|p| blend.block_at(p)) {
+        let fsdna = mface.sdna;
+        for i in 0..mface.count {
+            let at = mface.offset + i * blend.structs[fsdna].size;
+            let v: Vec<u16> = ["v1", "v2", "v3", "v4"].iter().map(|n| {
+                blend.number(fsdna, at, *n).unwrap() as u16
+            }).collect();
+            // a zero fourth index marks a triangle
+            faces.push(if v[3] == 0 { v[..3].to_vec() } else { v });
+        }
+    }
+
+    for face in faces.iter() {
+        match face.len() {
+            3 => object.triangles.push([face[0], face[1], face[2]]),
+            4 => object.quads.push([face[0], face[1], face[2], face[3]]),
+            n if n > 4 => {
+                for i in 1..n - 1 {
+                    object.triangles.push([face[0], face[i], face[i + 1]]);
+                }
+            },
+            _ => {},
+        }
+    }
+}
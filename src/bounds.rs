@@ -0,0 +1,184 @@
+//! Bounding volumes: axis aligned boxes and spheres. Both are cheap to test against and are
+//! what culling, framing an object with the camera and the first step of collision detection
+//! work with.
+//!
+//! An empty box (nothing inside it) has its minimum above its maximum, so that growing it by a
+//! point or joining it with another box just works.
+
+use std::num::Float;
+
+use cgmath;
+use cgmath::{Vector, EuclideanVector, Matrix};
+
+/// An axis aligned bounding box.
+#[derive(Copy, Clone)]
+pub struct Aabb {
+    pub min:            cgmath::Vector3<f32>,
+    pub max:            cgmath::Vector3<f32>,
+}
+
+impl Aabb {
+    /// Return a box with nothing in it.
+    pub fn empty() -> Aabb {
+        let big: f32 = Float::max_value();
+        Aabb {
+            min:    cgmath::Vector3::new(big, big, big),
+            max:    cgmath::Vector3::new(-big, -big, -big),
+        }
+    }
+
+    /// Return the smallest box around the points.
+    pub fn from_points(points: &[cgmath::Vector3<f32>]) -> Aabb {
+        let mut b = Aabb::empty();
+        for p in points.iter() {
+            b.add_point(p);
+        }
+        b
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    /// Grow the box to take in a point.
+    pub fn add_point(&mut self, p: &cgmath::Vector3<f32>) {
+        self.min = cgmath::Vector3::new(self.min.x.min(p.x), self.min.y.min(p.y), self.min.z.min(p.z));
+        self.max = cgmath::Vector3::new(self.max.x.max(p.x), self.max.y.max(p.y), self.max.z.max(p.z));
+    }
+
+    /// Return the smallest box around both boxes.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut b = *self;
+        if !other.is_empty() {
+            b.add_point(&other.min);
+            b.add_point(&other.max);
+        }
+        b
+    }
+
+    pub fn center(&self) -> cgmath::Vector3<f32> {
+        self.min.add_v(&self.max).mul_s(0.5)
+    }
+
+    /// Return half of the size of the box along each axis.
+    pub fn extent(&self) -> cgmath::Vector3<f32> {
+        self.max.sub_v(&self.min).mul_s(0.5)
+    }
+
+    pub fn contains(&self, p: &cgmath::Vector3<f32>) -> bool {
+        p.x >= self.min.x && p.y >= self.min.y && p.z >= self.min.z &&
+        p.x <= self.max.x && p.y <= self.max.y && p.z <= self.max.z
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x && self.min.y <= other.max.y && self.min.z <= other.max.z &&
+        other.min.x <= self.max.x && other.min.y <= self.max.y && other.min.z <= self.max.z
+    }
+
+    /// Return the box around this box after a transformation. The result is not as tight as
+    /// the box around the transformed points would be, but it needs no points.
+    pub fn transform(&self, m: &cgmath::Matrix4<f32>) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+
+        // Each axis of the transformation stretches the extent by its absolute value.
+        let c = m.mul_v(&point(&self.center())).truncate();
+        let e = self.extent();
+        let abs = |v: cgmath::Vector4<f32>| cgmath::Vector3::new(v.x.abs(), v.y.abs(), v.z.abs());
+        let e = abs(m.x).mul_s(e.x).add_v(&abs(m.y).mul_s(e.y)).add_v(&abs(m.z).mul_s(e.z));
+
+        Aabb { min: c.sub_v(&e), max: c.add_v(&e) }
+    }
+}
+
+/// A bounding sphere.
+#[derive(Copy, Clone)]
+pub struct BoundingSphere {
+    pub center:         cgmath::Vector3<f32>,
+    /// negative for an empty sphere
+    pub radius:         f32,
+}
+
+impl BoundingSphere {
+    /// Return a sphere with nothing in it.
+    pub fn empty() -> BoundingSphere {
+        BoundingSphere { center: cgmath::Vector3::new(0.0, 0.0, 0.0), radius: -1.0 }
+    }
+
+    /// Return a sphere around the points. It is found with Ritter's method so it is close to
+    /// but not always the smallest one.
+    pub fn from_points(points: &[cgmath::Vector3<f32>]) -> BoundingSphere {
+        if points.len() == 0 {
+            return BoundingSphere::empty();
+        }
+
+        let farthest = |from: &cgmath::Vector3<f32>| -> cgmath::Vector3<f32> {
+            let mut best = points[0];
+            for p in points.iter() {
+                if p.sub_v(from).length2() > best.sub_v(from).length2() {
+                    best = *p;
+                }
+            }
+            best
+        };
+
+        // start with the sphere through two points far from each other
+        let a = farthest(&points[0]);
+        let b = farthest(&a);
+        let mut s = BoundingSphere { center: a.add_v(&b).mul_s(0.5), radius: b.sub_v(&a).length() * 0.5 };
+
+        for p in points.iter() {
+            s.add_point(p);
+        }
+        s
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.radius < 0.0
+    }
+
+    /// Grow the sphere just enough to take in a point.
+    pub fn add_point(&mut self, p: &cgmath::Vector3<f32>) {
+        if self.is_empty() {
+            self.center = *p;
+            self.radius = 0.0;
+            return;
+        }
+
+        let d = p.sub_v(&self.center);
+        let distance = d.length();
+        if distance > self.radius {
+            let radius = (self.radius + distance) * 0.5;
+            self.center = self.center.add_v(&d.mul_s((radius - self.radius) / distance));
+            self.radius = radius;
+        }
+    }
+
+    pub fn contains(&self, p: &cgmath::Vector3<f32>) -> bool {
+        !self.is_empty() && p.sub_v(&self.center).length() <= self.radius
+    }
+
+    pub fn intersects(&self, other: &BoundingSphere) -> bool {
+        !self.is_empty() && !other.is_empty() &&
+        other.center.sub_v(&self.center).length() <= self.radius + other.radius
+    }
+
+    /// Return the sphere around this sphere after a transformation. With a non-uniform scale
+    /// the radius grows by the largest of the scales.
+    pub fn transform(&self, m: &cgmath::Matrix4<f32>) -> BoundingSphere {
+        if self.is_empty() {
+            return *self;
+        }
+
+        let scale = m.x.truncate().length().max(m.y.truncate().length()).max(m.z.truncate().length());
+        BoundingSphere {
+            center: m.mul_v(&point(&self.center)).truncate(),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+fn point(v: &cgmath::Vector3<f32>) -> cgmath::Vector4<f32> {
+    cgmath::Vector4::new(v.x, v.y, v.z, 1.0)
+}
@@ -0,0 +1,282 @@
+//! Loads motion capture in the BVH format. The `HIERARCHY` section gives the joints of the
+//! skeleton with their offsets from the parent joint and the order of their channels, and the
+//! `MOTION` section gives the channel values for every frame.
+//!
+//! The result can be turned into a `SimpleSceneFile` with one object per joint (parented the
+//! same way) and an animation driving them, or a single frame can be applied directly to the
+//! `SkeletonObject` hierarchy in `main` where the objects are matched to joints by name.
+//!
+//! Rotations in BVH are euler angles in degrees applied in the order the channels are listed,
+//! so `Zrotation Xrotation Yrotation` means the joint rotation is `Z * X * Y`.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::num::Float;
+use std::old_io::{File, Open, Read};
+
+use cgmath;
+
+use simplescene::{SimpleSceneFile, SimpleSceneObject};
+use simplescene::{SimpleSceneAnimation, SimpleSceneChannel, SimpleSceneProperty, SimpleSceneInterpolation};
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum BvhChannel {
+    Xposition,
+    Yposition,
+    Zposition,
+    Xrotation,
+    Yrotation,
+    Zrotation,
+}
+
+/// A joint of the skeleton.
+pub struct BvhJoint {
+    pub name:           String,
+    /// offset from the parent joint
+    pub offset:         cgmath::Vector3<f32>,
+    pub channels:       Vec<BvhChannel>,
+    pub parent:         Option<usize>,
+    pub children:       Vec<usize>,
+    /// offset of the end site of a leaf joint, which gives the length of the last bone
+    pub end:            Option<cgmath::Vector3<f32>>,
+    /// index of the first channel of this joint in a frame
+    first:              usize,
+}
+
+/// Represents the skeleton and motion loaded from a BVH file.
+pub struct BvhFile {
+    /// the joints in file order, so a parent always comes before its children
+    pub joints:         Vec<BvhJoint>,
+    /// seconds per frame
+    pub frame_time:     f32,
+    /// the values of every channel for every frame
+    pub frames:         Vec<Vec<f32>>,
+}
+
+struct Tokens<'a> {
+    words:          Vec<&'a str>,
+    next:           usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> &'a str {
+        if self.next >= self.words.len() {
+            panic!("bvh: unexpected end of file");
+        }
+        self.next += 1;
+        self.words[self.next - 1]
+    }
+
+    fn expect(&mut self, word: &str) {
+        let found = self.next();
+        if found != word {
+            panic!("bvh: expected {} but found {}", word, found);
+        }
+    }
+
+    fn number(&mut self) -> f32 {
+        self.next().parse::<f32>().unwrap()
+    }
+
+    fn vector(&mut self) -> cgmath::Vector3<f32> {
+        let x = self.number();
+        let y = self.number();
+        let z = self.number();
+        cgmath::Vector3::new(x, y, z)
+    }
+}
+
+impl BvhFile {
+    /// Return the skeleton and motion loaded from a file source.
+    pub fn from_file(source: &str) -> BvhFile {
+        let path = Path::new(source);
+        let mut file = File::open_mode(&path, Open, Read).unwrap();
+        let data = file.read_to_string().unwrap();
+
+        let mut tokens = Tokens { words: data.words().collect(), next: 0 };
+        let mut bvh = BvhFile {
+            joints:         Vec::new(),
+            frame_time:     0.0,
+            frames:         Vec::new(),
+        };
+
+        tokens.expect("HIERARCHY");
+        tokens.expect("ROOT");
+        let mut channels = 0;
+        bvh.read_joint(&mut tokens, Option::None, &mut channels);
+
+        tokens.expect("MOTION");
+        tokens.expect("Frames:");
+        let count = tokens.number() as usize;
+        tokens.expect("Frame");
+        tokens.expect("Time:");
+        bvh.frame_time = tokens.number();
+
+        for _ in 0..count {
+            bvh.frames.push((0..channels).map(|_| tokens.number()).collect());
+        }
+
+        bvh
+    }
+
+    /// Read a joint and its children, the `ROOT` or `JOINT` keyword has already been read.
+    fn read_joint(&mut self, tokens: &mut Tokens, parent: Option<usize>, channels: &mut usize) -> usize {
+        let index = self.joints.len();
+
+        self.joints.push(BvhJoint {
+            name:       String::from_str(tokens.next()),
+            offset:     cgmath::Vector3::new(0.0, 0.0, 0.0),
+            channels:   Vec::new(),
+            parent:     parent,
+            children:   Vec::new(),
+            end:        Option::None,
+            first:      *channels,
+        });
+
+        tokens.expect("{");
+
+        loop {
+            match tokens.next() {
+                "OFFSET" => self.joints[index].offset = tokens.vector(),
+                "CHANNELS" => {
+                    let n = tokens.number() as usize;
+                    for _ in 0..n {
+                        let channel = match tokens.next() {
+                            "Xposition" => BvhChannel::Xposition,
+                            "Yposition" => BvhChannel::Yposition,
+                            "Zposition" => BvhChannel::Zposition,
+                            "Xrotation" => BvhChannel::Xrotation,
+                            "Yrotation" => BvhChannel::Yrotation,
+                            "Zrotation" => BvhChannel::Zrotation,
+                            other => panic!("bvh: unknown channel {}", other),
+                        };
+                        self.joints[index].channels.push(channel);
+                    }
+                    *channels += n;
+                },
+                "JOINT" => {
+                    let child = self.read_joint(tokens, Option::Some(index), channels);
+                    self.joints[index].children.push(child);
+                },
+                "End" => {
+                    tokens.expect("Site");
+                    tokens.expect("{");
+                    tokens.expect("OFFSET");
+                    self.joints[index].end = Option::Some(tokens.vector());
+                    tokens.expect("}");
+                },
+                "}" => break,
+                other => panic!("bvh: unexpected {}", other),
+            }
+        }
+
+        index
+    }
+
+    /// Return the index of a joint by it's name.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|j| j.name.as_slice().eq(name))
+    }
+
+    /// Return the local location and rotation of a joint in a frame. The location includes the
+    /// joint offset, so for joints without position channels it is just the offset.
+    pub fn pose(&self, frame: usize, joint: usize) -> (cgmath::Vector3<f32>, cgmath::Quaternion<f32>) {
+        use cgmath::Rotation3;
+
+        let joint = &self.joints[joint];
+        let values = &self.frames[frame][joint.first..joint.first + joint.channels.len()];
+
+        let mut location = joint.offset;
+        let mut rotation: cgmath::Quaternion<f32> = cgmath::Quaternion::from_sv(1.0, cgmath::Vector3::new(0.0, 0.0, 0.0));
+
+        for (channel, value) in joint.channels.iter().zip(values.iter()) {
+            let axis = match *channel {
+                BvhChannel::Xposition => { location.x += *value; continue; },
+                BvhChannel::Yposition => { location.y += *value; continue; },
+                BvhChannel::Zposition => { location.z += *value; continue; },
+                BvhChannel::Xrotation => cgmath::Vector3::new(1.0, 0.0, 0.0),
+                BvhChannel::Yrotation => cgmath::Vector3::new(0.0, 1.0, 0.0),
+                BvhChannel::Zrotation => cgmath::Vector3::new(0.0, 0.0, 1.0),
+            };
+            let r: cgmath::Quaternion<f32> = Rotation3::from_axis_angle(&axis, cgmath::Rad { s: value.to_radians() });
+            rotation = rotation.mul_q(&r);
+        }
+
+        (location, rotation)
+    }
+
+    /// Return a scene with an `EMPTY` object for every joint, parented like the joints, posed
+    /// at the rest position and with an animation holding the captured motion.
+    pub fn to_simplescene(&self, source: &str) -> SimpleSceneFile {
+        let mut objects: Vec<Arc<Mutex<SimpleSceneObject>>> = Vec::new();
+        let mut channels: Vec<SimpleSceneChannel> = Vec::new();
+        let times: Vec<f32> = (0..self.frames.len()).map(|f| f as f32 * self.frame_time).collect();
+
+        for (i, joint) in self.joints.iter().enumerate() {
+            let mut object = SimpleSceneObject::new();
+            object.name = joint.name.clone();
+            object.typ = String::from_str("EMPTY");
+            object.location = joint.offset;
+
+            let object = Arc::new(Mutex::new(object));
+
+            if let Some(parent) = joint.parent {
+                object.lock().unwrap().parent = Option::Some(objects[parent].clone());
+                objects[parent].lock().unwrap().child.push(object.clone());
+            }
+
+            objects.push(object);
+
+            let poses: Vec<(cgmath::Vector3<f32>, cgmath::Quaternion<f32>)> = (0..self.frames.len()).map(|f| {
+                self.pose(f, i)
+            }).collect();
+
+            let positioned = joint.channels.iter().any(|c| {
+                *c == BvhChannel::Xposition || *c == BvhChannel::Yposition || *c == BvhChannel::Zposition
+            });
+
+            if positioned {
+                channels.push(SimpleSceneChannel {
+                    target:         joint.name.clone(),
+                    property:       SimpleSceneProperty::Location,
+                    interpolation:  SimpleSceneInterpolation::Linear,
+                    times:          times.clone(),
+                    values:         poses.iter().map(|p| [p.0.x, p.0.y, p.0.z, 0.0]).collect(),
+                });
+            }
+
+            if joint.channels.len() > 0 {
+                channels.push(SimpleSceneChannel {
+                    target:         joint.name.clone(),
+                    property:       SimpleSceneProperty::Rotation,
+                    interpolation:  SimpleSceneInterpolation::Linear,
+                    times:          times.clone(),
+                    values:         poses.iter().map(|p| [p.1.v.x, p.1.v.y, p.1.v.z, p.1.s]).collect(),
+                });
+            }
+        }
+
+        SimpleSceneFile {
+            path:           Path::new(source),
+            objects:        objects,
+            materials:      Vec::new(),
+            animations:     vec![SimpleSceneAnimation {
+                name:       String::from_str(Path::new(source).filestem_str().unwrap_or("motion")),
+                channels:   channels,
+            }],
+        }
+    }
+}
+
+/// Return the euler angles in radians, applied X then Y then Z, of a rotation.
+pub fn to_euler(q: &cgmath::Quaternion<f32>) -> cgmath::Vector3<f32> {
+    use cgmath::ToMatrix3;
+
+    // For R = Z * Y * X the bottom row is (-sin y, cos y sin x, cos y cos x)
+    // and the first column is (cos z cos y, sin z cos y, -sin y).
+    let m = q.to_matrix3();
+    let sy = -m.x.z;
+    let sy = if sy > 1.0 { 1.0 } else if sy < -1.0 { -1.0 } else { sy };
+
+    cgmath::Vector3::new(m.y.z.atan2(m.z.z), sy.asin(), m.x.y.atan2(m.x.x))
+}
@@ -0,0 +1,347 @@
+//! The camera the scene is seen through and controllers that move it around from user input.
+//!
+//! The camera keeps the view (where it is and which way it looks) apart from the projection
+//! (how the view is flattened onto the screen), and both apart from the model matrices of the
+//! objects. A drawable gets its final matrix from `DrawableObject::update_matrix`.
+//!
+//! Like the camera objects Blender exports, the camera looks down its own -Z axis with +Y up.
+//! The controllers treat world +Z as up, which is how the scenes are made.
+
+use std::num::Float;
+
+use cgmath;
+use cgmath::{Vector, EuclideanVector, Matrix, Rotation, ToMatrix4};
+
+use glutin;
+
+use raycast;
+use raycast::Ray;
+use simplescene::quaternion_from_axes;
+
+/// How the view is flattened onto the screen.
+#[derive(Copy, Clone)]
+pub enum Projection {
+    /// things further away look smaller, `fov` is the vertical field of view in degrees
+    Perspective { fov: f32, near: f32, far: f32 },
+    /// things keep their size at any distance, `height` is how much of the world fits from the
+    /// bottom of the screen to the top
+    Orthographic { height: f32, near: f32, far: f32 },
+}
+
+#[derive(Copy, Clone)]
+pub struct Camera {
+    pub position:       cgmath::Vector3<f32>,
+    pub orientation:    cgmath::Quaternion<f32>,
+    pub projection:     Projection,
+    /// width divided by height of the viewport
+    pub aspect:         f32,
+}
+
+impl Camera {
+    /// Return a camera at the origin looking down -Z with a 45 degree field of view.
+    pub fn new() -> Camera {
+        Camera {
+            position:       cgmath::Vector3::new(0.0, 0.0, 0.0),
+            orientation:    cgmath::Quaternion::from_sv(1.0, cgmath::Vector3::new(0.0, 0.0, 0.0)),
+            projection:     Projection::Perspective { fov: 45.0, near: 0.1, far: 100.0 },
+            aspect:         1.0,
+        }
+    }
+
+    /// Take the aspect ratio from the size of the viewport in pixels.
+    pub fn set_viewport(&mut self, width: u32, height: u32) {
+        self.aspect = width as f32 / height.max(1) as f32;
+    }
+
+    /// Turn the camera to look at `target`, keeping `up` pointing up on the screen.
+    pub fn look_at(&mut self, target: &cgmath::Vector3<f32>, up: &cgmath::Vector3<f32>) {
+        let back = self.position.sub_v(target);
+        if back.length2() == 0.0 {
+            return;
+        }
+        let z = back.normalize();
+        let x = up.cross(&z);
+        // looking straight along `up` leaves no way to tell which way is up, keep the old one
+        if x.length2() < 1e-12 {
+            return;
+        }
+        let x = x.normalize();
+        self.orientation = quaternion_from_axes(x, z.cross(&x), z);
+    }
+
+    /// Return the direction the camera looks in.
+    pub fn forward(&self) -> cgmath::Vector3<f32> {
+        self.orientation.rotate_vector(&cgmath::Vector3::new(0.0, 0.0, -1.0))
+    }
+
+    /// Return the direction to the right of the screen.
+    pub fn right(&self) -> cgmath::Vector3<f32> {
+        self.orientation.rotate_vector(&cgmath::Vector3::new(1.0, 0.0, 0.0))
+    }
+
+    /// Return the direction to the top of the screen.
+    pub fn up(&self) -> cgmath::Vector3<f32> {
+        self.orientation.rotate_vector(&cgmath::Vector3::new(0.0, 1.0, 0.0))
+    }
+
+    /// Return the matrix taking world space into the space of the camera.
+    pub fn view_matrix(&self) -> cgmath::Matrix4<f32> {
+        let rotation = self.orientation.to_matrix4().transpose();
+        rotation * cgmath::Matrix4::from_translation(&self.position.mul_s(-1.0))
+    }
+
+    /// Return the matrix taking the space of the camera onto the screen.
+    pub fn projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        match self.projection {
+            Projection::Perspective { fov, near, far } => {
+                cgmath::perspective(cgmath::Deg { s: fov }, self.aspect, near, far)
+            },
+            Projection::Orthographic { height, near, far } => {
+                let h = height * 0.5;
+                let w = h * self.aspect;
+                cgmath::ortho(-w, w, -h, h, near, far)
+            },
+        }
+    }
+
+    /// Return the projection matrix times the view matrix.
+    pub fn view_projection(&self) -> cgmath::Matrix4<f32> {
+        self.projection_matrix() * self.view_matrix()
+    }
+
+    /// Return the world space ray under a point of the screen, in pixels from the top left
+    /// corner of a viewport `width` by `height` pixels.
+    pub fn screen_ray(&self, x: f32, y: f32, width: f32, height: f32) -> Ray {
+        raycast::screen_ray(x, y, width, height, &self.view_projection())
+    }
+}
+
+/// What the user did since the last update, in a form the controllers understand.
+#[derive(Copy, Clone)]
+pub struct CameraInput {
+    /// how far the mouse moved with a button held, in pixels
+    pub drag:           (f32, f32),
+    /// mouse wheel steps, away from the user is positive
+    pub zoom:           f32,
+    /// wanted movement along the right, up and forward axes of the camera, each from -1 to 1
+    pub movement:       cgmath::Vector3<f32>,
+    /// seconds since the last update
+    pub dt:             f32,
+}
+
+/// Collects the window events into `CameraInput`. Dragging is done with the left mouse button,
+/// moving with W, A, S and D, and Q and E go down and up.
+pub struct InputState {
+    mouse:              Option<(i32, i32)>,
+    dragging:           bool,
+    drag:               (f32, f32),
+    zoom:               f32,
+    keys:               Vec<glutin::VirtualKeyCode>,
+}
+
+impl InputState {
+    pub fn new() -> InputState {
+        InputState {
+            mouse:          Option::None,
+            dragging:       false,
+            drag:           (0.0, 0.0),
+            zoom:           0.0,
+            keys:           Vec::new(),
+        }
+    }
+
+    pub fn handle(&mut self, event: &glutin::Event) {
+        match *event {
+            glutin::Event::MouseMoved((x, y)) => {
+                if let Some((ox, oy)) = self.mouse {
+                    if self.dragging {
+                        self.drag = (self.drag.0 + (x - ox) as f32, self.drag.1 + (y - oy) as f32);
+                    }
+                }
+                self.mouse = Option::Some((x, y));
+            },
+            glutin::Event::MouseInput(state, glutin::MouseButton::Left) => {
+                self.dragging = state == glutin::ElementState::Pressed;
+            },
+            glutin::Event::MouseWheel(delta) => {
+                self.zoom += delta as f32;
+            },
+            glutin::Event::KeyboardInput(state, _, Some(key)) => {
+                self.keys.retain(|k| *k != key);
+                if state == glutin::ElementState::Pressed {
+                    self.keys.push(key);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Return the input since the last call, `dt` seconds ago.
+    pub fn take(&mut self, dt: f32) -> CameraInput {
+        use glutin::VirtualKeyCode::{W, A, S, D, Q, E};
+
+        let input = {
+            let keys = &self.keys;
+            let axis = |minus: glutin::VirtualKeyCode, plus: glutin::VirtualKeyCode| -> f32 {
+                let mut a = 0.0;
+                if keys.contains(&minus) { a -= 1.0; }
+                if keys.contains(&plus) { a += 1.0; }
+                a
+            };
+
+            CameraInput {
+                drag:       self.drag,
+                zoom:       self.zoom,
+                movement:   cgmath::Vector3::new(axis(A, D), axis(Q, E), axis(S, W)),
+                dt:         dt,
+            }
+        };
+        self.drag = (0.0, 0.0);
+        self.zoom = 0.0;
+        input
+    }
+}
+
+/// Moves a camera from user input.
+pub trait CameraController {
+    fn update(&mut self, camera: &mut Camera, input: &CameraInput);
+}
+
+/// The highest the pitch of the controllers goes, just short of straight up or down (in
+/// radians).
+const MAX_PITCH: f32 = 1.55;
+
+/// Return the direction for a yaw around world Z and a pitch above the XY plane.
+fn direction(yaw: f32, pitch: f32) -> cgmath::Vector3<f32> {
+    cgmath::Vector3::new(pitch.cos() * yaw.cos(), pitch.cos() * yaw.sin(), pitch.sin())
+}
+
+/// Circles around a point: dragging turns around it and the wheel moves closer or further away.
+pub struct OrbitController {
+    pub target:         cgmath::Vector3<f32>,
+    pub distance:       f32,
+    /// angle around world Z of the camera seen from the target (in radians)
+    pub yaw:            f32,
+    /// angle of the camera above the target (in radians)
+    pub pitch:          f32,
+    /// radians per pixel dragged
+    pub rotate_speed:   f32,
+    /// fraction of the distance per wheel step
+    pub zoom_speed:     f32,
+    pub min_distance:   f32,
+    pub max_distance:   f32,
+}
+
+impl OrbitController {
+    pub fn new(target: cgmath::Vector3<f32>, distance: f32) -> OrbitController {
+        OrbitController {
+            target:         target,
+            distance:       distance,
+            yaw:            -::std::f32::consts::FRAC_PI_2,
+            pitch:          0.4,
+            rotate_speed:   0.01,
+            zoom_speed:     0.1,
+            min_distance:   0.1,
+            max_distance:   1000.0,
+        }
+    }
+}
+
+impl CameraController for OrbitController {
+    fn update(&mut self, camera: &mut Camera, input: &CameraInput) {
+        self.yaw -= input.drag.0 * self.rotate_speed;
+        self.pitch = (self.pitch + input.drag.1 * self.rotate_speed).max(-MAX_PITCH).min(MAX_PITCH);
+        self.distance = (self.distance * (1.0 - input.zoom * self.zoom_speed)).max(self.min_distance).min(self.max_distance);
+
+        camera.position = self.target.add_v(&direction(self.yaw, self.pitch).mul_s(self.distance));
+        camera.look_at(&self.target, &cgmath::Vector3::new(0.0, 0.0, 1.0));
+    }
+}
+
+/// Flies freely: dragging looks around and the keys move along where the camera looks.
+pub struct FlyController {
+    /// angle around world Z of the direction looked in (in radians)
+    pub yaw:            f32,
+    /// angle of the direction looked in above the XY plane (in radians)
+    pub pitch:          f32,
+    /// units per second
+    pub speed:          f32,
+    /// radians per pixel dragged
+    pub look_speed:     f32,
+}
+
+impl FlyController {
+    /// Return a controller looking the way the camera already does.
+    pub fn new(camera: &Camera, speed: f32) -> FlyController {
+        let f = camera.forward();
+        FlyController {
+            yaw:            f.y.atan2(f.x),
+            pitch:          f.z.max(-1.0).min(1.0).asin().max(-MAX_PITCH).min(MAX_PITCH),
+            speed:          speed,
+            look_speed:     0.005,
+        }
+    }
+}
+
+impl CameraController for FlyController {
+    fn update(&mut self, camera: &mut Camera, input: &CameraInput) {
+        self.yaw -= input.drag.0 * self.look_speed;
+        self.pitch = (self.pitch - input.drag.1 * self.look_speed).max(-MAX_PITCH).min(MAX_PITCH);
+
+        let forward = direction(self.yaw, self.pitch);
+        let up = cgmath::Vector3::new(0.0, 0.0, 1.0);
+        let right = forward.cross(&up).normalize();
+        let m = input.movement;
+        let step = right.mul_s(m.x).add_v(&up.mul_s(m.y)).add_v(&forward.mul_s(m.z));
+
+        camera.position = camera.position.add_v(&step.mul_s(self.speed * input.dt));
+        let target = camera.position.add_v(&forward);
+        camera.look_at(&target, &up);
+    }
+}
+
+/// Trails behind something that moves, like the player, and keeps looking at it. The camera
+/// eases towards where it should be instead of sticking to it, which hides small jumps.
+pub struct FollowController {
+    /// the world matrix of what is followed, set it every frame
+    pub target:         cgmath::Matrix4<f32>,
+    /// where the camera should be in the space of the target
+    pub offset:         cgmath::Vector3<f32>,
+    /// the point looked at in the space of the target
+    pub look_offset:    cgmath::Vector3<f32>,
+    /// how quickly the camera catches up, higher is faster and zero never moves
+    pub stiffness:      f32,
+}
+
+impl FollowController {
+    pub fn new(offset: cgmath::Vector3<f32>) -> FollowController {
+        FollowController {
+            target:         cgmath::Matrix4::identity(),
+            offset:         offset,
+            look_offset:    cgmath::Vector3::new(0.0, 0.0, 0.0),
+            stiffness:      5.0,
+        }
+    }
+
+    /// Put the camera where it should be right away, for when the followed thing appears or
+    /// jumps.
+    pub fn snap(&self, camera: &mut Camera) {
+        camera.position = self.place(&self.offset);
+        camera.look_at(&self.place(&self.look_offset), &cgmath::Vector3::new(0.0, 0.0, 1.0));
+    }
+
+    fn place(&self, v: &cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        self.target.mul_v(&cgmath::Vector4::new(v.x, v.y, v.z, 1.0)).truncate()
+    }
+}
+
+impl CameraController for FollowController {
+    fn update(&mut self, camera: &mut Camera, input: &CameraInput) {
+        // the same ease whatever the frame rate
+        let t = 1.0 - (-self.stiffness * input.dt).exp();
+        let wanted = self.place(&self.offset);
+
+        camera.position = camera.position.add_v(&wanted.sub_v(&camera.position).mul_s(t));
+        camera.look_at(&self.place(&self.look_offset), &cgmath::Vector3::new(0.0, 0.0, 1.0));
+    }
+}
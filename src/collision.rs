@@ -0,0 +1,489 @@
+//! Simplified shapes for collision detection and physics, made from the vertices of scene
+//! objects: convex hulls (found with quickhull), oriented bounding boxes and sets of convex hulls
+//! for objects that are not convex themselves.
+//!
+//! Objects in the `Collision` group are meant to be collision geometry rather than something to
+//! draw, like in most engines. The shape is picked by the prefix of the name, with the same
+//! prefixes the Unreal engine uses so that existing Blender habits carry over:
+//!
+//! * `UBX_` an oriented box
+//! * `USP_` a sphere
+//! * `UCX_` a convex hull
+//!
+//! Anything else becomes a convex hull when it is close to convex, and otherwise is split up into
+//! a number of convex hulls.
+//!
+//! The convex decomposition is a simple one. It looks for the vertex deepest inside the hull of a
+//! piece and cuts the piece in two there, across its longest axis, until the pieces are convex
+//! enough. It works well for the kind of shapes collision geometry is modelled as (a few boxy
+//! parts put together) but is no match for the voxel based methods on organic shapes.
+
+use std::num::Float;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use cgmath;
+use cgmath::{Vector, EuclideanVector};
+
+use mesh::Mesh;
+use bounds::{Aabb, BoundingSphere};
+use simplescene::{SimpleSceneFile, SimpleSceneObject};
+
+/// The group that marks objects as collision geometry.
+pub const COLLISION_GROUP: &'static str = "Collision";
+
+/// How deep, as a fraction of the size of the hull, a vertex can be inside the hull of a piece
+/// before the piece no longer counts as convex.
+pub const DEFAULT_CONCAVITY: f32 = 0.05;
+
+/// The most pieces an object is split into by default.
+pub const DEFAULT_MAX_PARTS: usize = 16;
+
+/// A box that is free to rotate.
+#[derive(Copy, Clone)]
+pub struct Obb {
+    pub center:         cgmath::Vector3<f32>,
+    /// the unit axes of the box, which form a right handed set
+    pub axes:           [cgmath::Vector3<f32>;3],
+    /// half of the size of the box along each of the axes
+    pub extent:         cgmath::Vector3<f32>,
+}
+
+impl Obb {
+    /// Return the eight corners of the box.
+    pub fn corners(&self) -> Vec<cgmath::Vector3<f32>> {
+        let mut corners = Vec::with_capacity(8);
+        for i in 0..8 {
+            let sx = if i & 1 == 0 { -self.extent.x } else { self.extent.x };
+            let sy = if i & 2 == 0 { -self.extent.y } else { self.extent.y };
+            let sz = if i & 4 == 0 { -self.extent.z } else { self.extent.z };
+            corners.push(self.center
+                .add_v(&self.axes[0].mul_s(sx))
+                .add_v(&self.axes[1].mul_s(sy))
+                .add_v(&self.axes[2].mul_s(sz)));
+        }
+        corners
+    }
+}
+
+pub enum CollisionShape {
+    Box(Obb),
+    Sphere(BoundingSphere),
+    /// a convex hull made of triangles
+    Hull(Mesh),
+    /// convex hulls that together cover the object
+    Compound(Vec<Mesh>),
+}
+
+/// The collision shape of an object in object space, with the object transform to place it.
+pub struct CollisionObject {
+    pub name:           String,
+    pub shape:          CollisionShape,
+    pub matrix:         cgmath::Matrix4<f32>,
+}
+
+struct Face {
+    v:              [usize;3],
+    normal:         cgmath::Vector3<f32>,
+    offset:         f32,
+    /// the points that are above the face and not yet in the hull
+    outside:        Vec<usize>,
+    alive:          bool,
+}
+
+impl Face {
+    fn new(points: &[cgmath::Vector3<f32>], a: usize, b: usize, c: usize) -> Face {
+        let n = points[b].sub_v(&points[a]).cross(&points[c].sub_v(&points[a]));
+        let n = if n.length() > 0.0 { n.normalize() } else { n };
+        Face { v: [a, b, c], normal: n, offset: n.dot(&points[a]), outside: Vec::new(), alive: true }
+    }
+
+    fn distance(&self, p: &cgmath::Vector3<f32>) -> f32 {
+        self.normal.dot(p) - self.offset
+    }
+}
+
+/// Return the convex hull of a set of points as a triangle mesh with its faces pointing out, or
+/// `None` if the points are all in one plane (or there are fewer than four of them).
+pub fn convex_hull(points: &[cgmath::Vector3<f32>]) -> Option<Mesh> {
+    if points.len() < 4 {
+        return Option::None;
+    }
+
+    let size = Aabb::from_points(points).extent().length();
+    let epsilon = size * 0.00001;
+
+    // Start with a tetrahedron made of points far apart from each other.
+    let a = farthest(points, |p| p.x);
+    let b = farthest(points, |p| p.sub_v(&points[a]).length());
+    let ab = points[b].sub_v(&points[a]);
+    let c = farthest(points, |p| p.sub_v(&points[a]).cross(&ab).length());
+    let plane = Face::new(points, a, b, c);
+    let d = farthest(points, |p| plane.distance(p).abs());
+
+    if ab.length() <= epsilon || plane.normal.length() == 0.0 || plane.distance(&points[d]).abs() <= epsilon {
+        return Option::None;
+    }
+
+    let mut faces: Vec<Face> = Vec::new();
+    for v in [[a, b, c], [a, c, d], [a, d, b], [b, d, c]].iter() {
+        let face = Face::new(points, v[0], v[1], v[2]);
+        // the corner not on the face has to be below it
+        let other = [a, b, c, d].iter().map(|i| *i).find(|i| !v.contains(i)).unwrap();
+        if face.distance(&points[other]) > 0.0 {
+            faces.push(Face::new(points, v[0], v[2], v[1]));
+        } else {
+            faces.push(face);
+        }
+    }
+
+    for i in 0..points.len() {
+        assign(&mut faces, 0, points, i, epsilon);
+    }
+
+    loop {
+        let current = match faces.iter().position(|f| f.alive && f.outside.len() > 0) {
+            Some(f) => f,
+            None => break,
+        };
+
+        let eye = {
+            let face = &faces[current];
+            let mut best = face.outside[0];
+            for i in face.outside.iter() {
+                if face.distance(&points[*i]) > face.distance(&points[best]) {
+                    best = *i;
+                }
+            }
+            best
+        };
+
+        // The faces the new point can see go, and the edges around them are joined to it.
+        let visible: Vec<usize> = (0..faces.len()).filter(|f| {
+            faces[*f].alive && faces[*f].distance(&points[eye]) > epsilon
+        }).collect();
+
+        let mut edges: Vec<(usize, usize)> = Vec::new();
+        for f in visible.iter() {
+            let v = faces[*f].v;
+            for i in 0..3 {
+                edges.push((v[i], v[(i + 1) % 3]));
+            }
+        }
+        let horizon: Vec<(usize, usize)> = edges.iter().filter(|e| {
+            !edges.contains(&(e.1, e.0))
+        }).map(|e| *e).collect();
+
+        let mut orphans: Vec<usize> = Vec::new();
+        for f in visible.iter() {
+            faces[*f].alive = false;
+            orphans.extend(faces[*f].outside.drain());
+        }
+
+        let first = faces.len();
+        for e in horizon.iter() {
+            faces.push(Face::new(points, e.0, e.1, eye));
+        }
+
+        for i in orphans.into_iter() {
+            if i != eye {
+                assign(&mut faces, first, points, i, epsilon);
+            }
+        }
+    }
+
+    let mut hull = Mesh::new();
+    hull.positions = points.to_vec();
+    hull.triangles = faces.iter().filter(|f| f.alive).map(|f| {
+        [f.v[0] as u16, f.v[1] as u16, f.v[2] as u16]
+    }).collect();
+    hull.compact();
+
+    Option::Some(hull)
+}
+
+/// Return the index of the point with the largest `measure`.
+fn farthest<F: Fn(&cgmath::Vector3<f32>) -> f32>(points: &[cgmath::Vector3<f32>], measure: F) -> usize {
+    let mut best = 0;
+    for i in 1..points.len() {
+        if measure(&points[i]) > measure(&points[best]) {
+            best = i;
+        }
+    }
+    best
+}
+
+/// Hand a point to the first face from `first` on that it is above.
+fn assign(faces: &mut Vec<Face>, first: usize, points: &[cgmath::Vector3<f32>], i: usize, epsilon: f32) {
+    for f in first..faces.len() {
+        if faces[f].alive && faces[f].distance(&points[i]) > epsilon {
+            faces[f].outside.push(i);
+            return;
+        }
+    }
+}
+
+/// Return the box around the points lined up with their principal axes. This is not always the
+/// smallest box but is close for most shapes.
+pub fn fit_obb(points: &[cgmath::Vector3<f32>]) -> Obb {
+    // Using the hull gets rid of the effect that a dense patch of inner vertices would have.
+    let hull = convex_hull(points);
+    let points = match hull {
+        Some(ref hull) => hull.positions.as_slice(),
+        None => points,
+    };
+
+    let n = points.len() as f32;
+    let mean = points.iter().fold(cgmath::Vector3::new(0.0, 0.0, 0.0), |a, p| a.add_v(p)).div_s(n.max(1.0));
+
+    let mut covariance = [[0.0f64;3];3];
+    for p in points.iter() {
+        let d = p.sub_v(&mean);
+        let d = [d.x as f64, d.y as f64, d.z as f64];
+        for i in 0..3 {
+            for j in 0..3 {
+                covariance[i][j] += d[i] * d[j];
+            }
+        }
+    }
+
+    let axes = eigenvectors(covariance);
+    let axes = [axes[0], axes[1], axes[0].cross(&axes[1])];
+
+    let big: f32 = Float::max_value();
+    let mut min = [big, big, big];
+    let mut max = [-big, -big, -big];
+    for p in points.iter() {
+        for i in 0..3 {
+            let d: f32 = axes[i].dot(p);
+            min[i] = min[i].min(d);
+            max[i] = max[i].max(d);
+        }
+    }
+
+    let mut center = cgmath::Vector3::new(0.0, 0.0, 0.0);
+    for i in 0..3 {
+        center = center.add_v(&axes[i].mul_s((min[i] + max[i]) * 0.5));
+    }
+
+    Obb {
+        center:     center,
+        axes:       axes,
+        extent:     cgmath::Vector3::new((max[0] - min[0]) * 0.5, (max[1] - min[1]) * 0.5, (max[2] - min[2]) * 0.5),
+    }
+}
+
+/// Return the unit eigenvectors of a symmetric matrix, found with Jacobi rotations.
+fn eigenvectors(m: [[f64;3];3]) -> [cgmath::Vector3<f32>;3] {
+    let mut a = m;
+    let mut v = [[1.0f64, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    for _ in 0..32 {
+        for &(p, q) in [(0, 1), (0, 2), (1, 2)].iter() {
+            if a[p][q].abs() < 1e-12 {
+                continue;
+            }
+
+            let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+            let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+            let c = 1.0 / (t * t + 1.0).sqrt();
+            let s = t * c;
+
+            for k in 0..3 {
+                let (kp, kq) = (a[k][p], a[k][q]);
+                a[k][p] = c * kp - s * kq;
+                a[k][q] = s * kp + c * kq;
+            }
+            for k in 0..3 {
+                let (pk, qk) = (a[p][k], a[q][k]);
+                a[p][k] = c * pk - s * qk;
+                a[q][k] = s * pk + c * qk;
+            }
+            for k in 0..3 {
+                let (kp, kq) = (v[k][p], v[k][q]);
+                v[k][p] = c * kp - s * kq;
+                v[k][q] = s * kp + c * kq;
+            }
+        }
+    }
+
+    let column = |i: usize| {
+        let c = cgmath::Vector3::new(v[0][i] as f32, v[1][i] as f32, v[2][i] as f32);
+        if c.length() > 0.0 { c.normalize() } else { c }
+    };
+    [column(0), column(1), column(2)]
+}
+
+/// A part of the object being decomposed: its vertices and the parts of its edges.
+struct Piece {
+    points:         Vec<cgmath::Vector3<f32>>,
+    segments:       Vec<(cgmath::Vector3<f32>, cgmath::Vector3<f32>)>,
+}
+
+/// Return how deep the deepest point is inside the hull, and that point.
+fn concavity(hull: &Mesh, points: &[cgmath::Vector3<f32>]) -> (f32, cgmath::Vector3<f32>) {
+    let mut deepest = (0.0, points[0]);
+
+    for p in points.iter() {
+        let mut depth: f32 = Float::max_value();
+        for t in hull.triangles.iter() {
+            let n = hull.face_normal(t.as_slice());
+            depth = depth.min(n.dot(&hull.positions[t[0] as usize].sub_v(p)));
+        }
+        if depth > deepest.0 {
+            deepest = (depth, *p);
+        }
+    }
+
+    deepest
+}
+
+/// Split a mesh into convex hulls, cutting it up until no vertex is deeper than `threshold`
+/// (as a fraction of the size of the whole hull) inside the hull of its piece, or there are
+/// `max_parts` pieces.
+pub fn convex_decomposition(mesh: &Mesh, max_parts: usize, threshold: f32) -> Vec<Mesh> {
+    let edges = mesh.edges(Option::None);
+    let size = Aabb::from_points(mesh.positions.as_slice()).extent().length() * 2.0;
+
+    let mut pieces: Vec<(Piece, Mesh, f32, cgmath::Vector3<f32>)> = Vec::new();
+    let mut done: Vec<Mesh> = Vec::new();
+
+    let whole = Piece {
+        points:     mesh.positions.clone(),
+        segments:   edges.iter().map(|e| (mesh.positions[e[0] as usize], mesh.positions[e[1] as usize])).collect(),
+    };
+
+    let mut work = vec![whole];
+
+    loop {
+        for piece in work.drain() {
+            if let Some(hull) = convex_hull(piece.points.as_slice()) {
+                let (depth, point) = concavity(&hull, piece.points.as_slice());
+                if depth / size > threshold {
+                    pieces.push((piece, hull, depth, point));
+                } else {
+                    done.push(hull);
+                }
+            }
+        }
+
+        if pieces.len() == 0 || done.len() + pieces.len() >= max_parts {
+            break;
+        }
+
+        // cut the piece that is furthest from being convex
+        let worst = (0..pieces.len()).fold(0, |w, i| if pieces[i].2 > pieces[w].2 { i } else { w });
+        let (piece, _, _, point) = pieces.remove(worst);
+
+        let obb = fit_obb(piece.points.as_slice());
+        let e = obb.extent;
+        let axis = if e.x >= e.y && e.x >= e.z { obb.axes[0] } else if e.y >= e.z { obb.axes[1] } else { obb.axes[2] };
+        let offset = axis.dot(&point);
+
+        let (below, above) = cut(&piece, &axis, offset);
+        if below.points.len() < 4 || above.points.len() < 4 {
+            // can not be cut any further
+            done.push(convex_hull(piece.points.as_slice()).unwrap());
+            continue;
+        }
+        work.push(below);
+        work.push(above);
+    }
+
+    for piece in pieces.into_iter() {
+        done.push(piece.1);
+    }
+
+    done
+}
+
+/// Cut a piece in two with the plane `normal . p = offset`, the points where the edges cross
+/// the plane go into both halves.
+fn cut(piece: &Piece, normal: &cgmath::Vector3<f32>, offset: f32) -> (Piece, Piece) {
+    let mut below = Piece { points: Vec::new(), segments: Vec::new() };
+    let mut above = Piece { points: Vec::new(), segments: Vec::new() };
+
+    for p in piece.points.iter() {
+        if normal.dot(p) < offset { below.points.push(*p); } else { above.points.push(*p); }
+    }
+
+    for s in piece.segments.iter() {
+        let da = normal.dot(&s.0) - offset;
+        let db = normal.dot(&s.1) - offset;
+
+        if da < 0.0 && db < 0.0 {
+            below.segments.push(*s);
+        } else if da >= 0.0 && db >= 0.0 {
+            above.segments.push(*s);
+        } else {
+            let cross = s.0.add_v(&s.1.sub_v(&s.0).mul_s(da / (da - db)));
+            below.points.push(cross);
+            above.points.push(cross);
+            if da < 0.0 {
+                below.segments.push((s.0, cross));
+                above.segments.push((cross, s.1));
+            } else {
+                above.segments.push((s.0, cross));
+                below.segments.push((cross, s.1));
+            }
+        }
+    }
+
+    (below, above)
+}
+
+/// Return the collision shape for an object, picked by the prefix of its name as described at
+/// the top of this module.
+pub fn collision_shape(object: &SimpleSceneObject) -> Option<CollisionShape> {
+    let points = object.vertices.as_slice();
+    if points.len() == 0 {
+        return Option::None;
+    }
+
+    let name = object.name.as_slice();
+    if name.starts_with("UBX_") {
+        return Option::Some(CollisionShape::Box(fit_obb(points)));
+    }
+    if name.starts_with("USP_") {
+        return Option::Some(CollisionShape::Sphere(BoundingSphere::from_points(points)));
+    }
+
+    let hull = match convex_hull(points) {
+        Some(hull) => hull,
+        // flat objects get a box with no thickness
+        None => return Option::Some(CollisionShape::Box(fit_obb(points))),
+    };
+
+    if name.starts_with("UCX_") {
+        return Option::Some(CollisionShape::Hull(hull));
+    }
+
+    let size = Aabb::from_points(points).extent().length() * 2.0;
+    if concavity(&hull, points).0 / size <= DEFAULT_CONCAVITY {
+        return Option::Some(CollisionShape::Hull(hull));
+    }
+
+    let mesh = Mesh::from_simplescene(object);
+    Option::Some(CollisionShape::Compound(convex_decomposition(&mesh, DEFAULT_MAX_PARTS, DEFAULT_CONCAVITY)))
+}
+
+/// Return the collision shapes of all the objects in a group, normally `COLLISION_GROUP`.
+pub fn collision_objects(scene: &SimpleSceneFile, group: &str) -> Vec<CollisionObject> {
+    let mut found: Vec<CollisionObject> = Vec::new();
+
+    for object in scene.objects.iter() {
+        let object = object.lock().unwrap();
+        if !object.groups.iter().any(|g| g.as_slice() == group) {
+            continue;
+        }
+        if let Some(shape) = collision_shape(&*object) {
+            found.push(CollisionObject {
+                name:       object.name.clone(),
+                shape:      shape,
+                matrix:     object.world_matrix(),
+            });
+        }
+    }
+
+    found
+}
@@ -0,0 +1,481 @@
+//! `kordom-convert` converts scenes between the formats the engine understands so that asset
+//! pipelines can run without opening the viewer window (or any window at all).
+//!
+//!     kordom-convert [options] <input> <output>
+//!
+//! The formats are picked by file extension:
+//!
+//! * `.txt` simple scene text format
+//! * `.ssb` simple scene binary format
+//! * `.obj` Wavefront obj (with a `.mtl` next to it)
+//! * `.gltf` and `.glb` glTF 2.0
+//! * `.stl` STL, binary unless `--stl-ascii` is given
+//! * `.ply` PLY
+//! * `.blend` Blender files (input only)
+//!
+//! STL and PLY only hold a single mesh, so when writing them every object with geometry is
+//! merged into one with the object transforms baked in.
+//!
+//! Options:
+//!
+//! * `--axis z-to-y` or `--axis y-to-z` converts between Blender's Z-up and glTF's Y-up
+//! * `--triangulate` splits all quads into triangles
+//! * `--cleanup <epsilon>` welds vertices within the distance, drops degenerate faces and
+//!   removes unused vertices
+//! * `--normals <degrees>` generates normals for the objects without them, smoothing across edges
+//!   where the faces meet at less than the given angle
+//! * `--name <name>` only keeps objects with that name, can be given more than once
+//! * `--group <group>` only keeps objects in that group, can be given more than once
+//! * `--validate` checks the scene before writing and the output after writing, and fails
+//!   without writing anything if the scene has problems
+//! * `--fix-winding` turns faces around so that every mesh is wound consistently, with closed
+//!   meshes facing out
+//! * `--topology` prints the topology of every mesh and its problems, like holes and
+//!   non-manifold edges
+//! * `--stl-ascii` writes ASCII STL instead of binary
+//! * `--weld <epsilon>` distance within which STL corners are welded on import
+
+#![allow(unstable)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_imports)]
+
+extern crate cgmath;
+extern crate "rustc-serialize" as rustc_serialize;
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::old_io::stdio;
+
+use cgmath::FixedArray;
+
+use simplescene::{SimpleSceneFile, SimpleSceneObject};
+use mesh::Mesh;
+
+pub mod simplescene;
+pub mod mesh;
+pub mod mikktspace;
+pub mod bounds;
+pub mod raycast;
+pub mod topology;
+pub mod objwriter;
+pub mod objreader;
+pub mod gltf;
+pub mod gltfwriter;
+pub mod blend;
+pub mod stl;
+pub mod ply;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Format {
+    Text,
+    Binary,
+    Obj,
+    Gltf,
+    Stl,
+    Ply,
+    Blend,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Axis {
+    Keep,
+    ZToY,
+    YToZ,
+}
+
+struct Options {
+    input:          String,
+    output:         String,
+    input_format:   Format,
+    output_format:  Format,
+    axis:           Axis,
+    triangulate:    bool,
+    normals:        Option<f32>,
+    cleanup:        Option<f32>,
+    names:          Vec<String>,
+    groups:         Vec<String>,
+    validate:       bool,
+    fix_winding:    bool,
+    topology:       bool,
+    stl_ascii:      bool,
+    weld:           f32,
+}
+
+fn usage() {
+    let mut err = stdio::stderr();
+    writeln!(&mut err, "usage: kordom-convert [--axis z-to-y|y-to-z] [--triangulate] [--cleanup <epsilon>]").unwrap();
+    writeln!(&mut err, "                      [--normals <degrees>] [--name <name>]... [--group <group>]... [--validate]").unwrap();
+    writeln!(&mut err, "                      [--fix-winding] [--topology] [--stl-ascii] [--weld <epsilon>]").unwrap();
+    writeln!(&mut err, "                      <input> <output>").unwrap();
+}
+
+fn parse_args() -> Result<Options, String> {
+    let args = std::os::args();
+    let mut options = Options {
+        input:          String::new(),
+        output:         String::new(),
+        input_format:   Format::Text,
+        output_format:  Format::Text,
+        axis:           Axis::Keep,
+        triangulate:    false,
+        normals:        Option::None,
+        cleanup:        Option::None,
+        names:          Vec::new(),
+        groups:         Vec::new(),
+        validate:       false,
+        fix_winding:    false,
+        topology:       false,
+        stl_ascii:      false,
+        weld:           0.0,
+    };
+    let mut files: Vec<String> = Vec::new();
+    let mut i = 1;
+
+    while i < args.len() {
+        let value = |i: usize| -> Result<String, String> {
+            if i + 1 >= args.len() {
+                return Err(format!("{} needs a value", args[i]));
+            }
+            Ok(args[i + 1].clone())
+        };
+        let number = |i: usize| -> Result<f32, String> {
+            let v = try!(value(i));
+            v.parse::<f32>().map_err(|_| format!("{} needs a number, not {}", args[i], v))
+        };
+
+        match args[i].as_slice() {
+            "--axis" => {
+                options.axis = match try!(value(i)).as_slice() {
+                    "z-to-y" => Axis::ZToY,
+                    "y-to-z" => Axis::YToZ,
+                    other => return Err(format!("unknown axis conversion {}", other)),
+                };
+                i += 1;
+            },
+            "--triangulate" => options.triangulate = true,
+            "--cleanup" => { options.cleanup = Option::Some(try!(number(i))); i += 1; },
+            "--normals" => { options.normals = Option::Some(try!(number(i))); i += 1; },
+            "--name" => { options.names.push(try!(value(i))); i += 1; },
+            "--group" => { options.groups.push(try!(value(i))); i += 1; },
+            "--validate" => options.validate = true,
+            "--fix-winding" => options.fix_winding = true,
+            "--topology" => options.topology = true,
+            "--stl-ascii" => options.stl_ascii = true,
+            "--weld" => { options.weld = try!(number(i)); i += 1; },
+            other if other.starts_with("--") => return Err(format!("unknown option {}", other)),
+            other => files.push(String::from_str(other)),
+        }
+        i += 1;
+    }
+
+    if files.len() != 2 {
+        return Err(String::from_str("expected an input and an output file"));
+    }
+
+    options.output = files.pop().unwrap();
+    options.input = files.pop().unwrap();
+    options.input_format = try!(format_of(options.input.as_slice()));
+    options.output_format = try!(format_of(options.output.as_slice()));
+
+    if options.output_format == Format::Blend {
+        return Err(String::from_str("blend files can only be read"));
+    }
+
+    Ok(options)
+}
+
+fn format_of(path: &str) -> Result<Format, String> {
+    match Path::new(path).extension_str().unwrap_or("") {
+        "txt" => Ok(Format::Text),
+        "ssb" => Ok(Format::Binary),
+        "obj" => Ok(Format::Obj),
+        "gltf" | "glb" => Ok(Format::Gltf),
+        "stl" => Ok(Format::Stl),
+        "ply" => Ok(Format::Ply),
+        "blend" => Ok(Format::Blend),
+        other => Err(format!("unknown file type .{} for {}", other, path)),
+    }
+}
+
+/// Wrap a single object into a scene.
+fn single(object: SimpleSceneObject, path: &str) -> SimpleSceneFile {
+    SimpleSceneFile {
+        path:           Path::new(path),
+        objects:        vec![Arc::new(Mutex::new(object))],
+        materials:      Vec::new(),
+        animations:     Vec::new(),
+    }
+}
+
+fn read(path: &str, format: Format, options: &Options) -> SimpleSceneFile {
+    match format {
+        Format::Text => SimpleSceneFile::from_file(path),
+        Format::Binary => SimpleSceneFile::from_binary(path),
+        Format::Obj => objreader::read_scene(path),
+        Format::Gltf => gltf::load(path),
+        Format::Stl => single(stl::read(path, options.weld), path),
+        Format::Ply => single(ply::read(path), path),
+        Format::Blend => blend::load(path),
+    }
+}
+
+fn write(scene: &SimpleSceneFile, path: &str, format: Format, options: &Options) {
+    match format {
+        Format::Text => scene.write_file(path),
+        Format::Binary => scene.write_binary(path),
+        Format::Obj => objwriter::write_scene(scene, path, &std::default::Default::default()),
+        Format::Gltf => gltfwriter::write_scene(scene, path),
+        Format::Stl => stl::write(&merge(scene), path, !options.stl_ascii),
+        Format::Ply => ply::write(&merge(scene), path, true),
+        // turned away by `parse_args`
+        Format::Blend => unreachable!(),
+    }
+}
+
+/// Return one object holding the geometry of every object in the scene, in world space.
+fn merge(scene: &SimpleSceneFile) -> SimpleSceneObject {
+    use cgmath::{Matrix, EuclideanVector};
+
+    let mut merged = SimpleSceneObject::new();
+    merged.typ = String::from_str("MESH");
+    merged.name = String::from_str(scene.path.filestem_str().unwrap_or("merged"));
+
+    for o in scene.objects.iter() {
+        let o = o.lock().unwrap();
+        let base = merged.vertices.len();
+
+        if o.vertices.len() == 0 {
+            continue;
+        }
+
+        if base + o.vertices.len() > 65536 {
+            panic!("too many vertices in the scene to merge into one mesh with 16-bit indices");
+        }
+
+        let m = o.world_matrix();
+        let n = m.invert().map(|m| m.transpose()).unwrap_or(m);
+        let count = o.vertices.len();
+
+        for v in o.vertices.iter() {
+            merged.vertices.push(m.mul_v(&cgmath::Vector4::new(v.x, v.y, v.z, 1.0)).truncate());
+        }
+
+        // Per-vertex data can only be kept when every object has it, otherwise
+        // it would no longer line up with the vertices.
+        let first = base == 0;
+        if o.normals.len() == count && (first || merged.normals.len() == base) {
+            for v in o.normals.iter() {
+                merged.normals.push(n.mul_v(&cgmath::Vector4::new(v.x, v.y, v.z, 0.0)).truncate().normalize());
+            }
+        } else {
+            merged.normals.clear();
+        }
+        if o.colors.len() == count && (first || merged.colors.len() == base) {
+            merged.colors.push_all(o.colors.as_slice());
+        } else {
+            merged.colors.clear();
+        }
+        if o.uvs.len() == count && (first || merged.uvs.len() == base) {
+            merged.uvs.push_all(o.uvs.as_slice());
+        } else {
+            merged.uvs.clear();
+        }
+
+        let b = base as u16;
+        for t in o.triangles.iter() {
+            merged.triangles.push([t[0] + b, t[1] + b, t[2] + b]);
+        }
+        for q in o.quads.iter() {
+            merged.quads.push([q[0] + b, q[1] + b, q[2] + b, q[3] + b]);
+        }
+    }
+
+    merged
+}
+
+fn same(a: &Arc<Mutex<SimpleSceneObject>>, b: &Arc<Mutex<SimpleSceneObject>>) -> bool {
+    &**a as *const Mutex<SimpleSceneObject> == &**b as *const Mutex<SimpleSceneObject>
+}
+
+/// Drop the objects that do not match the name and group filters. An object that is kept while
+/// its parent is dropped has the parent transform baked into its own.
+fn filter(scene: &mut SimpleSceneFile, options: &Options) {
+    if options.names.len() == 0 && options.groups.len() == 0 {
+        return;
+    }
+
+    let kept: Vec<Arc<Mutex<SimpleSceneObject>>> = scene.objects.iter().filter(|o| {
+        let o = o.lock().unwrap();
+        options.names.contains(&o.name) || o.groups.iter().any(|g| options.groups.contains(g))
+    }).map(|o| o.clone()).collect();
+
+    for o in kept.iter() {
+        let parent = o.lock().unwrap().parent.clone();
+        if let Some(parent) = parent {
+            if !kept.iter().any(|k| same(k, &parent)) {
+                let mut o = o.lock().unwrap();
+                let m = o.world_matrix();
+                o.set_matrix(&m);
+                o.parent = Option::None;
+            }
+        }
+
+        let mut o = o.lock().unwrap();
+        o.child.retain(|c| kept.iter().any(|k| same(k, c)));
+    }
+
+    scene.objects = kept;
+}
+
+/// Rotate the scene between Z-up and Y-up by rotating the objects at the top of the hierarchy,
+/// along with the location and rotation keys that animate them.
+fn convert_axis(scene: &mut SimpleSceneFile, axis: Axis) {
+    use cgmath::{Rotation3, ToRad};
+    use simplescene::SimpleSceneProperty;
+
+    let angle = match axis {
+        Axis::Keep => return,
+        Axis::ZToY => -90.0,
+        Axis::YToZ => 90.0,
+    };
+    let r: cgmath::Quaternion<f32> = Rotation3::from_axis_angle(
+        &cgmath::Vector3::new(1.0, 0.0, 0.0), (cgmath::Deg { s: angle }).to_rad()
+    );
+
+    let mut roots: Vec<String> = Vec::new();
+    for o in scene.objects.iter() {
+        let mut o = o.lock().unwrap();
+        if o.parent.is_none() {
+            o.location = r.mul_v(&o.location);
+            o.rotation = r.mul_q(&o.rotation);
+            roots.push(o.name.clone());
+        }
+    }
+
+    for animation in scene.animations.iter_mut() {
+        for channel in animation.channels.iter_mut() {
+            if !roots.contains(&channel.target) {
+                continue;
+            }
+            for v in channel.values.iter_mut() {
+                match channel.property {
+                    SimpleSceneProperty::Location => {
+                        let l = r.mul_v(&cgmath::Vector3::new(v[0], v[1], v[2]));
+                        *v = [l.x, l.y, l.z, v[3]];
+                    },
+                    SimpleSceneProperty::Rotation => {
+                        let q = r.mul_q(&cgmath::Quaternion::new(v[3], v[0], v[1], v[2]));
+                        *v = [q.v.x, q.v.y, q.v.z, q.s];
+                    },
+                    // scaling happens before the rotation, in the object's own space
+                    SimpleSceneProperty::Scale => {},
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut err = stdio::stderr();
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            writeln!(&mut err, "{}", e).unwrap();
+            usage();
+            std::os::set_exit_status(2);
+            return;
+        },
+    };
+
+    let mut scene = read(options.input.as_slice(), options.input_format, &options);
+
+    filter(&mut scene, &options);
+    convert_axis(&mut scene, options.axis);
+
+    if options.triangulate {
+        for o in scene.objects.iter() {
+            o.lock().unwrap().triangulate();
+        }
+    }
+
+    if let Some(epsilon) = options.cleanup {
+        for o in scene.objects.iter() {
+            o.lock().unwrap().cleanup(epsilon);
+        }
+    }
+
+    if let Some(degrees) = options.normals {
+        use cgmath::ToRad;
+
+        for o in scene.objects.iter() {
+            let mut o = o.lock().unwrap();
+            if o.vertices.len() > 0 && o.normals.len() != o.vertices.len() {
+                let mut mesh = Mesh::from_simplescene(&*o);
+                mesh.auto_smooth((cgmath::Deg { s: degrees }).to_rad());
+                mesh.to_simplescene(&mut *o);
+            }
+        }
+    }
+
+    if options.fix_winding {
+        for o in scene.objects.iter() {
+            let mut o = o.lock().unwrap();
+            let turned = topology::fix_winding(&mut *o);
+            if turned > 0 {
+                writeln!(&mut err, "{}: turned {} faces around", o.name, turned).unwrap();
+            }
+        }
+    }
+
+    if options.topology {
+        for o in scene.objects.iter() {
+            let o = o.lock().unwrap();
+            if o.triangles.len() + o.quads.len() == 0 {
+                continue;
+            }
+            let report = topology::analyze(&*o);
+            writeln!(&mut err, "{}: {} vertices, {} faces, {} edges, {} pieces", o.name,
+                     report.vertices, report.faces, report.edges, report.components).unwrap();
+            for problem in report.problems(o.name.as_slice()).iter() {
+                writeln!(&mut err, "{}", problem).unwrap();
+            }
+        }
+    }
+
+    if options.validate {
+        let problems = scene.validate();
+        if problems.len() > 0 {
+            for problem in problems.iter() {
+                writeln!(&mut err, "{}", problem).unwrap();
+            }
+            writeln!(&mut err, "{} problems found, nothing was written", problems.len()).unwrap();
+            std::os::set_exit_status(1);
+            return;
+        }
+    }
+
+    write(&scene, options.output.as_slice(), options.output_format, &options);
+
+    if options.validate {
+        let output = options.output.as_slice();
+        let result = match options.output_format {
+            Format::Gltf => gltfwriter::validate(&scene, output),
+            Format::Text | Format::Binary | Format::Obj => {
+                let loaded = read(output, options.output_format, &options);
+                let problems = loaded.validate();
+                if problems.len() > 0 {
+                    Err(problems.connect("\n"))
+                } else {
+                    Ok(())
+                }
+            },
+            _ => Ok(()),
+        };
+
+        if let Err(problem) = result {
+            writeln!(&mut err, "the written file did not load back correctly: {}", problem).unwrap();
+            std::os::set_exit_status(1);
+        }
+    }
+}
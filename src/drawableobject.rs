@@ -8,27 +8,165 @@
 //! the the perspective transformation. (potentially outdated information)_
 
 use glium::{Display, Frame, VertexBuffer, Surface, DrawParameters};
-use glium::index_buffer::{IndexBuffer, TrianglesList};
+use glium::vertex_buffer::VertexBufferAny;
+use glium::index_buffer::{IndexBuffer, TrianglesList, LinesList};
 use glium::program::Program;
 
 use std::sync::Arc;
+use std::default::Default;
 
 use super::Vertex;
+use super::VertexExt;
 use super::Uniform;
+use super::WireUniform;
+use super::LitUniform;
 
 use glium;
+use cgmath;
 
 use simplescene::SimpleSceneFile;
+use mesh::Mesh;
+use vcache;
+use bounds::{Aabb, BoundingSphere};
+use batch::StaticBatch;
+use lighting::{LightSet, LitMaterial, normal_matrix};
+use textures::BoundTexture;
+use camera::Camera;
 
 /// An object used directly by the engine to render an object.
 pub struct DrawableObject {
     name:               String,
-    vbuf:               VertexBuffer<Vertex>,
+    vbuf:               VertexBufferAny,
     tlst:               IndexBuffer,
+    /// the edges of the original faces as a line list, for drawing the wireframe
+    edges:              IndexBuffer,
+    /// the projection, view and model matrix together, see `update_matrix`
     uniform:            Uniform,
+    /// places the object in the world
+    model:              cgmath::Matrix4<f32>,
     program:            Arc<Program>,
+    /// average vertex cache misses per triangle of the uploaded indices
+    acmr:               f32,
+    /// the same for the indices as they were before `UploadOptions::optimize` reordered them
+    original_acmr:      f32,
+    /// box around the vertices in object space
+    bounds:             Aabb,
+    /// sphere around the vertices in object space
+    sphere:             BoundingSphere,
+    /// how `draw` draws the object
+    state:              RenderState,
 }
 
+/// The fixed function state an object is drawn with.
+#[derive(Copy, Clone)]
+pub struct RenderState {
+    /// fill the triangles, or only draw their edges or corners
+    pub polygon_mode:   glium::PolygonMode,
+    /// hide the parts of the object behind what has already been drawn. glium does not yet
+    /// allow testing the depth without writing it, so a tested object always leaves its depth
+    /// in the depth buffer.
+    pub depth_test:     bool,
+    pub culling:        glium::BackfaceCullingMode,
+    /// how the color is mixed with what has already been drawn, `None` to replace it
+    pub blending:       Option<glium::BlendingFunction>,
+    /// the width of the lines in pixels, for `PolygonMode::Line`
+    pub line_width:     f32,
+}
+
+impl ::std::default::Default for RenderState {
+    fn default() -> RenderState {
+        RenderState {
+            polygon_mode:   glium::PolygonMode::Fill,
+            depth_test:     true,
+            culling:        glium::BackfaceCullingMode::CullingDisabled,
+            blending:       Option::None,
+            line_width:     1.0,
+        }
+    }
+}
+
+impl RenderState {
+    /// Return the state for drawing the edges of the triangles, which is how objects used to
+    /// be drawn.
+    pub fn wireframe() -> RenderState {
+        RenderState {
+            polygon_mode:   glium::PolygonMode::Line,
+            .. Default::default()
+        }
+    }
+
+    /// Return the state for see-through objects, which mix with what is behind them by their
+    /// alpha. They still write their depth, so draw them after everything else and from back to
+    /// front or the ones further away are hidden behind them.
+    pub fn transparent() -> RenderState {
+        RenderState {
+            blending:       Option::Some(glium::BlendingFunction::Addition {
+                source:         glium::LinearBlendingFactor::SourceAlpha,
+                destination:    glium::LinearBlendingFactor::OneMinusSourceAlpha,
+            }),
+            .. Default::default()
+        }
+    }
+
+    /// Return the glium parameters for the state.
+    pub fn parameters(&self) -> DrawParameters {
+        DrawParameters {
+            depth_function:     if self.depth_test { glium::DepthFunction::IfLessOrEqual } else { glium::DepthFunction::Overwrite },
+            blending_function:  self.blending,
+            line_width:         Option::Some(self.line_width),
+            backface_culling:   self.culling,
+            polygon_mode:       self.polygon_mode,
+            .. Default::default()
+        }
+    }
+}
+
+/// Controls the work done on a mesh while it is uploaded.
+pub struct UploadOptions {
+    /// reorder the triangles for the vertex cache and the vertices for fetching, see `vcache`
+    pub optimize:       bool,
+    /// the number of vertex cache entries to optimize for
+    pub cache_size:     usize,
+    /// only keep the wireframe edges where the faces meet at more than this angle (in radians)
+    /// and the edges on the boundary, instead of every edge
+    pub feature_angle:  Option<f32>,
+}
+
+impl ::std::default::Default for UploadOptions {
+    fn default() -> UploadOptions {
+        UploadOptions {
+            optimize:       false,
+            cache_size:     vcache::DEFAULT_CACHE_SIZE,
+            feature_angle:  Option::None,
+        }
+    }
+}
+
+/// How `DrawableObject::draw_wireframe` draws the edges.
+pub struct WireframeStyle {
+    pub color:          [f32;3],
+    pub line_width:     f32,
+    /// hide the edges behind the faces of the object, by first filling the faces with the
+    /// `background` color
+    pub hidden_line_removal: bool,
+    pub background:     [f32;3],
+}
+
+impl ::std::default::Default for WireframeStyle {
+    fn default() -> WireframeStyle {
+        WireframeStyle {
+            color:                  [1.0, 1.0, 1.0],
+            line_width:             1.0,
+            hidden_line_removal:    true,
+            background:             [0.0, 0.0, 0.0],
+        }
+    }
+}
+
+/// How far the edges are pulled towards the camera, as a fraction of the depth range, so they
+/// are not hidden by the faces they lie on.
+const WIREFRAME_BIAS: f32 = 0.0005;
+
 impl DrawableObject {
     pub fn set_uniform_matrix(&mut self, m: [[f32;4];4]) {
         self.uniform.matrix = m;
@@ -38,74 +176,253 @@ impl DrawableObject {
         self.uniform.matrix
     }
 
+    pub fn set_model_matrix(&mut self, m: cgmath::Matrix4<f32>) {
+        self.model = m;
+    }
+
+    pub fn get_model_matrix(&self) -> cgmath::Matrix4<f32> {
+        self.model
+    }
+
+    /// Set the matrix `draw` and `draw_wireframe` use from the camera and the model matrix. Call
+    /// it after either of them changes.
+    pub fn update_matrix(&mut self, camera: &Camera) {
+        use cgmath::FixedArray;
+
+        self.uniform.matrix = (camera.view_projection() * self.model).into_fixed();
+    }
+
+    /// Return the average number of vertex cache misses per triangle of the uploaded indices,
+    /// measured with a cache of `vcache::DEFAULT_CACHE_SIZE` entries.
+    pub fn get_acmr(&self) -> f32 {
+        self.acmr
+    }
+
+    /// Return the average number of vertex cache misses per triangle from before the indices
+    /// were optimized, the same as `get_acmr` for objects that were not optimized.
+    pub fn get_original_acmr(&self) -> f32 {
+        self.original_acmr
+    }
+
+    /// Return the box around the vertices in object space.
+    pub fn get_bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    /// Return the sphere around the vertices in object space.
+    pub fn get_sphere(&self) -> BoundingSphere {
+        self.sphere
+    }
+
+    /// Return the box around the object once placed in the world by `model`.
+    pub fn world_bounds(&self, model: &cgmath::Matrix4<f32>) -> Aabb {
+        self.bounds.transform(model)
+    }
+
+    /// Return the sphere around the object once placed in the world by `model`.
+    pub fn world_sphere(&self, model: &cgmath::Matrix4<f32>) -> BoundingSphere {
+        self.sphere.transform(model)
+    }
+
+    /// Use another program, like the new version of a shader that was reloaded.
+    pub fn set_program(&mut self, program: Arc<Program>) {
+        self.program = program;
+    }
+
+    pub fn get_program(&self) -> Arc<Program> {
+        self.program.clone()
+    }
+
+    pub fn set_render_state(&mut self, state: RenderState) {
+        self.state = state;
+    }
+
+    pub fn get_render_state(&self) -> RenderState {
+        self.state
+    }
+
+    /// Draw the object with its render state.
     pub fn draw(&self, frame: &mut Frame) {
         use glium::Surface;
-        use std::default::Default;
-
-        let cfg = DrawParameters {
-            depth_function:     glium::DepthFunction::IfLessOrEqual,
-            depth_range:        (0.0, 1.0),
-            blending_function:  Option::None,
-            line_width:         Option::Some(1.0),
-            backface_culling:   glium::BackfaceCullingMode::CullClockWise,
-            polygon_mode:       glium::PolygonMode::Line,
-            multisampling:      false,
-            dithering:          false,
-            viewport:           Option::None,
-            scissor:            Option::None,
+
+        let cfg = self.state.parameters();
+        frame.draw(&self.vbuf, &self.tlst, &*self.program, &self.uniform, &cfg).unwrap();
+    }
+
+    /// Draw the object lit by `lights` with its render state. `program` has to be the `lit`
+    /// program of the shader library and the object needs normals. The object is placed by its
+    /// model matrix and seen through `camera`. `texture` usually comes from
+    /// `TextureManager::bind` for the object's material.
+    pub fn draw_lit(&self, frame: &mut Frame, program: &Program, camera: &Camera,
+                    lights: &LightSet, material: &LitMaterial, texture: &BoundTexture) {
+        use glium::Surface;
+        use cgmath::FixedArray;
+
+        let (count, packed) = lights.pack();
+        let uniform = LitUniform {
+            matrix:             (camera.view_projection() * self.model).into_fixed(),
+            model:              self.model.into_fixed(),
+            normal_matrix:      normal_matrix(&self.model),
+            camera_position:    [camera.position.x, camera.position.y, camera.position.z],
+            ambient:            lights.ambient,
+            diffuse:            material.diffuse,
+            specular:           material.specular,
+            shininess:          material.shininess,
+            light_count:        count,
+            light0:             packed[0],
+            light1:             packed[1],
+            light2:             packed[2],
+            light3:             packed[3],
+            texture:            texture.sampled(),
         };
-        
-        let cfg = DrawParameters {
-            //depth_function:     glium::DepthFunction::IfLessOrEqual,
-            polygon_mode:       glium::PolygonMode::Line,
+
+        frame.draw(&self.vbuf, &self.tlst, program, &uniform, &self.state.parameters()).unwrap();
+    }
+
+    /// Return the program `draw_wireframe` needs, it only uses the positions of the vertices.
+    pub fn wireframe_program(display: &Display) -> Program {
+        glium::Program::from_source(display,
+            "   #version 110
+
+                uniform mat4 matrix;
+                uniform float bias;
+
+                attribute vec3 position;
+
+                void main() {
+                    gl_Position = matrix * vec4(position, 1.0);
+                    gl_Position.z -= bias * gl_Position.w;
+                }
+            ",
+            "   #version 110
+
+                uniform vec3 color;
+
+                void main() {
+                    gl_FragColor = vec4(color, 1.0);
+                }
+            ",
+            None
+        ).unwrap()
+    }
+
+    /// Draw the edges of the original triangles and quads, which unlike drawing the triangles
+    /// with lines does not show the diagonals of the quads. `program` has to come from
+    /// `wireframe_program`. Hidden line removal needs a depth buffer that has been cleared.
+    pub fn draw_wireframe(&self, frame: &mut Frame, program: &Program, style: &WireframeStyle) {
+        use glium::Surface;
+        if style.hidden_line_removal {
+            let fill = DrawParameters {
+                depth_function:     glium::DepthFunction::IfLess,
+                .. Default::default()
+            };
+            let uniform = WireUniform { matrix: self.uniform.matrix, color: style.background, bias: 0.0 };
+            frame.draw(&self.vbuf, &self.tlst, program, &uniform, &fill).unwrap();
+        }
+
+        let lines = DrawParameters {
+            depth_function:     if style.hidden_line_removal { glium::DepthFunction::IfLessOrEqual } else { glium::DepthFunction::Overwrite },
+            line_width:         Option::Some(style.line_width),
             .. Default::default()
         };
-
-        frame.draw(&self.vbuf, &self.tlst, &*self.program, &self.uniform, &cfg).unwrap();
+        let uniform = WireUniform { matrix: self.uniform.matrix, color: style.color, bias: WIREFRAME_BIAS };
+        frame.draw(&self.vbuf, &self.edges, program, &uniform, &lines).unwrap();
     }
 
+    /// Return a new object from the object called `name` in a scene, see `from_mesh`.
     pub fn from_simplescene(display: &Display, scene: &SimpleSceneFile, name: &str, program: Arc<Program>) -> Option<DrawableObject> {
-        let found = scene.find(name);
-
-        if found.is_none() {
-            Option::None
-        } else {
-            let found = found.unwrap().lock().unwrap();
-            let mut vindex: Vec<u16> = Vec::new();
-            let mut vertices: Vec<Vertex> = Vec::new();
-
-            for t in found.triangles.iter() {
-                vindex.push(t[2]);
-                vindex.push(t[1]);
-                vindex.push(t[0]);
-            }
+        scene.find(name).map(|found| {
+            let mesh = Mesh::from_simplescene(&*found.lock().unwrap());
+            DrawableObject::from_mesh(display, &mesh, name, program)
+        })
+    }
 
-            for t in found.quads.iter() {
-                vindex.push(t[2]);
-                vindex.push(t[1]);
-                vindex.push(t[0]);
-                vindex.push(t[3]);
-                vindex.push(t[2]);
-                vindex.push(t[0]);
-            }
+    /// Return a new object from a mesh using the extended vertex format. Data the mesh does not
+    /// have is filled in: white for colors, zero for texture coordinates and normals, and the X
+    /// axis for tangents.
+    pub fn from_mesh(display: &Display, mesh: &Mesh, name: &str, program: Arc<Program>) -> DrawableObject {
+        DrawableObject::from_mesh_options(display, mesh, name, program, &Default::default())
+    }
 
-            for v in found.vertices.iter() {
-                vertices.push(Vertex {
-                    position:   [v.x, v.y, v.z],
-                    color:      [1.0, 1.0, 1.0],
-                });
+    /// Like `from_mesh` but with control over how the mesh is uploaded.
+    pub fn from_mesh_options(display: &Display, mesh: &Mesh, name: &str, program: Arc<Program>, options: &UploadOptions) -> DrawableObject {
+        let mut vindex: Vec<u16> = Vec::new();
+        let mut vertices: Vec<VertexExt> = Vec::with_capacity(mesh.positions.len());
+        let count = mesh.positions.len();
+
+        for t in mesh.triangles.iter() {
+            vindex.push(t[2]);
+            vindex.push(t[1]);
+            vindex.push(t[0]);
+        }
+
+        for t in mesh.quads.iter() {
+            vindex.push(t[2]);
+            vindex.push(t[1]);
+            vindex.push(t[0]);
+            vindex.push(t[3]);
+            vindex.push(t[2]);
+            vindex.push(t[0]);
+        }
+
+        for i in 0..count {
+            let v = mesh.positions[i];
+            let c = if mesh.colors.len() == count { mesh.colors[i] } else { cgmath::Vector3::new(1.0, 1.0, 1.0) };
+            let n = if mesh.normals.len() == count { mesh.normals[i] } else { cgmath::Vector3::new(0.0, 0.0, 0.0) };
+            let t = if mesh.uvs.len() == count { mesh.uvs[i] } else { cgmath::Vector2::new(0.0, 0.0) };
+            vertices.push(VertexExt {
+                position:   [v.x, v.y, v.z],
+                color:      [c.x, c.y, c.z],
+                normal:     [n.x, n.y, n.z],
+                uv:         [t.x, t.y],
+                tangent:    if mesh.tangents.len() == count { mesh.tangents[i] } else { [1.0, 0.0, 0.0, 1.0] },
+            });
+        }
+
+        let mut edges = mesh.edges(options.feature_angle.map(|a| cgmath::Rad { s: a }));
+
+        let original_acmr = vcache::acmr(vindex.as_slice(), vcache::DEFAULT_CACHE_SIZE);
+
+        if options.optimize {
+            vindex = vcache::optimize(vindex.as_slice(), count, options.cache_size);
+            let order = vcache::reorder_vertices(vindex.as_mut_slice(), count);
+            vertices = order.iter().map(|i| vertices[*i as usize]).collect();
+
+            let mut map: Vec<u16> = (0..count).map(|_| 0).collect();
+            for (new, old) in order.iter().enumerate() {
+                map[*old as usize] = new as u16;
             }
+            for e in edges.iter_mut() {
+                *e = [map[e[0] as usize], map[e[1] as usize]];
+            }
+        }
 
-            Option::Some(DrawableObject {
-                name:     String::from_str(name),
-                vbuf:     VertexBuffer::new(display, vertices),
-                tlst:     IndexBuffer::new(display, TrianglesList(vindex)),
-                uniform:  Uniform { matrix: [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]] },
-                program:  program,
-            })
+        let acmr = vcache::acmr(vindex.as_slice(), vcache::DEFAULT_CACHE_SIZE);
+
+        DrawableObject {
+            name:     String::from_str(name),
+            vbuf:     VertexBuffer::new(display, vertices).into_vertex_buffer_any(),
+            tlst:     IndexBuffer::new(display, TrianglesList(vindex)),
+            edges:    edge_buffer(display, edges.as_slice()),
+            uniform:  Uniform { matrix: [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]] },
+            model:    cgmath::Matrix4::identity(),
+            program:  program,
+            acmr:     acmr,
+            original_acmr: original_acmr,
+            bounds:   Aabb::from_points(mesh.positions.as_slice()),
+            sphere:   BoundingSphere::from_points(mesh.positions.as_slice()),
+            state:    Default::default(),
         }
     }
 
+    /// Return a new object from a static batch, named `batch:` and the material of the batch.
+    /// The triangles keep their order so the ranges of the batch still find the objects they
+    /// came from.
+    pub fn from_batch(display: &Display, batch: &StaticBatch, program: Arc<Program>) -> DrawableObject {
+        let name = format!("batch:{}", batch.material.as_ref().map(|m| m.as_slice()).unwrap_or(""));
+        DrawableObject::from_mesh(display, &batch.mesh, name.as_slice(), program)
+    }
+
     /// Read file in `obj` format and return a new `Mesh` object.
     pub fn from_obj(display: &Display, source: &str, program: Arc<Program>) -> Vec<DrawableObject> {
         use std::old_io::{File, Open, Read};
@@ -119,20 +436,33 @@ impl DrawableObject {
         let mut name: Option<String> = Option::None;
         let mut tlst: Vec<u16> = Vec::new();
         let mut vbuf: Vec<Vertex> = Vec::new();
+        // the faces as they are in the file, for the wireframe edges
+        let mut shape = Mesh::new();
         for line in lines {
             let mut parts = line.split_str(" ");
             let first = parts.next().unwrap();
             if first.eq("o") {
                 if name.is_some() {
+                    let acmr = vcache::acmr(tlst.as_slice(), vcache::DEFAULT_CACHE_SIZE);
+                    let (bounds, sphere) = vertex_bounds(vbuf.as_slice());
+                    let edges = shape.edges(Option::None);
                     objects.push(DrawableObject {
                         name:     name.unwrap(),
-                        vbuf:     VertexBuffer::new(display, vbuf),
+                        vbuf:     VertexBuffer::new(display, vbuf).into_vertex_buffer_any(),
                         tlst:     IndexBuffer::new(display, TrianglesList(tlst)),
+                        edges:    edge_buffer(display, edges.as_slice()),
                         uniform:  Uniform { matrix: [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]] },
+                        model:    cgmath::Matrix4::identity(),
                         program:  program.clone(),
+                        acmr:     acmr,
+                        original_acmr: acmr,
+                        bounds:   bounds,
+                        sphere:   sphere,
+                        state:    Default::default(),
                     });
                     tlst = Vec::new();
                     vbuf = Vec::new();
+                    shape = Mesh::new();
                 }
                 name = Option::Some(String::from_str(parts.next().unwrap()));
                 continue;
@@ -149,6 +479,7 @@ impl DrawableObject {
                     position:    [v[0] as f32 * scaler, v[2] as f32 * scaler, v[1] as f32 * scaler],
                     color:       [1.0, 1.0, 1.0],
                 });
+                shape.positions.push(cgmath::Vector3::new(v[0] as f32 * scaler, v[2] as f32 * scaler, v[1] as f32 * scaler));
                 continue;
             }
 
@@ -166,11 +497,13 @@ impl DrawableObject {
                     tlst.push(d);
                     tlst.push(c);
                     tlst.push(a);
+                    shape.quads.push([a, b, c, d]);
                 } else {
                     // triangle
                     tlst.push(c);
                     tlst.push(b);
                     tlst.push(a);
+                    shape.triangles.push([a, b, c]);
                 }
                 continue;
             }
@@ -178,14 +511,43 @@ impl DrawableObject {
 
         println!("tlst.len():{}", tlst.len());
         println!("vbuf.len():{}", vbuf.len());
+        let acmr = vcache::acmr(tlst.as_slice(), vcache::DEFAULT_CACHE_SIZE);
+        let (bounds, sphere) = vertex_bounds(vbuf.as_slice());
+        let edges = shape.edges(Option::None);
         objects.push(DrawableObject {
             name:     name.unwrap(),
-            vbuf:     VertexBuffer::new(display, vbuf),
+            vbuf:     VertexBuffer::new(display, vbuf).into_vertex_buffer_any(),
             tlst:     IndexBuffer::new(display, TrianglesList(tlst)),
+            edges:    edge_buffer(display, edges.as_slice()),
             uniform:  Uniform { matrix: [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]] },
+            model:    cgmath::Matrix4::identity(),
             program:  program,
+            acmr:     acmr,
+            original_acmr: acmr,
+            bounds:   bounds,
+            sphere:   sphere,
+            state:    Default::default(),
         });
 
         objects
     }
 }
+
+/// Return a line list index buffer for edges.
+fn edge_buffer(display: &Display, edges: &[[u16;2]]) -> IndexBuffer {
+    let mut lines: Vec<u16> = Vec::with_capacity(edges.len() * 2);
+    for e in edges.iter() {
+        lines.push(e[0]);
+        lines.push(e[1]);
+    }
+    IndexBuffer::new(display, LinesList(lines))
+}
+
+/// Return the bounds of the positions of some vertices.
+fn vertex_bounds(vertices: &[Vertex]) -> (Aabb, BoundingSphere) {
+    let points: Vec<cgmath::Vector3<f32>> = vertices.iter().map(|v| {
+        cgmath::Vector3::new(v.position[0], v.position[1], v.position[2])
+    }).collect();
+
+    (Aabb::from_points(points.as_slice()), BoundingSphere::from_points(points.as_slice()))
+}
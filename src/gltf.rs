@@ -0,0 +1,444 @@
+//! Imports glTF 2.0 scenes into the same structures that the simple scene loader produces, so
+//! that anything which works with a `SimpleSceneFile` (for example `DrawableObject`) works with
+//! glTF content as well. Both the `.gltf` form (JSON with external or embedded `.bin` buffers)
+//! and the single file binary `.glb` form are supported.
+//!
+//! Every node becomes an object and keeps its name, its location/rotation/scale and its place in
+//! the hierarchy. A mesh with more than one primitive is split up so that each primitive can keep
+//! its own material; the first primitive stays on the node object and the rest become children
+//! of it named `<node>.<n>`. Only triangle list primitives are imported and, because the engine
+//! uses 16-bit indices, a primitive can have at most 65536 vertices.
+//!
+//! Texture coordinates are flipped so that `v` runs upward like it does in Blender and OpenGL.
+//! Nothing else is converted, so the scene stays Y-up as glTF defines it.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::num::Float;
+use std::old_io::{File, BufReader, Reader};
+
+use rustc_serialize::json::Json;
+use rustc_serialize::base64::FromBase64;
+
+use cgmath;
+
+use simplescene::{SimpleSceneFile, SimpleSceneObject, SimpleSceneMaterial, SimpleSceneSkin};
+use simplescene::{SimpleSceneAnimation, SimpleSceneChannel, SimpleSceneProperty, SimpleSceneInterpolation};
+
+pub const GLB_MAGIC: u32 = 0x46546C67;
+pub const GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+pub const GLB_CHUNK_BIN: u32 = 0x004E4942;
+
+/// Return a simple scene instance by loading a `.gltf` or `.glb` file. The format is detected
+/// from the file contents and not the extension.
+pub fn load(source: &str) -> SimpleSceneFile {
+    let path = Path::new(source);
+    let data = File::open(&path).unwrap().read_to_end().unwrap();
+
+    let (doc, bin) = if data.len() >= 12 && BufReader::new(&data[..4]).read_le_u32().unwrap() == GLB_MAGIC {
+        split_glb(data.as_slice())
+    } else {
+        (parse_json(data.as_slice()), Option::None)
+    };
+
+    let buffers = load_buffers(&path, &doc, bin);
+    let materials = load_materials(&doc);
+    let nodes = array(&doc, "nodes");
+
+    let names: Vec<String> = nodes.iter().enumerate().map(|(i, node)| {
+        string(node, "name").unwrap_or(format!("node{}", i))
+    }).collect();
+
+    let mut objects: Vec<Arc<Mutex<SimpleSceneObject>>> = Vec::new();
+    let mut node_objects: Vec<Arc<Mutex<SimpleSceneObject>>> = Vec::new();
+
+    for (i, node) in nodes.iter().enumerate() {
+        let mut object = SimpleSceneObject::new();
+        object.name = names[i].clone();
+        set_transform(&mut object, node);
+
+        let is_lamp = node.find("extensions").and_then(|e| e.find("KHR_lights_punctual")).is_some();
+        object.typ = String::from_str(match (index(node, "mesh"), index(node, "camera")) {
+            (Some(_), _) => "MESH",
+            (None, Some(_)) => "CAMERA",
+            _ if is_lamp => "LAMP",
+            _ => "EMPTY",
+        });
+
+        let skin = index(node, "skin").map(|s| load_skin(&doc, buffers.as_slice(), &array(&doc, "skins")[s], names.as_slice()));
+
+        let object = Arc::new(Mutex::new(object));
+
+        if let Some(mesh) = index(node, "mesh") {
+            let primitives = array(&array(&doc, "meshes")[mesh], "primitives");
+            for (n, primitive) in primitives.iter().enumerate() {
+                if n == 0 {
+                    let mut object = object.lock().unwrap();
+                    load_primitive(&doc, buffers.as_slice(), primitive, materials.as_slice(), &mut *object);
+                    object.skin = skin.clone();
+                    continue;
+                }
+
+                let mut child = SimpleSceneObject::new();
+                child.name = format!("{}.{}", names[i], n);
+                child.typ = String::from_str("MESH");
+                child.skin = skin.clone();
+                child.parent = Option::Some(object.clone());
+                load_primitive(&doc, buffers.as_slice(), primitive, materials.as_slice(), &mut child);
+
+                let child = Arc::new(Mutex::new(child));
+                object.lock().unwrap().child.push(child.clone());
+                objects.push(child);
+            }
+        }
+
+        node_objects.push(object.clone());
+        objects.push(object);
+    }
+
+    // Now that every node has an object we can hook up the hierarchy.
+    for (i, node) in nodes.iter().enumerate() {
+        for c in array(node, "children").iter() {
+            let c = c.as_u64().unwrap() as usize;
+            node_objects[c].lock().unwrap().parent = Option::Some(node_objects[i].clone());
+            node_objects[i].lock().unwrap().child.push(node_objects[c].clone());
+        }
+    }
+
+    let animations = array(&doc, "animations").iter().enumerate().map(|(i, animation)| {
+        load_animation(&doc, buffers.as_slice(), animation, names.as_slice(), i)
+    }).collect();
+
+    let scene = SimpleSceneFile {
+        path:           path,
+        objects:        objects,
+        materials:      materials,
+        animations:     animations,
+    };
+
+    scene.update_bounds();
+    scene
+}
+
+/// Split a `.glb` file into its JSON document and its binary buffer (if it has one).
+fn split_glb(data: &[u8]) -> (Json, Option<Vec<u8>>) {
+    let mut r = BufReader::new(data);
+    let _magic = r.read_le_u32().unwrap();
+    let version = r.read_le_u32().unwrap();
+    let length = r.read_le_u32().unwrap() as usize;
+
+    if version != 2 {
+        panic!("gltf: unsupported glb version {}", version);
+    }
+
+    let mut json: Option<Json> = Option::None;
+    let mut bin: Option<Vec<u8>> = Option::None;
+    let mut offset = 12;
+
+    while offset + 8 <= length && offset + 8 <= data.len() {
+        let mut r = BufReader::new(&data[offset..]);
+        let clen = r.read_le_u32().unwrap() as usize;
+        let ctype = r.read_le_u32().unwrap();
+        let chunk = &data[offset + 8..offset + 8 + clen];
+        match ctype {
+            GLB_CHUNK_JSON => json = Option::Some(parse_json(chunk)),
+            GLB_CHUNK_BIN => bin = Option::Some(chunk.to_vec()),
+            // unknown chunks must be ignored
+            _ => {},
+        }
+        offset += 8 + clen;
+    }
+
+    match json {
+        Some(json) => (json, bin),
+        None => panic!("gltf: glb file has no JSON chunk"),
+    }
+}
+
+fn parse_json(data: &[u8]) -> Json {
+    Json::from_str(::std::str::from_utf8(data).unwrap()).unwrap()
+}
+
+fn load_buffers(path: &Path, doc: &Json, bin: Option<Vec<u8>>) -> Vec<Vec<u8>> {
+    let mut bin = bin;
+    array(doc, "buffers").iter().map(|buffer| {
+        match buffer.find("uri").and_then(|u| u.as_string()) {
+            Some(uri) => load_uri(path, uri),
+            None => bin.take().expect("gltf: buffer without an uri outside of a glb file"),
+        }
+    }).collect()
+}
+
+fn load_uri(path: &Path, uri: &str) -> Vec<u8> {
+    if uri.starts_with("data:") {
+        match uri.find_str(";base64,") {
+            Some(i) => uri[i + 8..].from_base64().unwrap(),
+            None => panic!("gltf: only base64 data uris are supported"),
+        }
+    } else {
+        File::open(&path.dir_path().join(uri)).unwrap().read_to_end().unwrap()
+    }
+}
+
+/// Return the elements of an accessor as floats along with the number of components in each
+/// element. Integer components are converted as is unless the accessor is normalized.
+fn read_accessor(doc: &Json, buffers: &[Vec<u8>], accessor: usize) -> (Vec<f32>, usize) {
+    let accessor = &array(doc, "accessors")[accessor];
+
+    if accessor.find("sparse").is_some() {
+        panic!("gltf: sparse accessors are not supported");
+    }
+
+    let count = index(accessor, "count").unwrap();
+    let ctype = index(accessor, "componentType").unwrap();
+    let normalized = accessor.find("normalized").and_then(|v| v.as_boolean()).unwrap_or(false);
+    let components = match accessor.find("type").and_then(|v| v.as_string()).unwrap() {
+        "SCALAR" => 1,
+        "VEC2" => 2,
+        "VEC3" => 3,
+        "VEC4" | "MAT2" => 4,
+        "MAT3" => 9,
+        "MAT4" => 16,
+        other => panic!("gltf: unknown accessor type {}", other),
+    };
+    let size = match ctype {
+        5120 | 5121 => 1,
+        5122 | 5123 => 2,
+        5125 | 5126 => 4,
+        other => panic!("gltf: unknown component type {}", other),
+    };
+
+    // An accessor without a buffer view is defined to be all zeros.
+    let view = match index(accessor, "bufferView") {
+        Some(view) => &array(doc, "bufferViews")[view],
+        None => return (::std::iter::repeat(0.0f32).take(count * components).collect(), components),
+    };
+
+    let buffer = &buffers[index(view, "buffer").unwrap()];
+    let offset = index(view, "byteOffset").unwrap_or(0) + index(accessor, "byteOffset").unwrap_or(0);
+    let stride = index(view, "byteStride").unwrap_or(size * components);
+
+    let mut out: Vec<f32> = Vec::with_capacity(count * components);
+
+    for i in 0..count {
+        for c in 0..components {
+            let at = offset + i * stride + c * size;
+            let mut r = BufReader::new(&buffer[at..at + size]);
+            out.push(match ctype {
+                5120 => {
+                    let v = r.read_i8().unwrap() as f32;
+                    if normalized { (v / 127.0).max(-1.0) } else { v }
+                },
+                5121 => {
+                    let v = r.read_u8().unwrap() as f32;
+                    if normalized { v / 255.0 } else { v }
+                },
+                5122 => {
+                    let v = r.read_le_i16().unwrap() as f32;
+                    if normalized { (v / 32767.0).max(-1.0) } else { v }
+                },
+                5123 => {
+                    let v = r.read_le_u16().unwrap() as f32;
+                    if normalized { v / 65535.0 } else { v }
+                },
+                5125 => r.read_le_u32().unwrap() as f32,
+                _ => r.read_le_f32().unwrap(),
+            });
+        }
+    }
+
+    (out, components)
+}
+
+fn load_materials(doc: &Json) -> Vec<SimpleSceneMaterial> {
+    array(doc, "materials").iter().enumerate().map(|(i, m)| {
+        let name = string(m, "name").unwrap_or(format!("material{}", i));
+        let mut material = SimpleSceneMaterial::new(name.as_slice());
+
+        if let Some(pbr) = m.find("pbrMetallicRoughness") {
+            if let Some(c) = numbers(pbr, "baseColorFactor") {
+                material.diffuse = [c[0], c[1], c[2], c[3]];
+            }
+            material.metallic = number(pbr, "metallicFactor", 1.0);
+            material.roughness = number(pbr, "roughnessFactor", 1.0);
+            material.texture = pbr.find("baseColorTexture")
+                .and_then(|t| index(t, "index"))
+                .and_then(|t| index(&array(doc, "textures")[t], "source"))
+                .and_then(|s| string(&array(doc, "images")[s], "uri"));
+        }
+
+        material
+    }).collect()
+}
+
+fn load_primitive(doc: &Json, buffers: &[Vec<u8>], primitive: &Json, materials: &[SimpleSceneMaterial], object: &mut SimpleSceneObject) {
+    // only triangle lists
+    if index(primitive, "mode").unwrap_or(4) != 4 {
+        return;
+    }
+
+    let attributes = primitive.find("attributes").expect("gltf: primitive without attributes");
+    let (positions, _) = read_accessor(doc, buffers, index(attributes, "POSITION").expect("gltf: primitive without positions"));
+    let count = positions.len() / 3;
+
+    if count > 65536 {
+        panic!("gltf: primitive has {} vertices which is too many for 16-bit indices", count);
+    }
+
+    for p in positions.chunks(3) {
+        object.vertices.push(cgmath::Vector3::new(p[0], p[1], p[2]));
+    }
+
+    if let Some(a) = index(attributes, "NORMAL") {
+        for n in read_accessor(doc, buffers, a).0.chunks(3) {
+            object.normals.push(cgmath::Vector3::new(n[0], n[1], n[2]));
+        }
+    }
+
+    if let Some(a) = index(attributes, "TEXCOORD_0") {
+        for t in read_accessor(doc, buffers, a).0.chunks(2) {
+            object.uvs.push(cgmath::Vector2::new(t[0], 1.0 - t[1]));
+        }
+    }
+
+    if let Some(a) = index(attributes, "JOINTS_0") {
+        for j in read_accessor(doc, buffers, a).0.chunks(4) {
+            object.joints.push([j[0] as u16, j[1] as u16, j[2] as u16, j[3] as u16]);
+        }
+    }
+
+    if let Some(a) = index(attributes, "WEIGHTS_0") {
+        for w in read_accessor(doc, buffers, a).0.chunks(4) {
+            object.weights.push([w[0], w[1], w[2], w[3]]);
+        }
+    }
+
+    let indices: Vec<u16> = match index(primitive, "indices") {
+        Some(a) => read_accessor(doc, buffers, a).0.iter().map(|i| *i as u16).collect(),
+        None => (0..count).map(|i| i as u16).collect(),
+    };
+
+    for t in indices.chunks(3) {
+        if t.len() == 3 {
+            object.triangles.push([t[0], t[1], t[2]]);
+        }
+    }
+
+    object.material = index(primitive, "material").map(|m| materials[m].name.clone());
+}
+
+fn load_skin(doc: &Json, buffers: &[Vec<u8>], skin: &Json, names: &[String]) -> SimpleSceneSkin {
+    let joints: Vec<String> = array(skin, "joints").iter().map(|j| {
+        names[j.as_u64().unwrap() as usize].clone()
+    }).collect();
+
+    let inverse_bind = match index(skin, "inverseBindMatrices") {
+        Some(a) => read_accessor(doc, buffers, a).0.chunks(16).map(|m| {
+            cgmath::Matrix4::new(
+                m[0], m[1], m[2], m[3],
+                m[4], m[5], m[6], m[7],
+                m[8], m[9], m[10], m[11],
+                m[12], m[13], m[14], m[15]
+            )
+        }).collect(),
+        None => joints.iter().map(|_| cgmath::Matrix4::identity()).collect(),
+    };
+
+    SimpleSceneSkin {
+        joints:         joints,
+        inverse_bind:   inverse_bind,
+    }
+}
+
+fn load_animation(doc: &Json, buffers: &[Vec<u8>], animation: &Json, names: &[String], i: usize) -> SimpleSceneAnimation {
+    let samplers = array(animation, "samplers");
+    let mut channels: Vec<SimpleSceneChannel> = Vec::new();
+
+    for channel in array(animation, "channels").iter() {
+        let target = channel.find("target").expect("gltf: animation channel without target");
+        let node = match index(target, "node") {
+            Some(node) => node,
+            None => continue,
+        };
+        let property = match target.find("path").and_then(|p| p.as_string()).unwrap() {
+            "translation" => SimpleSceneProperty::Location,
+            "rotation" => SimpleSceneProperty::Rotation,
+            "scale" => SimpleSceneProperty::Scale,
+            // morph target weights have nowhere to go
+            _ => continue,
+        };
+
+        let sampler = &samplers[index(channel, "sampler").unwrap()];
+        let interpolation = match string(sampler, "interpolation") {
+            Some(ref s) if s.as_slice() == "STEP" => SimpleSceneInterpolation::Step,
+            Some(ref s) if s.as_slice() == "CUBICSPLINE" => SimpleSceneInterpolation::CubicSpline,
+            _ => SimpleSceneInterpolation::Linear,
+        };
+
+        let (times, _) = read_accessor(doc, buffers, index(sampler, "input").unwrap());
+        let (values, n) = read_accessor(doc, buffers, index(sampler, "output").unwrap());
+
+        channels.push(SimpleSceneChannel {
+            target:         names[node].clone(),
+            property:       property,
+            interpolation:  interpolation,
+            times:          times,
+            values:         values.chunks(n).map(|v| {
+                [v[0], v[1], v[2], if n > 3 { v[3] } else { 0.0 }]
+            }).collect(),
+        });
+    }
+
+    SimpleSceneAnimation {
+        name:       string(animation, "name").unwrap_or(format!("animation{}", i)),
+        channels:   channels,
+    }
+}
+
+fn set_transform(object: &mut SimpleSceneObject, node: &Json) {
+    if let Some(m) = numbers(node, "matrix") {
+        object.set_matrix(&cgmath::Matrix4::new(
+            m[0], m[1], m[2], m[3],
+            m[4], m[5], m[6], m[7],
+            m[8], m[9], m[10], m[11],
+            m[12], m[13], m[14], m[15]
+        ));
+    }
+
+    if let Some(t) = numbers(node, "translation") {
+        object.location = cgmath::Vector3::new(t[0], t[1], t[2]);
+    }
+
+    if let Some(r) = numbers(node, "rotation") {
+        object.rotation = cgmath::Quaternion::new(r[3], r[0], r[1], r[2]);
+    }
+
+    if let Some(s) = numbers(node, "scale") {
+        object.scale = cgmath::Vector3::new(s[0], s[1], s[2]);
+    }
+}
+
+fn array<'a>(json: &'a Json, key: &str) -> &'a [Json] {
+    match json.find(key).and_then(|v| v.as_array()) {
+        Some(a) => a.as_slice(),
+        None => &[],
+    }
+}
+
+fn index(json: &Json, key: &str) -> Option<usize> {
+    json.find(key).and_then(|v| v.as_u64()).map(|v| v as usize)
+}
+
+fn number(json: &Json, key: &str, default: f32) -> f32 {
+    json.find(key).and_then(|v| v.as_f64()).map(|v| v as f32).unwrap_or(default)
+}
+
+fn numbers(json: &Json, key: &str) -> Option<Vec<f32>> {
+    json.find(key).and_then(|v| v.as_array()).map(|a| {
+        a.iter().map(|v| v.as_f64().unwrap() as f32).collect()
+    })
+}
+
+fn string(json: &Json, key: &str) -> Option<String> {
+    json.find(key).and_then(|v| v.as_string()).map(|v| String::from_str(v))
+}
@@ -0,0 +1,550 @@
+//! Writes a `SimpleSceneFile` out as glTF 2.0 so our scenes can be looked at in external viewers
+//! and shared with people who do not run the engine. This is the reverse of the `gltf` module and
+//! a scene written here and loaded back with `gltf::load` should come back the same, which is
+//! what `validate` checks.
+//!
+//! Every object becomes a node with its location, rotation, scale and children. Objects with
+//! geometry get a mesh with a single triangle list primitive (quads are split into two triangles)
+//! carrying whatever normals, texture coordinates, joints and weights the object has. Materials,
+//! skins and animations are written when the scene has them.
+//!
+//! All binary data goes into one buffer. For a `.glb` path it is stored inside the file and for
+//! anything else it is written next to it as a `.bin` file.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::collections::BTreeMap;
+use std::old_io::{File, BufferedWriter, Writer};
+
+use rustc_serialize::json::Json;
+
+use cgmath;
+
+use gltf;
+use gltf::{GLB_MAGIC, GLB_CHUNK_JSON, GLB_CHUNK_BIN};
+use simplescene::{SimpleSceneFile, SimpleSceneObject, SimpleSceneProperty, SimpleSceneInterpolation};
+
+const ARRAY_BUFFER: u64 = 34962;
+const ELEMENT_ARRAY_BUFFER: u64 = 34963;
+const UNSIGNED_SHORT: u64 = 5123;
+const FLOAT: u64 = 5126;
+
+/// Collects the binary data along with the buffer views and accessors describing it.
+struct Builder {
+    bin:            Vec<u8>,
+    views:          Vec<Json>,
+    accessors:      Vec<Json>,
+}
+
+impl Builder {
+    fn new() -> Builder {
+        Builder {
+            bin:            Vec::new(),
+            views:          Vec::new(),
+            accessors:      Vec::new(),
+        }
+    }
+
+    /// Start a new buffer view, views have to start on a four byte boundary.
+    fn begin_view(&mut self) -> usize {
+        while self.bin.len() % 4 != 0 {
+            self.bin.push(0);
+        }
+        self.bin.len()
+    }
+
+    fn end_view(&mut self, start: usize, target: Option<u64>) -> usize {
+        let mut view = vec![
+            ("buffer", Json::U64(0)),
+            ("byteOffset", Json::U64(start as u64)),
+            ("byteLength", Json::U64((self.bin.len() - start) as u64)),
+        ];
+        if let Some(target) = target {
+            view.push(("target", Json::U64(target)));
+        }
+        self.views.push(object(view));
+        self.views.len() - 1
+    }
+
+    /// Add an accessor of floats, `components` at a time. The bounds are only written when
+    /// asked for since the specification only requires them for positions and key times.
+    fn floats(&mut self, data: &[f32], components: usize, typ: &str, target: Option<u64>, bounds: bool) -> usize {
+        let start = self.begin_view();
+        for v in data.iter() {
+            self.bin.write_le_f32(*v).unwrap();
+        }
+        let view = self.end_view(start, target);
+
+        let mut accessor = vec![
+            ("bufferView", Json::U64(view as u64)),
+            ("componentType", Json::U64(FLOAT)),
+            ("count", Json::U64((data.len() / components) as u64)),
+            ("type", Json::String(String::from_str(typ))),
+        ];
+
+        if bounds && data.len() >= components {
+            let mut min: Vec<f32> = data[..components].to_vec();
+            let mut max: Vec<f32> = data[..components].to_vec();
+            for e in data.chunks(components) {
+                for c in 0..components {
+                    if e[c] < min[c] { min[c] = e[c]; }
+                    if e[c] > max[c] { max[c] = e[c]; }
+                }
+            }
+            accessor.push(("min", numbers(min.as_slice())));
+            accessor.push(("max", numbers(max.as_slice())));
+        }
+
+        self.accessors.push(object(accessor));
+        self.accessors.len() - 1
+    }
+
+    /// Add an accessor of unsigned shorts, `components` at a time.
+    fn shorts(&mut self, data: &[u16], components: usize, typ: &str, target: Option<u64>) -> usize {
+        let start = self.begin_view();
+        for v in data.iter() {
+            self.bin.write_le_u16(*v).unwrap();
+        }
+        let view = self.end_view(start, target);
+
+        self.accessors.push(object(vec![
+            ("bufferView", Json::U64(view as u64)),
+            ("componentType", Json::U64(UNSIGNED_SHORT)),
+            ("count", Json::U64((data.len() / components) as u64)),
+            ("type", Json::String(String::from_str(typ))),
+        ]));
+        self.accessors.len() - 1
+    }
+}
+
+/// Write the scene as glTF to `path`. When the path ends in `.glb` the binary form is written,
+/// otherwise a `.gltf` JSON file plus a `.bin` file with the same name.
+pub fn write_scene(scene: &SimpleSceneFile, path: &str) {
+    let path = Path::new(path);
+    let binary = path.extension_str().map(|e| e.eq("glb")).unwrap_or(false);
+
+    let mut builder = Builder::new();
+    let doc = build_document(scene, &mut builder);
+
+    if binary {
+        write_glb(&path, doc, builder.bin);
+    } else {
+        let binpath = path.with_extension("bin");
+        let mut doc = doc;
+        if let Json::Object(ref mut map) = doc {
+            map.insert(String::from_str("buffers"), Json::Array(vec![object(vec![
+                ("byteLength", Json::U64(builder.bin.len() as u64)),
+                ("uri", Json::String(String::from_str(binpath.filename_str().unwrap()))),
+            ])]));
+        }
+
+        let mut w = BufferedWriter::new(File::create(&path).unwrap());
+        write!(&mut w, "{}", doc).unwrap();
+        w.flush().unwrap();
+
+        File::create(&binpath).unwrap().write_all(builder.bin.as_slice()).unwrap();
+    }
+}
+
+/// Load a file written by `write_scene` back in and check that it matches the scene it was
+/// written from. The first difference found is returned as the error.
+pub fn validate(scene: &SimpleSceneFile, path: &str) -> Result<(), String> {
+    let loaded = gltf::load(path);
+
+    if loaded.objects.len() != scene.objects.len() {
+        return Err(format!("expected {} objects but found {}", scene.objects.len(), loaded.objects.len()));
+    }
+
+    if loaded.materials.len() != scene.materials.len() {
+        return Err(format!("expected {} materials but found {}", scene.materials.len(), loaded.materials.len()));
+    }
+
+    if loaded.animations.len() != scene.animations.len() {
+        return Err(format!("expected {} animations but found {}", scene.animations.len(), loaded.animations.len()));
+    }
+
+    for (a, b) in scene.objects.iter().zip(loaded.objects.iter()) {
+        let a = a.lock().unwrap();
+        let b = b.lock().unwrap();
+
+        if a.name != b.name {
+            return Err(format!("expected object {} but found {}", a.name, b.name));
+        }
+
+        let parent = |o: &SimpleSceneObject| o.parent.as_ref().map(|p| p.lock().unwrap().name.clone());
+        if parent(&*a) != parent(&*b) {
+            return Err(format!("object {} has the wrong parent", a.name));
+        }
+
+        if a.vertices.len() != b.vertices.len() || a.normals.len() != b.normals.len() || a.uvs.len() != b.uvs.len() {
+            return Err(format!("object {} has the wrong vertex data", a.name));
+        }
+
+        if a.triangles.len() + a.quads.len() * 2 != b.triangles.len() {
+            return Err(format!("object {} has the wrong number of triangles", a.name));
+        }
+
+        if a.material != b.material {
+            return Err(format!("object {} has the wrong material", a.name));
+        }
+
+        if !close(a.location, b.location) || !close(a.scale, b.scale) || !close_rotation(a.rotation, b.rotation) {
+            return Err(format!("object {} has the wrong transform", a.name));
+        }
+    }
+
+    for (a, b) in scene.animations.iter().zip(loaded.animations.iter()) {
+        // channels without keys are not written
+        if a.channels.iter().filter(|c| !c.times.is_empty()).count() != b.channels.len() {
+            return Err(format!("animation {} has the wrong number of channels", a.name));
+        }
+    }
+
+    Ok(())
+}
+
+fn close(a: cgmath::Vector3<f32>, b: cgmath::Vector3<f32>) -> bool {
+    use std::num::Float;
+    (a.x - b.x).abs() < 1e-5 && (a.y - b.y).abs() < 1e-5 && (a.z - b.z).abs() < 1e-5
+}
+
+/// Return true if two rotations are the same, `q` and `-q` being the same rotation.
+fn close_rotation(a: cgmath::Quaternion<f32>, b: cgmath::Quaternion<f32>) -> bool {
+    use std::num::Float;
+    let dot = a.s * b.s + a.v.x * b.v.x + a.v.y * b.v.y + a.v.z * b.v.z;
+    dot.abs() > 1.0 - 1e-5
+}
+
+fn write_glb(path: &Path, doc: Json, bin: Vec<u8>) {
+    let mut doc = doc;
+    if let Json::Object(ref mut map) = doc {
+        map.insert(String::from_str("buffers"), Json::Array(vec![object(vec![
+            ("byteLength", Json::U64(bin.len() as u64)),
+        ])]));
+    }
+
+    // Chunks have to be padded to four bytes, JSON with spaces and binary with zeros.
+    let mut json = format!("{}", doc).into_bytes();
+    while json.len() % 4 != 0 {
+        json.push(b' ');
+    }
+    let mut bin = bin;
+    while bin.len() % 4 != 0 {
+        bin.push(0);
+    }
+
+    let mut w = BufferedWriter::new(File::create(path).unwrap());
+    w.write_le_u32(GLB_MAGIC).unwrap();
+    w.write_le_u32(2).unwrap();
+    w.write_le_u32((12 + 8 + json.len() + 8 + bin.len()) as u32).unwrap();
+    w.write_le_u32(json.len() as u32).unwrap();
+    w.write_le_u32(GLB_CHUNK_JSON).unwrap();
+    w.write_all(json.as_slice()).unwrap();
+    w.write_le_u32(bin.len() as u32).unwrap();
+    w.write_le_u32(GLB_CHUNK_BIN).unwrap();
+    w.write_all(bin.as_slice()).unwrap();
+    w.flush().unwrap();
+}
+
+/// Build everything except the `buffers` entry which depends on how the data is stored.
+fn build_document(scene: &SimpleSceneFile, builder: &mut Builder) -> Json {
+    let position = |o: &Arc<Mutex<SimpleSceneObject>>| -> usize {
+        scene.objects.iter().position(|other| {
+            &**other as *const Mutex<SimpleSceneObject> == &**o as *const Mutex<SimpleSceneObject>
+        }).expect("object is not part of the scene")
+    };
+    // The names are gathered up front since the objects are locked while the
+    // nodes are built and a skin can refer to the object that owns it.
+    let names: Vec<String> = scene.objects.iter().map(|o| o.lock().unwrap().name.clone()).collect();
+    let node_by_name = |name: &str| -> Option<usize> {
+        names.iter().position(|n| n.as_slice().eq(name))
+    };
+
+    let mut nodes: Vec<Json> = Vec::new();
+    let mut meshes: Vec<Json> = Vec::new();
+    let mut skins: Vec<Json> = Vec::new();
+    let mut roots: Vec<Json> = Vec::new();
+
+    for (i, o) in scene.objects.iter().enumerate() {
+        let o = o.lock().unwrap();
+        let r = o.rotation;
+
+        let mut node = vec![
+            ("name", Json::String(o.name.clone())),
+            ("translation", numbers(&[o.location.x, o.location.y, o.location.z])),
+            ("rotation", numbers(&[r.v.x, r.v.y, r.v.z, r.s])),
+            ("scale", numbers(&[o.scale.x, o.scale.y, o.scale.z])),
+        ];
+
+        if o.child.len() > 0 {
+            node.push(("children", Json::Array(o.child.iter().map(|c| Json::U64(position(c) as u64)).collect())));
+        }
+
+        if o.parent.is_none() {
+            roots.push(Json::U64(i as u64));
+        }
+
+        if o.vertices.len() > 0 && (o.triangles.len() > 0 || o.quads.len() > 0) {
+            meshes.push(build_mesh(scene, &*o, builder));
+            node.push(("mesh", Json::U64((meshes.len() - 1) as u64)));
+        }
+
+        if let Some(ref skin) = o.skin {
+            let joints: Vec<Json> = skin.joints.iter().map(|j| {
+                Json::U64(node_by_name(j.as_slice()).expect("skin joint is not part of the scene") as u64)
+            }).collect();
+            let mut matrices: Vec<f32> = Vec::new();
+            for m in skin.inverse_bind.iter() {
+                for c in [m.x, m.y, m.z, m.w].iter() {
+                    matrices.push_all(&[c.x, c.y, c.z, c.w]);
+                }
+            }
+            let ibm = builder.floats(matrices.as_slice(), 16, "MAT4", Option::None, false);
+            skins.push(object(vec![
+                ("joints", Json::Array(joints)),
+                ("inverseBindMatrices", Json::U64(ibm as u64)),
+            ]));
+            node.push(("skin", Json::U64((skins.len() - 1) as u64)));
+        }
+
+        nodes.push(object(node));
+    }
+
+    let mut images: Vec<Json> = Vec::new();
+    let mut textures: Vec<Json> = Vec::new();
+    let materials: Vec<Json> = scene.materials.iter().map(|m| {
+        let d = m.diffuse;
+        let mut pbr = vec![
+            ("baseColorFactor", numbers(&d)),
+            ("metallicFactor", Json::F64(m.metallic as f64)),
+            ("roughnessFactor", Json::F64(m.roughness as f64)),
+        ];
+        if let Some(ref texture) = m.texture {
+            images.push(object(vec![("uri", Json::String(texture.clone()))]));
+            textures.push(object(vec![("source", Json::U64((images.len() - 1) as u64))]));
+            pbr.push(("baseColorTexture", object(vec![("index", Json::U64((textures.len() - 1) as u64))])));
+        }
+        object(vec![
+            ("name", Json::String(m.name.clone())),
+            ("pbrMetallicRoughness", object(pbr)),
+        ])
+    }).collect();
+
+    let animations: Vec<Json> = scene.animations.iter().map(|a| {
+        let mut samplers: Vec<Json> = Vec::new();
+        let mut channels: Vec<Json> = Vec::new();
+
+        for channel in a.channels.iter() {
+            let node = match node_by_name(channel.target.as_slice()) {
+                Some(node) => node,
+                None => continue,
+            };
+            // glTF accessors can not be empty, a channel without keys does nothing anyway
+            if channel.times.is_empty() {
+                continue;
+            }
+            let (path, n, typ) = match channel.property {
+                SimpleSceneProperty::Location => ("translation", 3, "VEC3"),
+                SimpleSceneProperty::Rotation => ("rotation", 4, "VEC4"),
+                SimpleSceneProperty::Scale => ("scale", 3, "VEC3"),
+            };
+            let interpolation = match channel.interpolation {
+                SimpleSceneInterpolation::Step => "STEP",
+                SimpleSceneInterpolation::Linear => "LINEAR",
+                SimpleSceneInterpolation::CubicSpline => "CUBICSPLINE",
+            };
+
+            let mut values: Vec<f32> = Vec::new();
+            for v in channel.values.iter() {
+                values.push_all(&v[..n]);
+            }
+
+            let input = builder.floats(channel.times.as_slice(), 1, "SCALAR", Option::None, true);
+            let output = builder.floats(values.as_slice(), n, typ, Option::None, false);
+
+            samplers.push(object(vec![
+                ("input", Json::U64(input as u64)),
+                ("output", Json::U64(output as u64)),
+                ("interpolation", Json::String(String::from_str(interpolation))),
+            ]));
+            channels.push(object(vec![
+                ("sampler", Json::U64((samplers.len() - 1) as u64)),
+                ("target", object(vec![
+                    ("node", Json::U64(node as u64)),
+                    ("path", Json::String(String::from_str(path))),
+                ])),
+            ]));
+        }
+
+        object(vec![
+            ("name", Json::String(a.name.clone())),
+            ("samplers", Json::Array(samplers)),
+            ("channels", Json::Array(channels)),
+        ])
+    }).collect();
+
+    let mut doc = vec![
+        ("asset", object(vec![
+            ("version", Json::String(String::from_str("2.0"))),
+            ("generator", Json::String(String::from_str("kordom"))),
+        ])),
+        ("scene", Json::U64(0)),
+        ("scenes", Json::Array(vec![object(vec![("nodes", Json::Array(roots))])])),
+        ("nodes", Json::Array(nodes)),
+    ];
+
+    // The specification does not allow empty arrays at the top level.
+    let lists = vec![
+        ("meshes", meshes),
+        ("materials", materials),
+        ("images", images),
+        ("textures", textures),
+        ("skins", skins),
+        ("animations", animations),
+    ];
+    for (name, list) in lists.into_iter() {
+        if list.len() > 0 {
+            doc.push((name, Json::Array(list)));
+        }
+    }
+
+    if builder.views.len() > 0 {
+        doc.push(("bufferViews", Json::Array(builder.views.clone())));
+        doc.push(("accessors", Json::Array(builder.accessors.clone())));
+    }
+
+    object(doc)
+}
+
+fn build_mesh(scene: &SimpleSceneFile, o: &SimpleSceneObject, builder: &mut Builder) -> Json {
+    let mut positions: Vec<f32> = Vec::new();
+    for v in o.vertices.iter() {
+        positions.push_all(&[v.x, v.y, v.z]);
+    }
+
+    let mut attributes = vec![
+        ("POSITION", Json::U64(builder.floats(positions.as_slice(), 3, "VEC3", Option::Some(ARRAY_BUFFER), true) as u64)),
+    ];
+
+    if o.normals.len() == o.vertices.len() {
+        let mut normals: Vec<f32> = Vec::new();
+        for n in o.normals.iter() {
+            normals.push_all(&[n.x, n.y, n.z]);
+        }
+        attributes.push(("NORMAL", Json::U64(builder.floats(normals.as_slice(), 3, "VEC3", Option::Some(ARRAY_BUFFER), false) as u64)));
+    }
+
+    if o.uvs.len() == o.vertices.len() {
+        // glTF puts the origin of the texture in the top left corner
+        let mut uvs: Vec<f32> = Vec::new();
+        for t in o.uvs.iter() {
+            uvs.push_all(&[t.x, 1.0 - t.y]);
+        }
+        attributes.push(("TEXCOORD_0", Json::U64(builder.floats(uvs.as_slice(), 2, "VEC2", Option::Some(ARRAY_BUFFER), false) as u64)));
+    }
+
+    if o.joints.len() == o.vertices.len() && o.weights.len() == o.vertices.len() {
+        let mut joints: Vec<u16> = Vec::new();
+        let mut weights: Vec<f32> = Vec::new();
+        for (j, w) in o.joints.iter().zip(o.weights.iter()) {
+            joints.push_all(j);
+            weights.push_all(w);
+        }
+        attributes.push(("JOINTS_0", Json::U64(builder.shorts(joints.as_slice(), 4, "VEC4", Option::Some(ARRAY_BUFFER)) as u64)));
+        attributes.push(("WEIGHTS_0", Json::U64(builder.floats(weights.as_slice(), 4, "VEC4", Option::Some(ARRAY_BUFFER), false) as u64)));
+    }
+
+    let mut indices: Vec<u16> = Vec::new();
+    for t in o.triangles.iter() {
+        indices.push_all(t);
+    }
+    for q in o.quads.iter() {
+        indices.push_all(&[q[0], q[1], q[2], q[0], q[2], q[3]]);
+    }
+
+    let mut primitive = vec![
+        ("attributes", object(attributes)),
+        ("indices", Json::U64(builder.shorts(indices.as_slice(), 1, "SCALAR", Option::Some(ELEMENT_ARRAY_BUFFER)) as u64)),
+        ("mode", Json::U64(4)),
+    ];
+
+    if let Some(ref material) = o.material {
+        if let Some(m) = scene.materials.iter().position(|m| m.name == *material) {
+            primitive.push(("material", Json::U64(m as u64)));
+        }
+    }
+
+    object(vec![
+        ("name", Json::String(o.name.clone())),
+        ("primitives", Json::Array(vec![object(primitive)])),
+    ])
+}
+
+fn object(pairs: Vec<(&str, Json)>) -> Json {
+    let mut map = BTreeMap::new();
+    for (k, v) in pairs.into_iter() {
+        map.insert(String::from_str(k), v);
+    }
+    Json::Object(map)
+}
+
+fn numbers(v: &[f32]) -> Json {
+    Json::Array(v.iter().map(|v| Json::F64(*v as f64)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use cgmath;
+
+    use simplescene::{SimpleSceneFile, SimpleSceneObject, SimpleSceneAnimation, SimpleSceneChannel};
+    use simplescene::{SimpleSceneProperty, SimpleSceneInterpolation};
+
+    use super::{write_scene, validate};
+
+    fn channel(property: SimpleSceneProperty, times: Vec<f32>, values: Vec<[f32;4]>) -> SimpleSceneChannel {
+        SimpleSceneChannel {
+            target:         String::from_str("Box"),
+            property:       property,
+            interpolation:  SimpleSceneInterpolation::Linear,
+            times:          times,
+            values:         values,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut object = SimpleSceneObject::new();
+        object.name = String::from_str("Box");
+        object.typ = String::from_str("MESH");
+        object.vertices = vec![
+            cgmath::Vector3::new(0.0, 0.0, 0.0),
+            cgmath::Vector3::new(1.0, 0.0, 0.0),
+            cgmath::Vector3::new(1.0, 1.0, 0.0),
+            cgmath::Vector3::new(0.0, 1.0, 0.0),
+        ];
+        object.quads = vec![[0, 1, 2, 3]];
+        object.location = cgmath::Vector3::new(1.0, 2.0, 3.0);
+        object.rotation = cgmath::Quaternion::from_sv(-0.6, cgmath::Vector3::new(0.0, 0.8, 0.0));
+
+        let scene = SimpleSceneFile {
+            path:           Path::new("test"),
+            objects:        vec![Arc::new(Mutex::new(object))],
+            materials:      Vec::new(),
+            animations:     vec![SimpleSceneAnimation {
+                name:       String::from_str("Move"),
+                channels:   vec![
+                    channel(SimpleSceneProperty::Location, vec![0.0, 1.0], vec![[0.0, 0.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0]]),
+                    channel(SimpleSceneProperty::Rotation, Vec::new(), Vec::new()),
+                ],
+            }],
+        };
+
+        let path = ::std::os::tmpdir().join("gltfwriter_round_trip.gltf");
+        let path = path.as_str().unwrap();
+        write_scene(&scene, path);
+
+        assert_eq!(validate(&scene, path), Ok(()));
+    }
+}
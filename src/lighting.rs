@@ -0,0 +1,193 @@
+//! Lights and the lit forward shading path. Objects are shaded per pixel with Lambert diffuse
+//! and Blinn-Phong specular lighting by `shaders/lit.vert` and `shaders/lit.frag`, from up to
+//! `MAX_LIGHTS` directional, point and spot lights at a time.
+//!
+//! Each light goes to the shader as one 4x4 matrix, see `Light::pack` for the layout, which
+//! `shaders/lighting.glsl` unpacks. The lighting is worked out in world space, so the shader gets
+//! the model matrix and the normal matrix besides the usual combined matrix.
+
+use std::num::Float;
+
+use cgmath;
+use cgmath::{Vector, EuclideanVector, Matrix, FixedArray};
+
+use simplescene::{SimpleSceneFile, SimpleSceneMaterial};
+
+/// The number of lights the shader takes.
+pub const MAX_LIGHTS: usize = 4;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum LightKind {
+    /// light from far away coming from one direction, like the sun
+    Directional,
+    /// light shining from a point in every direction
+    Point,
+    /// light shining from a point in a cone
+    Spot,
+}
+
+#[derive(Copy, Clone)]
+pub struct Light {
+    pub kind:           LightKind,
+    /// where a point or spot light is, in world space
+    pub position:       cgmath::Vector3<f32>,
+    /// the direction the light travels in, for directional and spot lights
+    pub direction:      cgmath::Vector3<f32>,
+    pub color:          [f32;3],
+    pub intensity:      f32,
+    /// the distance at which a point or spot light has faded out, zero for no fading
+    pub range:          f32,
+    /// the angle from the middle of a spot light's cone where it starts to fade (in radians)
+    pub inner_angle:    f32,
+    /// the angle from the middle of a spot light's cone where it has faded out (in radians)
+    pub outer_angle:    f32,
+}
+
+impl Light {
+    pub fn directional(direction: cgmath::Vector3<f32>, color: [f32;3], intensity: f32) -> Light {
+        Light {
+            kind:           LightKind::Directional,
+            position:       cgmath::Vector3::new(0.0, 0.0, 0.0),
+            direction:      direction.normalize(),
+            color:          color,
+            intensity:      intensity,
+            range:          0.0,
+            inner_angle:    0.0,
+            outer_angle:    0.0,
+        }
+    }
+
+    pub fn point(position: cgmath::Vector3<f32>, color: [f32;3], intensity: f32, range: f32) -> Light {
+        Light {
+            kind:           LightKind::Point,
+            position:       position,
+            direction:      cgmath::Vector3::new(0.0, 0.0, -1.0),
+            color:          color,
+            intensity:      intensity,
+            range:          range,
+            inner_angle:    0.0,
+            outer_angle:    0.0,
+        }
+    }
+
+    pub fn spot(position: cgmath::Vector3<f32>, direction: cgmath::Vector3<f32>, color: [f32;3], intensity: f32,
+                range: f32, inner_angle: f32, outer_angle: f32) -> Light {
+        Light {
+            kind:           LightKind::Spot,
+            position:       position,
+            direction:      direction.normalize(),
+            color:          color,
+            intensity:      intensity,
+            range:          range,
+            inner_angle:    inner_angle,
+            outer_angle:    outer_angle,
+        }
+    }
+
+    /// Return the light packed into the columns of a matrix for the shader:
+    ///
+    /// * the position, and the kind (0 directional, 1 point, 2 spot)
+    /// * the direction, and the range
+    /// * the color times the intensity
+    /// * the cosines of the inner and outer angle
+    pub fn pack(&self) -> [[f32;4];4] {
+        let kind = match self.kind {
+            LightKind::Directional => 0.0,
+            LightKind::Point => 1.0,
+            LightKind::Spot => 2.0,
+        };
+        let c = self.color;
+        let i = self.intensity;
+
+        [
+            [self.position.x, self.position.y, self.position.z, kind],
+            [self.direction.x, self.direction.y, self.direction.z, self.range],
+            [c[0] * i, c[1] * i, c[2] * i, 0.0],
+            [self.inner_angle.cos(), self.outer_angle.cos(), 0.0, 0.0],
+        ]
+    }
+}
+
+/// The lights used to draw a frame.
+pub struct LightSet {
+    /// light coming from everywhere, so that the sides facing away from the lights are not black
+    pub ambient:        [f32;3],
+    /// only the first `MAX_LIGHTS` are used
+    pub lights:         Vec<Light>,
+}
+
+impl LightSet {
+    pub fn new() -> LightSet {
+        LightSet {
+            ambient:        [0.1, 0.1, 0.1],
+            lights:         Vec::new(),
+        }
+    }
+
+    /// Return a set with a point light for every lamp in a scene. The scenes only say where the
+    /// lamps are, so they all get the same white light.
+    pub fn from_scene(scene: &SimpleSceneFile, intensity: f32, range: f32) -> LightSet {
+        let mut set = LightSet::new();
+        for object in scene.objects.iter() {
+            let object = object.lock().unwrap();
+            if object.typ.as_slice() == "LAMP" {
+                let p = object.world_matrix().w.truncate();
+                set.lights.push(Light::point(p, [1.0, 1.0, 1.0], intensity, range));
+            }
+        }
+        set
+    }
+
+    /// Return the number of lights used and the packed lights, unused ones are all zero.
+    pub fn pack(&self) -> (i32, [[[f32;4];4]; MAX_LIGHTS]) {
+        let mut packed = [[[0.0; 4]; 4]; MAX_LIGHTS];
+        let count = self.lights.len().min(MAX_LIGHTS);
+        for i in 0..count {
+            packed[i] = self.lights[i].pack();
+        }
+        (count as i32, packed)
+    }
+}
+
+/// The surface properties the lit shader uses.
+#[derive(Copy, Clone)]
+pub struct LitMaterial {
+    /// multiplied by the vertex colors
+    pub diffuse:        [f32;3],
+    pub specular:       [f32;3],
+    /// the Blinn-Phong exponent, higher for smaller and sharper highlights
+    pub shininess:      f32,
+}
+
+impl LitMaterial {
+    pub fn new() -> LitMaterial {
+        LitMaterial {
+            diffuse:        [1.0, 1.0, 1.0],
+            specular:       [0.04, 0.04, 0.04],
+            shininess:      32.0,
+        }
+    }
+
+    /// Return a material close to a scene material. Metals have no diffuse color and a specular
+    /// color of the base color, other surfaces reflect a little white light. The roughness is
+    /// turned into the exponent of the usual Beckmann to Blinn-Phong approximation.
+    pub fn from_material(material: &SimpleSceneMaterial) -> LitMaterial {
+        let d = material.diffuse;
+        let m = material.metallic;
+        let alpha = (material.roughness * material.roughness).max(0.03);
+        let mix = |base: f32| 0.04 + (base - 0.04) * m;
+
+        LitMaterial {
+            diffuse:        [d[0] * (1.0 - m), d[1] * (1.0 - m), d[2] * (1.0 - m)],
+            specular:       [mix(d[0]), mix(d[1]), mix(d[2])],
+            shininess:      (2.0 / (alpha * alpha) - 2.0).max(1.0).min(1024.0),
+        }
+    }
+}
+
+/// Return the matrix that takes normals into world space with `model`: the inverse transpose of
+/// its upper 3x3 part, so that scaling an object does not bend its normals.
+pub fn normal_matrix(model: &cgmath::Matrix4<f32>) -> [[f32;3];3] {
+    let m = cgmath::Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+    m.invert().map(|i| i.transpose()).unwrap_or(m).into_fixed()
+}
@@ -7,6 +7,8 @@
 extern crate cgmath;
 extern crate glutin;
 extern crate glium;
+extern crate image;
+extern crate "rustc-serialize" as rustc_serialize;
 #[plugin]
 extern crate glium_macros;
 
@@ -26,10 +28,36 @@ use cgmath::{Point3, Vector3, Matrix4, Basis3, Matrix3, Quaternion, PerspectiveF
 use cgmath::ToMatrix4;
 
 use simplescene::SimpleSceneFile;
-use drawableobject::DrawableObject;
+use drawableobject::{DrawableObject, WireframeStyle};
+use shaders::ShaderLibrary;
+use camera::{Camera, CameraController, OrbitController, InputState};
+use bvh::BvhFile;
 
 pub mod simplescene;
+pub mod mesh;
+pub mod mikktspace;
+pub mod bounds;
+pub mod raycast;
+pub mod simplify;
+pub mod subdivide;
+pub mod collision;
+pub mod primitives;
+pub mod topology;
+pub mod batch;
 pub mod drawableobject;
+pub mod shaders;
+pub mod lighting;
+pub mod textures;
+pub mod camera;
+pub mod vcache;
+pub mod objwriter;
+pub mod objreader;
+pub mod gltf;
+pub mod gltfwriter;
+pub mod blend;
+pub mod stl;
+pub mod ply;
+pub mod bvh;
 
 /// Represents an attachment of one object to another.
 struct SkeletonObjectAttachment {
@@ -45,18 +73,60 @@ pub struct Vertex {
     color:      [f32; 3],
 }
 
+/// The vertex format for meshes that carry everything needed for lighting and normal mapping.
+/// The `w` of the tangent is the bitangent sign, `bitangent = cross(normal, tangent.xyz) * w`.
+#[vertex_format]
+#[derive(Copy)]
+pub struct VertexExt {
+    position:   [f32; 3],
+    color:      [f32; 3],
+    normal:     [f32; 3],
+    uv:         [f32; 2],
+    tangent:    [f32; 4],
+}
+
 #[uniforms]
 pub struct Uniform {
     matrix:     [[f32; 4]; 4],
 }
 
+/// The uniforms of the lit shaders, see `lighting`.
+#[uniforms]
+pub struct LitUniform<'a> {
+    /// view, projection and model together
+    matrix:             [[f32; 4]; 4],
+    model:              [[f32; 4]; 4],
+    normal_matrix:      [[f32; 3]; 3],
+    camera_position:    [f32; 3],
+    ambient:            [f32; 3],
+    diffuse:            [f32; 3],
+    specular:           [f32; 3],
+    shininess:          f32,
+    light_count:        i32,
+    light0:             [[f32; 4]; 4],
+    light1:             [[f32; 4]; 4],
+    light2:             [[f32; 4]; 4],
+    light3:             [[f32; 4]; 4],
+    /// multiplied by the diffuse color
+    texture:            glium::uniforms::Sampler<'a, glium::texture::Texture2d>,
+}
+
+#[uniforms]
+pub struct WireUniform {
+    matrix:     [[f32; 4]; 4],
+    color:      [f32; 3],
+    bias:       f32,
+}
+
 /// Contains state data for game calculations and GL data rendering. The
 /// GL data must be updated from the game state to synchronize the rendering
 /// with the game state.
 struct SkeletonObject {
+    /// used to match the object to a joint of captured motion
+    name:           String,
     /// local location
     l:              Vector3<f64>,
-    /// local rotation
+    /// local rotation (euler angles in radians applied X then Y then Z)
     r:              Vector3<f64>,
     /// local scale
     s:              Vector3<f64>,
@@ -66,6 +136,39 @@ struct SkeletonObject {
     drawable:       DrawableObject,
 }
 
+impl SkeletonObject {
+    /// Pose this object and its attachments from a frame of captured motion. Objects are matched
+    /// to joints by name and the ones without a matching joint are left alone (but their
+    /// attachments are still posed).
+    fn apply_motion(&mut self, bvh: &BvhFile, frame: usize) {
+        self.apply_joint(bvh, frame, true);
+    }
+
+    fn apply_joint(&mut self, bvh: &BvhFile, frame: usize, root: bool) {
+        if let Some(joint) = bvh.find(self.name.as_slice()) {
+            let (location, rotation) = bvh.pose(frame, joint);
+            let r = bvh::to_euler(&rotation);
+            // An attached object already hangs off of its parent by the joint
+            // offset, so only what the position channels add is left over.
+            let offset = if root { Vector3::new(0.0, 0.0, 0.0) } else { bvh.joints[joint].offset };
+            self.l = Vector3::new(
+                (location.x - offset.x) as f64,
+                (location.y - offset.y) as f64,
+                (location.z - offset.z) as f64
+            );
+            self.r = Vector3::new(r.x as f64, r.y as f64, r.z as f64);
+        }
+
+        for attachment in self.attachment.iter_mut() {
+            if let Some(joint) = bvh.find(attachment.sobject.name.as_slice()) {
+                let offset = bvh.joints[joint].offset;
+                attachment.l = Vector3::new(offset.x as f64, offset.y as f64, offset.z as f64);
+            }
+            attachment.sobject.apply_joint(bvh, frame, false);
+        }
+    }
+}
+
 fn main() {
     use glium::DisplayBuild;
     use glium::Surface;
@@ -80,40 +183,23 @@ fn main() {
         .with_title(format!("Hello World"))
         .build_glium().unwrap();
 
-    let program = Arc::new(glium::Program::from_source(&display,
-        // vertex shader
-        "   #version 110
-
-            uniform mat4 matrix;
-
-            attribute vec3 position;
-            attribute vec3 color;
-
-            varying vec3 v_color;
-
-            void main() {
-                gl_Position = matrix * vec4(position, 1.0);
-                v_color = color;
-            }
-        ",
-
-        // fragment shader
-        "   #version 110
-            varying vec3 v_color;
-
-            void main() {
-                gl_FragColor = vec4(v_color, 1.0);
-            }
-        ",
-
-        // optional geometry shader
-        None
-    ).unwrap());
+    let mut shaders = ShaderLibrary::new(&display, "shaders");
+    let program = match shaders.get("basic") {
+        Ok(program) => program,
+        Err(e) => panic!("{}", e),
+    };
 
     //let mut objects = DrawableObject::from_obj(&display, "test.obj", program.clone());
 
     let scene = SimpleSceneFile::from_file("data.txt");
     let mut dobject = DrawableObject::from_simplescene(&display, &scene, "Grape", program.clone()).unwrap();
+    let wire_program = DrawableObject::wireframe_program(&display);
+    let wire_style: WireframeStyle = std::default::Default::default();
+
+    let mut camera = Camera::new();
+    camera.set_viewport(360, 360);
+    let mut orbit = OrbitController::new(cgmath::Vector3::new(0.0, 0.0, 0.0), 5.0);
+    let mut input = InputState::new();
 
     let mut rv = cgmath::Vector3::new(0.0, 1.0, 0.0);
 
@@ -127,25 +213,32 @@ fn main() {
     loop {
         for event in display.poll_events() {
             //println!("event {:?}", event);
+            input.handle(&event);
+            if let glutin::Event::Resized(width, height) = event {
+                camera.set_viewport(width, height);
+            }
+        }
+
+        orbit.update(&mut camera, &input.take(0.017));
+        dobject.set_model_matrix(q3a.to_matrix4());
+        dobject.update_matrix(&camera);
+
+        for (name, result) in shaders.reload_changed().into_iter() {
+            match result {
+                Ok(program) => if name.as_slice() == "basic" { dobject.set_program(program) },
+                Err(e) => println!("{}", e),
+            }
         }
 
         let mut target = display.draw();
 
-        target.clear_all((0.0, 0.0, 0.0, 0.0), 0.0, 0);
-        dobject.draw(&mut target);
+        target.clear_all((0.0, 0.0, 0.0, 0.0), 1.0, 0);
+        dobject.draw_wireframe(&mut target, &wire_program, &wire_style);
         target.finish();
 
         // Will rotate q3a by q3.
         //q3a = q3a.mul_q(&q3b).normalize();
 
-        let r = q3a.to_matrix4();
-
-        let m4: Matrix4<f32> = Matrix4::identity();
-        let per = cgmath::perspective(Deg { s: 45.0 }, 1.0, 0.1, 10.0);
-        let m4 = m4 * Matrix4::from_translation(&Vector3::new(0.0, 0.0, -5.0));
-
-        dobject.set_uniform_matrix((per * m4 * r).into_fixed());
-
         std::old_io::timer::sleep(Duration::milliseconds(17));
     }
 }
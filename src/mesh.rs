@@ -0,0 +1,616 @@
+//! The mesh is the CPU side form of an object's geometry. It holds the same data as the geometry
+//! part of a `SimpleSceneObject` but leaves out the name, transform and hierarchy so that it can
+//! be worked on freely (generating data, cleaning it up) before it is put back into an object or
+//! uploaded to the graphics card.
+//!
+//! Faces are triangles and quads just like in `SimpleSceneObject`, and the quads are kept as
+//! quads by everything here so that they survive a round trip.
+
+use std::num::Float;
+use std::collections::HashMap;
+
+use cgmath;
+use cgmath::{Vector, EuclideanVector};
+
+use mikktspace;
+use simplescene::SimpleSceneObject;
+
+/// The geometry of an object, with one index shared by all the per-vertex data.
+pub struct Mesh {
+    pub positions:      Vec<cgmath::Vector3<f32>>,
+    /// per-vertex normals, empty if there are none
+    pub normals:        Vec<cgmath::Vector3<f32>>,
+    /// per-vertex texture coordinates, empty if there are none
+    pub uvs:            Vec<cgmath::Vector2<f32>>,
+    /// per-vertex tangents with the bitangent sign in `w`, empty until `generate_tangents`
+    pub tangents:       Vec<[f32;4]>,
+    /// per-vertex colors, empty if there are none
+    pub colors:         Vec<cgmath::Vector3<f32>>,
+    /// per-vertex joint indices, empty if the mesh is not skinned
+    pub joints:         Vec<[u16;4]>,
+    /// per-vertex joint weights that go with `joints`
+    pub weights:        Vec<[f32;4]>,
+    pub triangles:      Vec<[u16;3]>,
+    pub quads:          Vec<[u16;4]>,
+}
+
+impl Mesh {
+    /// Return an empty mesh.
+    pub fn new() -> Mesh {
+        Mesh {
+            positions:  Vec::new(),
+            normals:    Vec::new(),
+            uvs:        Vec::new(),
+            tangents:   Vec::new(),
+            colors:     Vec::new(),
+            joints:     Vec::new(),
+            weights:    Vec::new(),
+            triangles:  Vec::new(),
+            quads:      Vec::new(),
+        }
+    }
+
+    /// Return a copy of the geometry of an object.
+    pub fn from_simplescene(object: &SimpleSceneObject) -> Mesh {
+        Mesh {
+            positions:  object.vertices.clone(),
+            normals:    object.normals.clone(),
+            uvs:        object.uvs.clone(),
+            tangents:   Vec::new(),
+            colors:     object.colors.clone(),
+            joints:     object.joints.clone(),
+            weights:    object.weights.clone(),
+            triangles:  object.triangles.clone(),
+            quads:      object.quads.clone(),
+        }
+    }
+
+    /// Replace the geometry of an object with this mesh, everything else is left alone. Objects
+    /// have no tangents so those are not carried over.
+    pub fn to_simplescene(&self, object: &mut SimpleSceneObject) {
+        object.vertices = self.positions.clone();
+        object.normals = self.normals.clone();
+        object.uvs = self.uvs.clone();
+        object.colors = self.colors.clone();
+        object.joints = self.joints.clone();
+        object.weights = self.weights.clone();
+        object.triangles = self.triangles.clone();
+        object.quads = self.quads.clone();
+        object.update_bounds();
+    }
+
+    /// Return the number of faces, triangles first and then quads.
+    pub fn face_count(&self) -> usize {
+        self.triangles.len() + self.quads.len()
+    }
+
+    /// Return the vertex indices of a face, counting triangles first and then quads.
+    pub fn face(&self, f: usize) -> &[u16] {
+        if f < self.triangles.len() {
+            self.triangles[f].as_slice()
+        } else {
+            self.quads[f - self.triangles.len()].as_slice()
+        }
+    }
+
+    fn set_corner(&mut self, f: usize, c: usize, v: u16) {
+        if f < self.triangles.len() {
+            self.triangles[f][c] = v;
+        } else {
+            let f = f - self.triangles.len();
+            self.quads[f][c] = v;
+        }
+    }
+
+    /// Return the unit normal of a face, or a zero vector for a face without area. For quads the
+    /// diagonals are used so that quads which are not quite planar still get a sensible normal.
+    pub fn face_normal(&self, face: &[u16]) -> cgmath::Vector3<f32> {
+        let p = |i: usize| self.positions[face[i] as usize];
+
+        let n = if face.len() == 4 {
+            p(2).sub_v(&p(0)).cross(&p(3).sub_v(&p(1)))
+        } else {
+            p(1).sub_v(&p(0)).cross(&p(2).sub_v(&p(0)))
+        };
+
+        if n.length() > 0.0 { n.normalize() } else { n }
+    }
+
+    /// Return the angle of a face at one of its corners, used to weight the face normals so
+    /// that how a face happens to be split into triangles does not change the result.
+    fn corner_angle(&self, face: &[u16], c: usize) -> f32 {
+        let n = face.len();
+        let v = self.positions[face[c] as usize];
+        let a = self.positions[face[(c + 1) % n] as usize].sub_v(&v);
+        let b = self.positions[face[(c + n - 1) % n] as usize].sub_v(&v);
+
+        if a.length() == 0.0 || b.length() == 0.0 {
+            return 0.0;
+        }
+
+        let cos = a.normalize().dot(&b.normalize());
+        (if cos > 1.0 { 1.0 } else if cos < -1.0 { -1.0 } else { cos }).acos()
+    }
+
+    /// Return the edges of the faces, each one once, as pairs of vertex indices. Quads give their
+    /// four sides and not the diagonal. Edges are matched by the position of their ends, so an
+    /// edge along a seam is not returned twice.
+    ///
+    /// With a `feature_angle` only the edges where the faces meet at more than that angle, and
+    /// the edges on the boundary of the mesh, are returned.
+    pub fn edges(&self, feature_angle: Option<cgmath::Rad<f32>>) -> Vec<[u16;2]> {
+        let mut found: HashMap<((u32, u32, u32), (u32, u32, u32)), usize> = HashMap::new();
+        // the edges in the order they were found with the faces on either side
+        let mut edges: Vec<([u16;2], Vec<usize>)> = Vec::new();
+
+        for f in 0..self.face_count() {
+            let face = self.face(f);
+            for i in 0..face.len() {
+                let (a, b) = (face[i], face[(i + 1) % face.len()]);
+                let ka = position_key(&self.positions[a as usize]);
+                let kb = position_key(&self.positions[b as usize]);
+                let key = if ka < kb { (ka, kb) } else { (kb, ka) };
+
+                match found.get(&key).map(|e| *e) {
+                    Some(e) => edges[e].1.push(f),
+                    None => {
+                        found.insert(key, edges.len());
+                        edges.push(([a, b], vec![f]));
+                    },
+                }
+            }
+        }
+
+        let limit = match feature_angle {
+            Some(angle) => angle.s.cos(),
+            None => return edges.into_iter().map(|e| e.0).collect(),
+        };
+
+        edges.into_iter().filter(|e| {
+            e.1.len() != 2 || {
+                let a = self.face_normal(self.face(e.1[0]));
+                let b = self.face_normal(self.face(e.1[1]));
+                a.dot(&b) < limit
+            }
+        }).map(|e| e.0).collect()
+    }
+
+    /// Add a copy of a vertex with all of its per-vertex data and return the new index.
+    pub fn duplicate_vertex(&mut self, v: u16) -> u16 {
+        let count = self.positions.len();
+        let i = v as usize;
+
+        if count >= 65536 {
+            panic!("mesh: too many vertices for 16-bit indices");
+        }
+
+        if self.normals.len() == count {
+            let n = self.normals[i];
+            self.normals.push(n);
+        }
+        if self.uvs.len() == count {
+            let t = self.uvs[i];
+            self.uvs.push(t);
+        }
+        if self.tangents.len() == count {
+            let t = self.tangents[i];
+            self.tangents.push(t);
+        }
+        if self.colors.len() == count {
+            let c = self.colors[i];
+            self.colors.push(c);
+        }
+        if self.joints.len() == count {
+            let j = self.joints[i];
+            self.joints.push(j);
+        }
+        if self.weights.len() == count {
+            let w = self.weights[i];
+            self.weights.push(w);
+        }
+        let p = self.positions[i];
+        self.positions.push(p);
+
+        count as u16
+    }
+
+    /// Give every face its own normal. Vertices are only shared between faces that lie in the
+    /// same plane, the others are split.
+    pub fn flat_normals(&mut self) {
+        self.generate_normals(Option::Some(0.0));
+    }
+
+    /// Give every vertex the average of the normals of the faces around it. No vertices are
+    /// split, and vertices in the same place (like at a texture seam) get the same normal.
+    pub fn smooth_normals(&mut self) {
+        self.generate_normals(Option::None);
+    }
+
+    /// Smooth the normals across edges where the faces meet at less than `angle` and keep the
+    /// edges where they meet at a sharper angle hard, splitting the vertices along them.
+    pub fn auto_smooth(&mut self, angle: cgmath::Rad<f32>) {
+        self.generate_normals(Option::Some(angle.s));
+    }
+
+    fn generate_normals(&mut self, angle: Option<f32>) {
+        let count = self.face_count();
+        let face_normals: Vec<cgmath::Vector3<f32>> = (0..count).map(|f| self.face_normal(self.face(f))).collect();
+
+        // Group the face corners by the position of their vertex. Going by the position instead
+        // of the index means vertices split for other reasons do not cause a crease.
+        let mut places: HashMap<(u32, u32, u32), usize> = HashMap::new();
+        let mut around: Vec<Vec<(usize, usize)>> = Vec::new();
+        for f in 0..count {
+            for (c, v) in self.face(f).iter().enumerate() {
+                let key = position_key(&self.positions[*v as usize]);
+                let place = match places.get(&key) {
+                    Some(place) => *place,
+                    None => {
+                        around.push(Vec::new());
+                        around.len() - 1
+                    },
+                };
+                places.insert(key, place);
+                around[place].push((f, c));
+            }
+        }
+
+        // a little slack so faces in the same plane still count as such with an angle of zero
+        let limit = angle.map(|a| a.cos() - 0.00001);
+
+        let mut corners: Vec<Vec<cgmath::Vector3<f32>>> = (0..count).map(|f| {
+            (0..self.face(f).len()).map(|_| cgmath::Vector3::new(0.0, 0.0, 0.0)).collect()
+        }).collect();
+
+        for group in around.iter() {
+            for &(f, c) in group.iter() {
+                let mut sum = cgmath::Vector3::new(0.0, 0.0, 0.0);
+                for &(g, d) in group.iter() {
+                    let smooth = f == g || match limit {
+                        Some(limit) => face_normals[f].dot(&face_normals[g]) >= limit,
+                        None => true,
+                    };
+                    if smooth {
+                        sum = sum.add_v(&face_normals[g].mul_s(self.corner_angle(self.face(g), d)));
+                    }
+                }
+                corners[f][c] = if sum.length() > 0.0 { sum.normalize() } else { face_normals[f] };
+            }
+        }
+
+        // Hand the corner normals to the vertices, splitting a vertex when its corners disagree.
+        let original = self.positions.len();
+        let mut assigned: Vec<bool> = (0..original).map(|_| false).collect();
+        let mut copies: HashMap<u16, Vec<u16>> = HashMap::new();
+        self.normals = (0..original).map(|_| cgmath::Vector3::new(0.0, 0.0, 0.0)).collect();
+
+        for f in 0..count {
+            for c in 0..corners[f].len() {
+                let v = self.face(f)[c];
+                let n = corners[f][c];
+
+                if !assigned[v as usize] {
+                    assigned[v as usize] = true;
+                    self.normals[v as usize] = n;
+                    continue;
+                }
+
+                if same_normal(&self.normals[v as usize], &n) {
+                    continue;
+                }
+
+                let found = copies.get(&v).and_then(|list| {
+                    list.iter().find(|i| same_normal(&self.normals[**i as usize], &n)).map(|i| *i)
+                });
+
+                let copy = match found {
+                    Some(copy) => copy,
+                    None => {
+                        let copy = self.duplicate_vertex(v);
+                        self.normals[copy as usize] = n;
+                        if !copies.contains_key(&v) {
+                            copies.insert(v, Vec::new());
+                        }
+                        copies.get_mut(&v).unwrap().push(copy);
+                        copy
+                    },
+                };
+
+                self.set_corner(f, c, copy);
+            }
+        }
+    }
+
+    /// Generate a tangent for every vertex with MikkTSpace (see the `mikktspace` module), so
+    /// that normal maps baked in Blender come out right. The bitangent is
+    /// `cross(normal, tangent) * w`. A vertex whose face corners get different tangents, like
+    /// one on a mirror seam, is split. Returns false and leaves the mesh as it is if it does not
+    /// have a normal and a texture coordinate for every vertex.
+    pub fn generate_tangents(&mut self) -> bool {
+        let count = self.positions.len();
+
+        if self.normals.len() != count || self.uvs.len() != count {
+            return false;
+        }
+
+        let corners = mikktspace::corner_tangents(self);
+        let mut set: Vec<bool> = (0..count).map(|_| false).collect();
+        // the copies made of a vertex for corners with a different tangent
+        let mut copies: HashMap<u16, Vec<u16>> = HashMap::new();
+        self.tangents = (0..count).map(|_| [0.0, 0.0, 0.0, 1.0]).collect();
+
+        for f in 0..corners.len() {
+            for c in 0..corners[f].len() {
+                let tangent = corners[f][c];
+                let v = self.face(f)[c];
+
+                if !set[v as usize] {
+                    set[v as usize] = true;
+                    self.tangents[v as usize] = tangent;
+                    continue;
+                }
+                if self.tangents[v as usize] == tangent {
+                    continue;
+                }
+
+                let found = copies.get(&v).and_then(|list| {
+                    list.iter().find(|i| self.tangents[**i as usize] == tangent).map(|i| *i)
+                });
+
+                let copy = match found {
+                    Some(copy) => copy,
+                    None => {
+                        let copy = self.duplicate_vertex(v);
+                        self.tangents[copy as usize] = tangent;
+                        if !copies.contains_key(&v) {
+                            copies.insert(v, Vec::new());
+                        }
+                        copies.get_mut(&v).unwrap().push(copy);
+                        copy
+                    },
+                };
+
+                self.set_corner(f, c, copy);
+            }
+        }
+
+        true
+    }
+
+    /// Run all of the cleanup passes: weld the vertices within `epsilon`, remove the degenerate
+    /// faces and then the vertices nothing uses anymore.
+    pub fn cleanup(&mut self, epsilon: f32) {
+        self.weld(epsilon);
+        self.remove_degenerates();
+        self.compact();
+    }
+
+    /// Merge vertices closer than `epsilon` to each other, an `epsilon` of zero only merges
+    /// vertices in exactly the same place. Vertices are only merged when the rest of their data
+    /// matches too, so seams in the normals, texture coordinates and so on are kept. The merged
+    /// away vertices are left unused, `compact` gets rid of them. Returns how many were merged.
+    pub fn weld(&mut self, epsilon: f32) -> usize {
+        let count = self.positions.len();
+        let reach: i64 = if epsilon > 0.0 { 1 } else { 0 };
+        let mut grid: HashMap<(i64, i64, i64), Vec<u16>> = HashMap::new();
+        let mut map: Vec<u16> = Vec::with_capacity(count);
+        let mut welded = 0;
+
+        for v in 0..count {
+            let cell = cell_key(&self.positions[v], epsilon);
+            let mut found: Option<u16> = Option::None;
+
+            // a vertex can be within reach of one in a neighbouring cell
+            'search: for dx in -reach..reach + 1 {
+                for dy in -reach..reach + 1 {
+                    for dz in -reach..reach + 1 {
+                        if let Some(list) = grid.get(&(cell.0 + dx, cell.1 + dy, cell.2 + dz)) {
+                            for u in list.iter() {
+                                if self.can_weld(*u as usize, v, epsilon) {
+                                    found = Option::Some(*u);
+                                    break 'search;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            match found {
+                Some(u) => {
+                    map.push(u);
+                    welded += 1;
+                },
+                None => {
+                    map.push(v as u16);
+                    if !grid.contains_key(&cell) {
+                        grid.insert(cell, Vec::new());
+                    }
+                    grid.get_mut(&cell).unwrap().push(v as u16);
+                },
+            }
+        }
+
+        self.remap_faces(map.as_slice());
+        welded
+    }
+
+    fn can_weld(&self, a: usize, b: usize, epsilon: f32) -> bool {
+        let count = self.positions.len();
+        let close = |x: f32, y: f32| (x - y).abs() <= 0.00001;
+
+        if self.positions[a].sub_v(&self.positions[b]).length() > epsilon {
+            return false;
+        }
+        if self.normals.len() == count && self.normals[a].dot(&self.normals[b]) <= 0.9999 {
+            return false;
+        }
+        if self.uvs.len() == count && !(close(self.uvs[a].x, self.uvs[b].x) && close(self.uvs[a].y, self.uvs[b].y)) {
+            return false;
+        }
+        if self.colors.len() == count && self.colors[a].sub_v(&self.colors[b]).length() > 0.00001 {
+            return false;
+        }
+        if self.tangents.len() == count && (0..4).any(|i| !close(self.tangents[a][i], self.tangents[b][i])) {
+            return false;
+        }
+        if self.joints.len() == count && self.joints[a] != self.joints[b] {
+            return false;
+        }
+        if self.weights.len() == count && (0..4).any(|i| !close(self.weights[a][i], self.weights[b][i])) {
+            return false;
+        }
+
+        true
+    }
+
+    /// Point the faces at new vertices, `map` gives the new index for every old one.
+    pub fn remap_faces(&mut self, map: &[u16]) {
+        for t in self.triangles.iter_mut() {
+            for i in t.iter_mut() {
+                *i = map[*i as usize];
+            }
+        }
+        for q in self.quads.iter_mut() {
+            for i in q.iter_mut() {
+                *i = map[*i as usize];
+            }
+        }
+    }
+
+    /// Give every distinct position a place, so that vertices split only because of their other
+    /// data can be treated as one. Returns the place of every vertex and the position of every
+    /// place, in the order the places are first used.
+    pub fn places(&self) -> (Vec<usize>, Vec<cgmath::Vector3<f32>>) {
+        let mut lookup: HashMap<(u32, u32, u32), usize> = HashMap::new();
+        let mut places: Vec<cgmath::Vector3<f32>> = Vec::new();
+        let place: Vec<usize> = self.positions.iter().map(|p| {
+            let key = position_key(p);
+            match lookup.get(&key).map(|i| *i) {
+                Some(i) => i,
+                None => {
+                    places.push(*p);
+                    lookup.insert(key, places.len() - 1);
+                    places.len() - 1
+                },
+            }
+        }).collect();
+
+        (place, places)
+    }
+
+    /// Remove the faces that have no area, either because a vertex is used twice or because
+    /// the corners are in a line. A quad with one corner used twice becomes a triangle. Returns
+    /// how many faces were removed.
+    pub fn remove_degenerates(&mut self) -> usize {
+        let before = self.face_count();
+        let mut triangles: Vec<[u16;3]> = Vec::with_capacity(self.triangles.len());
+        let mut quads: Vec<[u16;4]> = Vec::with_capacity(self.quads.len());
+
+        for t in self.triangles.iter() {
+            if self.has_area(t.as_slice()) {
+                triangles.push(*t);
+            }
+        }
+
+        for q in self.quads.iter() {
+            let mut corners: Vec<u16> = Vec::with_capacity(4);
+            for c in 0..4 {
+                if q[c] != q[(c + 1) % 4] {
+                    corners.push(q[c]);
+                }
+            }
+
+            if corners.len() == 4 && self.has_area(q.as_slice()) {
+                quads.push(*q);
+            } else if corners.len() == 3 && self.has_area(corners.as_slice()) {
+                triangles.push([corners[0], corners[1], corners[2]]);
+            }
+        }
+
+        self.triangles = triangles;
+        self.quads = quads;
+
+        // a quad turned triangle is changed rather than removed
+        before - self.face_count()
+    }
+
+    fn has_area(&self, face: &[u16]) -> bool {
+        for a in 0..face.len() {
+            for b in a + 1..face.len() {
+                if face[a] == face[b] {
+                    return false;
+                }
+            }
+        }
+
+        let p = |i: usize| self.positions[face[i] as usize];
+        let mut area = p(1).sub_v(&p(0)).cross(&p(2).sub_v(&p(0))).length();
+        if face.len() == 4 {
+            area += p(2).sub_v(&p(0)).cross(&p(3).sub_v(&p(0))).length();
+        }
+
+        area > 0.0
+    }
+
+    /// Remove the vertices no face uses and renumber the rest. Returns how many were removed.
+    pub fn compact(&mut self) -> usize {
+        let count = self.positions.len();
+        let mut used: Vec<bool> = (0..count).map(|_| false).collect();
+
+        for f in 0..self.face_count() {
+            for v in self.face(f).iter() {
+                used[*v as usize] = true;
+            }
+        }
+
+        let mut map: Vec<u16> = Vec::with_capacity(count);
+        let mut next = 0u16;
+        for v in 0..count {
+            map.push(next);
+            if used[v] {
+                next += 1;
+            }
+        }
+
+        fn keep<T: Copy>(list: &mut Vec<T>, used: &[bool]) {
+            if list.len() == used.len() {
+                *list = list.iter().zip(used.iter()).filter(|p| *p.1).map(|p| *p.0).collect();
+            }
+        }
+
+        keep(&mut self.normals, used.as_slice());
+        keep(&mut self.uvs, used.as_slice());
+        keep(&mut self.tangents, used.as_slice());
+        keep(&mut self.colors, used.as_slice());
+        keep(&mut self.joints, used.as_slice());
+        keep(&mut self.weights, used.as_slice());
+        keep(&mut self.positions, used.as_slice());
+
+        self.remap_faces(map.as_slice());
+        count - next as usize
+    }
+
+}
+
+/// Return a key that is the same for positions in exactly the same place.
+pub fn position_key(v: &cgmath::Vector3<f32>) -> (u32, u32, u32) {
+    use std::mem::transmute;
+
+    // get rid of negative zero so that it matches positive zero
+    let bits = |f: f32| unsafe { transmute::<f32, u32>(f + 0.0) };
+    (bits(v.x), bits(v.y), bits(v.z))
+}
+
+fn cell_key(v: &cgmath::Vector3<f32>, epsilon: f32) -> (i64, i64, i64) {
+    if epsilon > 0.0 {
+        ((v.x / epsilon).floor() as i64, (v.y / epsilon).floor() as i64, (v.z / epsilon).floor() as i64)
+    } else {
+        let (x, y, z) = position_key(v);
+        (x as i64, y as i64, z as i64)
+    }
+}
+
+fn same_normal(a: &cgmath::Vector3<f32>, b: &cgmath::Vector3<f32>) -> bool {
+    a.dot(b) > 0.9999
+}
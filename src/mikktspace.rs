@@ -0,0 +1,552 @@
+//! A port of MikkTSpace by Morten S. Mikkelsen (`mikktspace.c`), the tangent space Blender and
+//! most other tools bake normal maps in. A normal map only comes out right with the tangents it
+//! was baked against, so this follows the reference step by step, down to the way quads are split
+//! and the order the triangles are grouped in.
+//!
+//! In short: vertices with the same position, normal and texture coordinate count as one. Every
+//! triangle gets a tangent and a bitangent from its texture mapping. The triangles around each
+//! vertex are put into groups that agree on whether the mapping is mirrored, walking from
+//! triangle to triangle across the edges at the vertex. Each triangle of a group then gets the
+//! average of the triangles in the group whose tangents do not point the opposite way,
+//! projected into the plane of the normal and weighted by the angle of their corner. Degenerate
+//! triangles take the tangent space of a good triangle at the same vertex.
+//!
+//! Only the tangent and the sign of the bitangent are kept (what the reference calls the basic
+//! tangent space), the bitangent is `cross(normal, tangent) * sign`.
+
+use std::collections::HashMap;
+use std::iter::repeat;
+use std::num::Float;
+
+use cgmath;
+use cgmath::{Vector, EuclideanVector};
+
+use mesh::{Mesh, position_key};
+
+/// Anything closer to zero than the smallest normal float counts as zero, like in the reference.
+const FLT_MIN: f32 = 1.17549435e-38;
+
+/// The cosine of the angle between the tangents of two triangles above which they are averaged.
+/// The reference takes the angle as a parameter and Blender leaves it at 180 degrees.
+const THRESHOLD_COS: f32 = -1.0;
+
+#[derive(Copy, Clone)]
+struct TriInfo {
+    /// the triangle across each edge, edge `i` going from corner `i` to the next
+    neighbors:          [Option<usize>; 3],
+    /// the group of each corner
+    groups:             [Option<usize>; 3],
+    /// the tangent and bitangent of the texture mapping, turned around when it is mirrored
+    os:                 cgmath::Vector3<f32>,
+    ot:                 cgmath::Vector3<f32>,
+    mag_s:              f32,
+    mag_t:              f32,
+    /// the face the triangle is part of and the corners of the face it uses
+    face:               usize,
+    corners:            [usize; 3],
+    /// the texture mapping is not mirrored
+    preserving:         bool,
+    /// the texture mapping has no area, so the triangle can join a group of either orientation
+    group_with_any:     bool,
+    degenerate:         bool,
+    /// the triangle is half of a quad whose other half is degenerate
+    quad_one_degenerate: bool,
+}
+
+#[derive(Copy, Clone)]
+struct TSpace {
+    os:                 cgmath::Vector3<f32>,
+    mag_s:              f32,
+    ot:                 cgmath::Vector3<f32>,
+    mag_t:              f32,
+    /// how many triangles the face corner got its tangent space from, a quad corner on the
+    /// diagonal gets one from each half
+    counter:            usize,
+    orient:             bool,
+}
+
+/// The triangles around a vertex whose mappings agree on being mirrored or not.
+struct Group {
+    vertex:             usize,
+    preserving:         bool,
+    triangles:          Vec<usize>,
+}
+
+/// Return the tangent of every corner of every face of a mesh with the sign of the bitangent in
+/// `w`, in the order of `Mesh::face`. The mesh needs a normal and a texture coordinate for every
+/// vertex.
+pub fn corner_tangents(mesh: &Mesh) -> Vec<Vec<[f32;4]>> {
+    // Vertices the same in everything the tangents are worked out from are one vertex here.
+    let mut lookup: HashMap<((u32, u32, u32), (u32, u32, u32), (u32, u32, u32)), usize> = HashMap::new();
+    let mut positions: Vec<cgmath::Vector3<f32>> = Vec::new();
+    let mut normals: Vec<cgmath::Vector3<f32>> = Vec::new();
+    let mut uvs: Vec<cgmath::Vector2<f32>> = Vec::new();
+    let welded: Vec<usize> = (0..mesh.positions.len()).map(|v| {
+        let t = mesh.uvs[v];
+        let key = (position_key(&mesh.positions[v]), position_key(&mesh.normals[v]),
+                   position_key(&cgmath::Vector3::new(t.x, t.y, 0.0)));
+        match lookup.get(&key).map(|i| *i) {
+            Some(i) => i,
+            None => {
+                positions.push(mesh.positions[v]);
+                normals.push(mesh.normals[v]);
+                uvs.push(t);
+                lookup.insert(key, positions.len() - 1);
+                positions.len() - 1
+            },
+        }
+    }).collect();
+
+    // Split the quads along the diagonal that is shorter in texture space, or in space when
+    // those are the same length.
+    let mut triangles: Vec<[usize;3]> = Vec::new();
+    let mut infos: Vec<TriInfo> = Vec::new();
+    let mut offsets: Vec<usize> = Vec::with_capacity(mesh.face_count());
+    let mut total = 0;
+
+    for f in 0..mesh.face_count() {
+        let face = mesh.face(f);
+        offsets.push(total);
+        total += face.len();
+
+        let id = |c: usize| welded[face[c] as usize];
+        let splits: Vec<[usize;3]> = if face.len() == 3 {
+            vec![[0, 1, 2]]
+        } else {
+            let d02 = uvs[id(2)].sub_v(&uvs[id(0)]).length2();
+            let d13 = uvs[id(3)].sub_v(&uvs[id(1)]).length2();
+            let diagonal_02 = if d02 < d13 {
+                true
+            } else if d13 < d02 {
+                false
+            } else {
+                let d02 = positions[id(2)].sub_v(&positions[id(0)]).length2();
+                let d13 = positions[id(3)].sub_v(&positions[id(1)]).length2();
+                !(d13 < d02)
+            };
+            if diagonal_02 { vec![[0, 1, 2], [0, 2, 3]] } else { vec![[0, 1, 3], [1, 2, 3]] }
+        };
+
+        for c in splits.iter() {
+            triangles.push([id(c[0]), id(c[1]), id(c[2])]);
+            infos.push(TriInfo {
+                neighbors:              [Option::None; 3],
+                groups:                 [Option::None; 3],
+                os:                     cgmath::Vector3::new(0.0, 0.0, 0.0),
+                ot:                     cgmath::Vector3::new(0.0, 0.0, 0.0),
+                mag_s:                  0.0,
+                mag_t:                  0.0,
+                face:                   f,
+                corners:                *c,
+                preserving:             false,
+                group_with_any:         true,
+                degenerate:             false,
+                quad_one_degenerate:    false,
+            });
+        }
+    }
+
+    // Triangles with two corners in the same place are left out until the end, and the other
+    // half of a quad with one of them is marked.
+    for t in 0..triangles.len() {
+        let p = |i: usize| positions[triangles[t][i]];
+        infos[t].degenerate = p(0) == p(1) || p(0) == p(2) || p(1) == p(2);
+    }
+    let mut t = 0;
+    while t + 1 < triangles.len() {
+        if infos[t].face == infos[t + 1].face {
+            if infos[t].degenerate != infos[t + 1].degenerate {
+                infos[t].quad_one_degenerate = true;
+                infos[t + 1].quad_one_degenerate = true;
+            }
+            t += 2;
+        } else {
+            t += 1;
+        }
+    }
+
+    let order: Vec<usize> = (0..triangles.len()).filter(|t| !infos[*t].degenerate)
+        .chain((0..triangles.len()).filter(|t| infos[*t].degenerate)).collect();
+    let good = order.iter().filter(|t| !infos[**t].degenerate).count();
+    triangles = order.iter().map(|t| triangles[*t]).collect();
+    infos = order.iter().map(|t| infos[*t]).collect();
+
+    init_triangles(triangles.as_slice(), infos.as_mut_slice(), positions.as_slice(), uvs.as_slice(), good);
+    find_neighbors(triangles.as_slice(), infos.as_mut_slice(), good);
+
+    // Every corner of a triangle with a proper mapping starts a group if it is not in one yet.
+    let mut groups: Vec<Group> = Vec::new();
+    for f in 0..good {
+        for i in 0..3 {
+            if infos[f].group_with_any || infos[f].groups[i].is_some() {
+                continue;
+            }
+
+            let g = groups.len();
+            groups.push(Group {
+                vertex:         triangles[f][i],
+                preserving:     infos[f].preserving,
+                triangles:      vec![f],
+            });
+            infos[f].groups[i] = Option::Some(g);
+
+            let start = [infos[f].neighbors[i], infos[f].neighbors[(i + 2) % 3]];
+            assign(triangles.as_slice(), infos.as_mut_slice(), &mut groups[g], g, &start);
+        }
+    }
+
+    let tspaces = tangent_spaces(triangles.as_slice(), infos.as_slice(), groups.as_slice(),
+                                 offsets.as_slice(), total, positions.as_slice(), normals.as_slice(), mesh, good);
+
+    (0..mesh.face_count()).map(|f| {
+        (0..mesh.face(f).len()).map(|c| {
+            let s = tspaces[offsets[f] + c];
+            [s.os.x, s.os.y, s.os.z, if s.orient { 1.0 } else { -1.0 }]
+        }).collect()
+    }).collect()
+}
+
+/// Work out the tangent and bitangent of the good triangles, and make the two halves of a quad
+/// agree on whether the mapping is mirrored.
+fn init_triangles(triangles: &[[usize;3]], infos: &mut [TriInfo], positions: &[cgmath::Vector3<f32>],
+                  uvs: &[cgmath::Vector2<f32>], good: usize) {
+    for t in 0..good {
+        let v = triangles[t];
+        let d1 = positions[v[1]].sub_v(&positions[v[0]]);
+        let d2 = positions[v[2]].sub_v(&positions[v[0]]);
+        let t1 = uvs[v[1]].sub_v(&uvs[v[0]]);
+        let t2 = uvs[v[2]].sub_v(&uvs[v[0]]);
+        let area = t1.x * t2.y - t1.y * t2.x;
+        let os = d1.mul_s(t2.y).sub_v(&d2.mul_s(t1.y));
+        let ot = d1.mul_s(-t2.x).add_v(&d2.mul_s(t1.x));
+
+        let info = &mut infos[t];
+        info.preserving = area > 0.0;
+        if not_zero(area) {
+            let s = if info.preserving { 1.0 } else { -1.0 };
+            let (length_os, length_ot) = (os.length(), ot.length());
+            if not_zero(length_os) {
+                info.os = os.mul_s(s / length_os);
+            }
+            if not_zero(length_ot) {
+                info.ot = ot.mul_s(s / length_ot);
+            }
+            info.mag_s = length_os / area.abs();
+            info.mag_t = length_ot / area.abs();
+            if not_zero(info.mag_s) && not_zero(info.mag_t) {
+                info.group_with_any = false;
+            }
+        }
+    }
+
+    // A quad whose halves disagree has a very bad mapping, the half with the larger area in
+    // texture space (or the only one with a mapping at all) wins.
+    let mut t = 0;
+    while t + 1 < good {
+        if infos[t].face != infos[t + 1].face {
+            t += 1;
+            continue;
+        }
+        if infos[t].preserving != infos[t + 1].preserving {
+            let first = infos[t + 1].group_with_any ||
+                uv_area(triangles[t], uvs) >= uv_area(triangles[t + 1], uvs);
+            let (from, to) = if first { (t, t + 1) } else { (t + 1, t) };
+            infos[to].preserving = infos[from].preserving;
+        }
+        t += 2;
+    }
+}
+
+/// Find the triangle across every edge of the good triangles. An edge is only shared by
+/// triangles going along it the opposite ways, and by two at most.
+fn find_neighbors(triangles: &[[usize;3]], infos: &mut [TriInfo], good: usize) {
+    let mut edges: Vec<(usize, usize, usize, usize)> = Vec::with_capacity(good * 3);
+    for t in 0..good {
+        for i in 0..3 {
+            let (a, b) = (triangles[t][i], triangles[t][(i + 1) % 3]);
+            edges.push(if a < b { (a, b, t, i) } else { (b, a, t, i) });
+        }
+    }
+    edges.sort();
+
+    for i in 0..edges.len() {
+        let (low, high, f, ef) = edges[i];
+        if infos[f].neighbors[ef].is_some() {
+            continue;
+        }
+        let (a0, a1) = (triangles[f][ef], triangles[f][(ef + 1) % 3]);
+
+        let mut j = i + 1;
+        while j < edges.len() && edges[j].0 == low && edges[j].1 == high {
+            let (_, _, g, eg) = edges[j];
+            let (b0, b1) = (triangles[g][eg], triangles[g][(eg + 1) % 3]);
+            if a0 == b1 && a1 == b0 && infos[g].neighbors[eg].is_none() {
+                infos[f].neighbors[ef] = Option::Some(g);
+                infos[g].neighbors[eg] = Option::Some(f);
+                break;
+            }
+            j += 1;
+        }
+    }
+}
+
+/// Add the triangles around the vertex of a group to it, starting from the neighbours in
+/// `start` and walking on across the edges at the vertex while the triangles agree with the
+/// group on the orientation. This is `AssignRecur` of the reference with a stack in place of the
+/// recursion, which keeps the order the triangles are visited in.
+fn assign(triangles: &[[usize;3]], infos: &mut [TriInfo], group: &mut Group, index: usize, start: &[Option<usize>]) {
+    let mut stack: Vec<usize> = start.iter().rev().filter_map(|t| *t).collect();
+
+    while let Some(t) = stack.pop() {
+        // the triangles across the edges at a vertex always have the vertex too
+        let i = (0..3).find(|i| triangles[t][*i] == group.vertex).unwrap();
+        let info = &mut infos[t];
+
+        if info.groups[i].is_some() {
+            continue;
+        }
+
+        // The first group to reach a triangle without a mapping decides its orientation, the
+        // only place where the order matters.
+        if info.group_with_any && info.groups.iter().all(|g| g.is_none()) {
+            info.preserving = group.preserving;
+        }
+        if info.preserving != group.preserving {
+            continue;
+        }
+
+        group.triangles.push(t);
+        info.groups[i] = Option::Some(index);
+
+        if let Some(right) = info.neighbors[(i + 2) % 3] {
+            stack.push(right);
+        }
+        if let Some(left) = info.neighbors[i] {
+            stack.push(left);
+        }
+    }
+}
+
+/// Return the tangent space of every face corner, in the order of the faces.
+fn tangent_spaces(triangles: &[[usize;3]], infos: &[TriInfo], groups: &[Group], offsets: &[usize], total: usize,
+                  positions: &[cgmath::Vector3<f32>], normals: &[cgmath::Vector3<f32>], mesh: &Mesh, good: usize) -> Vec<TSpace> {
+    let unset = TSpace {
+        os:         cgmath::Vector3::new(1.0, 0.0, 0.0),
+        mag_s:      1.0,
+        ot:         cgmath::Vector3::new(0.0, 1.0, 0.0),
+        mag_t:      1.0,
+        counter:    0,
+        orient:     false,
+    };
+    let mut tspaces: Vec<TSpace> = repeat(unset).take(total).collect();
+
+    for (g, group) in groups.iter().enumerate() {
+        let n = normals[group.vertex];
+        // the sets of triangles already averaged for this group
+        let mut sets: Vec<(Vec<usize>, TSpace)> = Vec::new();
+
+        for f in group.triangles.iter() {
+            let f = *f;
+            let index = (0..3).find(|i| infos[f].groups[*i] == Option::Some(g)).unwrap();
+            let os = project(&infos[f].os, &n);
+            let ot = project(&infos[f].ot, &n);
+
+            // triangles of the same quad always go together
+            let mut members: Vec<usize> = group.triangles.iter().map(|t| *t).filter(|t| {
+                let other = &infos[*t];
+                infos[f].group_with_any || other.group_with_any || infos[f].face == other.face ||
+                    (os.dot(&project(&other.os, &n)) > THRESHOLD_COS && ot.dot(&project(&other.ot, &n)) > THRESHOLD_COS)
+            }).collect();
+            members.sort();
+
+            let set = match sets.iter().position(|s| s.0 == members) {
+                Some(set) => set,
+                None => {
+                    let tspace = average_corners(members.as_slice(), triangles, infos, positions, normals, group.vertex);
+                    sets.push((members, tspace));
+                    sets.len() - 1
+                },
+            };
+
+            let out = &mut tspaces[offsets[infos[f].face] + infos[f].corners[index]];
+            let counter = out.counter;
+            *out = if counter == 1 { average(out, &sets[set].1) } else { sets[set].1 };
+            out.counter = counter + 1;
+            out.orient = group.preserving;
+        }
+    }
+
+    // Degenerate triangles take the tangent space of the first good triangle with the same
+    // vertex, unless they are half of a quad.
+    for t in good..triangles.len() {
+        if infos[t].quad_one_degenerate {
+            continue;
+        }
+        for i in 0..3 {
+            let found = (0..good * 3).find(|j| triangles[*j / 3][*j % 3] == triangles[t][i]);
+            if let Some(j) = found {
+                let source = &infos[j / 3];
+                let from = offsets[source.face] + source.corners[j % 3];
+                tspaces[offsets[infos[t].face] + infos[t].corners[i]] = tspaces[from];
+            }
+        }
+    }
+
+    // The corner of a quad only its degenerate half has is in the same place as one of the
+    // corners of the good half, and takes its tangent space.
+    for t in 0..good {
+        if !infos[t].quad_one_degenerate {
+            continue;
+        }
+        let corners = infos[t].corners;
+        let missing = (1..4).find(|c| !corners.contains(c)).unwrap_or(0);
+        let face = mesh.face(infos[t].face);
+        let target = mesh.positions[face[missing] as usize];
+        let offset = offsets[infos[t].face];
+
+        if let Some(c) = corners.iter().find(|c| mesh.positions[face[**c] as usize] == target) {
+            tspaces[offset + missing] = tspaces[offset + *c];
+        }
+    }
+
+    tspaces
+}
+
+/// Return the tangent space of a vertex from the triangles around it with a mapping, projected
+/// into the plane of the normal and weighted by the angle of their corner at the vertex.
+fn average_corners(members: &[usize], triangles: &[[usize;3]], infos: &[TriInfo],
+                   positions: &[cgmath::Vector3<f32>], normals: &[cgmath::Vector3<f32>], vertex: usize) -> TSpace {
+    let mut res = TSpace {
+        os:         cgmath::Vector3::new(0.0, 0.0, 0.0),
+        mag_s:      0.0,
+        ot:         cgmath::Vector3::new(0.0, 0.0, 0.0),
+        mag_t:      0.0,
+        counter:    0,
+        orient:     false,
+    };
+    let mut angle_sum = 0.0;
+
+    for f in members.iter() {
+        let info = &infos[*f];
+        if info.group_with_any {
+            continue;
+        }
+
+        let v = triangles[*f];
+        let i = (0..3).find(|i| v[*i] == vertex).unwrap();
+        let n = normals[v[i]];
+        let os = project(&info.os, &n);
+        let ot = project(&info.ot, &n);
+
+        let p = positions[v[i]];
+        let e1 = project(&positions[v[(i + 2) % 3]].sub_v(&p), &n);
+        let e2 = project(&positions[v[(i + 1) % 3]].sub_v(&p), &n);
+        let cos = e1.dot(&e2);
+        let angle = (if cos > 1.0 { 1.0 } else if cos < -1.0 { -1.0 } else { cos }).acos();
+
+        res.os = res.os.add_v(&os.mul_s(angle));
+        res.ot = res.ot.add_v(&ot.mul_s(angle));
+        res.mag_s += angle * info.mag_s;
+        res.mag_t += angle * info.mag_t;
+        angle_sum += angle;
+    }
+
+    res.os = normalize(&res.os);
+    res.ot = normalize(&res.ot);
+    if angle_sum > 0.0 {
+        res.mag_s /= angle_sum;
+        res.mag_t /= angle_sum;
+    }
+    res
+}
+
+/// Return the average of two tangent spaces. Two that are the same are not averaged, as the
+/// rounding would make them differ from others that are the same too.
+fn average(a: &TSpace, b: &TSpace) -> TSpace {
+    if a.mag_s == b.mag_s && a.mag_t == b.mag_t && a.os == b.os && a.ot == b.ot {
+        return *a;
+    }
+
+    TSpace {
+        os:         normalize(&a.os.add_v(&b.os)),
+        mag_s:      0.5 * (a.mag_s + b.mag_s),
+        ot:         normalize(&a.ot.add_v(&b.ot)),
+        mag_t:      0.5 * (a.mag_t + b.mag_t),
+        counter:    a.counter,
+        orient:     a.orient,
+    }
+}
+
+/// Return twice the area of a triangle in texture space.
+fn uv_area(v: [usize;3], uvs: &[cgmath::Vector2<f32>]) -> f32 {
+    let t1 = uvs[v[1]].sub_v(&uvs[v[0]]);
+    let t2 = uvs[v[2]].sub_v(&uvs[v[0]]);
+    (t1.x * t2.y - t1.y * t2.x).abs()
+}
+
+/// Return `v` moved into the plane of the unit normal `n`, made unit length unless it is zero.
+fn project(v: &cgmath::Vector3<f32>, n: &cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+    normalize(&v.sub_v(&n.mul_s(n.dot(v))))
+}
+
+fn normalize(v: &cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+    if not_zero(v.x) || not_zero(v.y) || not_zero(v.z) { v.normalize() } else { *v }
+}
+
+fn not_zero(x: f32) -> bool {
+    x.abs() > FLT_MIN
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath;
+
+    use mesh::Mesh;
+
+    /// Return two quads side by side in the XY plane facing up, the left one mapped left to
+    /// right and the right one mirrored, so that they meet at a mirror seam in the middle.
+    fn mirrored_strip() -> Mesh {
+        let mut mesh = Mesh::new();
+        for y in 0..2 {
+            for x in 0..3 {
+                mesh.positions.push(cgmath::Vector3::new(x as f32, y as f32, 0.0));
+                mesh.normals.push(cgmath::Vector3::new(0.0, 0.0, 1.0));
+                let u = if x < 2 { x as f32 * 0.5 } else { 0.0 };
+                mesh.uvs.push(cgmath::Vector2::new(u, y as f32));
+            }
+        }
+        mesh.quads = vec![[0, 1, 4, 3], [1, 2, 5, 4]];
+        mesh
+    }
+
+    // On a flat mesh the reference gives every corner the direction the texture's u goes in,
+    // with a bitangent sign of -1 where the mapping is mirrored, and keeps the two sides of the
+    // seam apart because their tangents point opposite ways.
+    #[test]
+    fn mirror_seam() {
+        let mut mesh = mirrored_strip();
+        assert!(mesh.generate_tangents());
+
+        // the two vertices on the seam are split
+        assert_eq!(mesh.positions.len(), 8);
+        assert_eq!(mesh.tangents.len(), 8);
+
+        for v in mesh.quads[0].iter() {
+            assert_eq!(mesh.tangents[*v as usize], [1.0, 0.0, 0.0, 1.0]);
+        }
+        for v in mesh.quads[1].iter() {
+            assert_eq!(mesh.tangents[*v as usize], [-1.0, 0.0, 0.0, -1.0]);
+        }
+        assert!(mesh.quads[0][1] != mesh.quads[1][0]);
+        assert!(mesh.quads[0][2] != mesh.quads[1][3]);
+    }
+
+    #[test]
+    fn needs_normals_and_uvs() {
+        let mut mesh = mirrored_strip();
+        mesh.uvs.clear();
+        assert!(!mesh.generate_tangents());
+        assert_eq!(mesh.tangents.len(), 0);
+        assert_eq!(mesh.positions.len(), 6);
+    }
+}
@@ -0,0 +1,216 @@
+//! Reads Wavefront `obj` files (and the `mtl` files they reference) into a `SimpleSceneFile`.
+//! This is the counterpart of the `objwriter` module. Unlike `DrawableObject::from_obj`, which
+//! uploads straight to the graphics card, this keeps everything on our side so it can be
+//! converted or worked on.
+//!
+//! An `obj` file indexes positions, texture coordinates and normals separately while our objects
+//! use one index for all of them, so every distinct combination used by a face becomes a vertex.
+//! When an object switches material part way through, the rest of it is split off into a new
+//! object named `<object>.<n>` since an object can only have one material.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::collections::HashMap;
+use std::old_io::{File, Open, Read};
+
+use cgmath;
+
+use simplescene::{SimpleSceneFile, SimpleSceneObject, SimpleSceneMaterial};
+
+/// An object being built along with the mapping from `obj` index triples to its vertices.
+struct Building {
+    object:         SimpleSceneObject,
+    indices:        HashMap<(usize, Option<usize>, Option<usize>), u16>,
+}
+
+impl Building {
+    fn new(name: &str) -> Building {
+        let mut object = SimpleSceneObject::new();
+        object.name = String::from_str(name);
+        object.typ = String::from_str("MESH");
+        Building {
+            object:     object,
+            indices:    HashMap::new(),
+        }
+    }
+
+    fn has_faces(&self) -> bool {
+        self.object.triangles.len() > 0 || self.object.quads.len() > 0
+    }
+}
+
+/// Return a simple scene instance by loading it from an `obj` file.
+pub fn read_scene(source: &str) -> SimpleSceneFile {
+    let path = Path::new(source);
+    let mut file = File::open_mode(&path, Open, Read).unwrap();
+    let data = file.read_to_string().unwrap();
+
+    let mut positions: Vec<cgmath::Vector3<f32>> = Vec::new();
+    let mut uvs: Vec<cgmath::Vector2<f32>> = Vec::new();
+    let mut normals: Vec<cgmath::Vector3<f32>> = Vec::new();
+    let mut materials: Vec<SimpleSceneMaterial> = Vec::new();
+    let mut done: Vec<SimpleSceneObject> = Vec::new();
+
+    let mut current = Building::new(path.filestem_str().unwrap_or("obj"));
+    let mut splits = 0;
+
+    for line in data.lines() {
+        let parts: Vec<&str> = line.words().collect();
+        if parts.len() == 0 {
+            continue;
+        }
+
+        match parts[0] {
+            "v" => positions.push(vector3(parts.as_slice())),
+            "vn" => normals.push(vector3(parts.as_slice())),
+            "vt" => uvs.push(cgmath::Vector2::new(
+                parts[1].parse::<f32>().unwrap(),
+                if parts.len() > 2 { parts[2].parse::<f32>().unwrap() } else { 0.0 }
+            )),
+            "o" => {
+                let next = Building::new(if parts.len() > 1 { parts[1] } else { "obj" });
+                finish(::std::mem::replace(&mut current, next), &mut done);
+                splits = 0;
+            },
+            "g" => {
+                for g in parts[1..].iter() {
+                    let g = String::from_str(*g);
+                    if !current.object.groups.contains(&g) {
+                        current.object.groups.push(g);
+                    }
+                }
+            },
+            "usemtl" if parts.len() > 1 => {
+                let material = String::from_str(parts[1]);
+                if current.has_faces() && current.object.material.as_ref() != Option::Some(&material) {
+                    splits += 1;
+                    let mut next = Building::new(format!("{}.{}", current.object.name, splits).as_slice());
+                    next.object.groups = current.object.groups.clone();
+                    finish(::std::mem::replace(&mut current, next), &mut done);
+                }
+                current.object.material = Option::Some(material);
+            },
+            "mtllib" if parts.len() > 1 => {
+                read_materials(&path.dir_path().join(parts[1]), &mut materials);
+            },
+            "f" => {
+                let face: Vec<u16> = parts[1..].iter().map(|corner| {
+                    vertex(&mut current, *corner, positions.as_slice(), uvs.as_slice(), normals.as_slice())
+                }).collect();
+                match face.len() {
+                    3 => current.object.triangles.push([face[0], face[1], face[2]]),
+                    4 => current.object.quads.push([face[0], face[1], face[2], face[3]]),
+                    n if n > 4 => {
+                        for i in 1..n - 1 {
+                            current.object.triangles.push([face[0], face[i], face[i + 1]]);
+                        }
+                    },
+                    _ => {},
+                }
+            },
+            _ => continue,
+        }
+    }
+
+    finish(current, &mut done);
+
+    SimpleSceneFile {
+        path:           path,
+        objects:        done.into_iter().map(|o| Arc::new(Mutex::new(o))).collect(),
+        materials:      materials,
+        animations:     Vec::new(),
+    }
+}
+
+fn finish(mut building: Building, done: &mut Vec<SimpleSceneObject>) {
+    if building.object.vertices.len() > 0 {
+        building.object.update_bounds();
+        done.push(building.object);
+    }
+}
+
+fn vector3(parts: &[&str]) -> cgmath::Vector3<f32> {
+    cgmath::Vector3::new(
+        parts[1].parse::<f32>().unwrap(),
+        parts[2].parse::<f32>().unwrap(),
+        parts[3].parse::<f32>().unwrap()
+    )
+}
+
+/// Resolve an `obj` index, negative indices count back from the newest element.
+fn resolve(index: &str, len: usize) -> Option<usize> {
+    if index.len() == 0 {
+        return Option::None;
+    }
+    let i = index.parse::<i64>().unwrap();
+    Option::Some(if i < 0 { (len as i64 + i) as usize } else { (i - 1) as usize })
+}
+
+/// Return the object vertex for a face corner like `1`, `1/2`, `1//3` or `1/2/3`, adding it to
+/// the object the first time it is used.
+fn vertex(building: &mut Building, corner: &str, positions: &[cgmath::Vector3<f32>],
+          uvs: &[cgmath::Vector2<f32>], normals: &[cgmath::Vector3<f32>]) -> u16 {
+    let parts: Vec<&str> = corner.split('/').collect();
+    let v = resolve(parts[0], positions.len()).unwrap();
+    let t = if parts.len() > 1 { resolve(parts[1], uvs.len()) } else { Option::None };
+    let n = if parts.len() > 2 { resolve(parts[2], normals.len()) } else { Option::None };
+
+    if let Some(index) = building.indices.get(&(v, t, n)) {
+        return *index;
+    }
+
+    let object = &mut building.object;
+    if object.vertices.len() >= 65536 {
+        panic!("obj: too many vertices for 16-bit indices");
+    }
+
+    object.vertices.push(positions[v]);
+    if let Some(t) = t {
+        object.uvs.push(uvs[t]);
+    }
+    if let Some(n) = n {
+        object.normals.push(normals[n]);
+    }
+
+    let index = (object.vertices.len() - 1) as u16;
+    building.indices.insert((v, t, n), index);
+    index
+}
+
+fn read_materials(path: &Path, materials: &mut Vec<SimpleSceneMaterial>) {
+    // a missing material library should not keep the geometry from loading
+    let data = match File::open_mode(path, Open, Read).and_then(|mut f| f.read_to_string()) {
+        Ok(data) => data,
+        Err(_) => return,
+    };
+
+    for line in data.lines() {
+        let parts: Vec<&str> = line.words().collect();
+        if parts.len() < 2 {
+            continue;
+        }
+
+        if parts[0] == "newmtl" {
+            materials.push(SimpleSceneMaterial::new(parts[1]));
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => continue,
+        };
+
+        match parts[0] {
+            "Kd" if parts.len() > 3 => for i in 0..3 {
+                material.diffuse[i] = parts[i + 1].parse::<f32>().unwrap();
+            },
+            "d" => material.diffuse[3] = parts[1].parse::<f32>().unwrap(),
+            "Tr" => material.diffuse[3] = 1.0 - parts[1].parse::<f32>().unwrap(),
+            "Pr" => material.roughness = parts[1].parse::<f32>().unwrap(),
+            "Pm" => material.metallic = parts[1].parse::<f32>().unwrap(),
+            // the file name is last, after any options
+            "map_Kd" => material.texture = Option::Some(String::from_str(parts[parts.len() - 1])),
+            _ => continue,
+        }
+    }
+}
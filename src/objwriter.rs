@@ -0,0 +1,189 @@
+//! Writes simple scene objects back out in the Wavefront `obj` format, along with a companion `mtl`
+//! file, so that meshes built or changed inside the engine can be handed back to an artist and
+//! opened in Blender or pretty much any other modeling tool.
+//!
+//! Each object becomes an `o` entry. The object groups are written on a `g` line and the normals
+//! and texture coordinates are written only when the object actually has them. The object
+//! transforms can optionally be baked into the vertices, which is what you want if the file is
+//! going to be opened somewhere that knows nothing about our hierarchy.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::old_io::{File, BufferedWriter, Writer};
+use std::default::Default;
+
+use cgmath;
+
+use simplescene::{SimpleSceneFile, SimpleSceneObject, SimpleSceneMaterial};
+
+/// Controls what the writer puts into the `obj` file.
+pub struct ObjExportOptions {
+    /// bake the world transform (including parents) into the positions and normals
+    pub bake_transforms:    bool,
+    /// write `vn` entries for objects that have normals
+    pub normals:            bool,
+    /// write `vt` entries for objects that have texture coordinates
+    pub uvs:                bool,
+}
+
+impl Default for ObjExportOptions {
+    fn default() -> ObjExportOptions {
+        ObjExportOptions {
+            bake_transforms:    true,
+            normals:            true,
+            uvs:                true,
+        }
+    }
+}
+
+/// Write every object in the scene that has geometry.
+pub fn write_scene(scene: &SimpleSceneFile, path: &str, options: &ObjExportOptions) {
+    write_objects(scene.objects.as_slice(), scene.materials.as_slice(), path, options);
+}
+
+/// Write the objects to `path` and the materials to a `mtl` file of the same name next to it.
+/// Objects without any vertices (cameras, lamps, empties) are skipped and objects without a
+/// material use a plain white one called `default`.
+pub fn write_objects(objects: &[Arc<Mutex<SimpleSceneObject>>], materials: &[SimpleSceneMaterial],
+                     path: &str, options: &ObjExportOptions) {
+    let path = Path::new(path);
+    let mtlpath = path.with_extension("mtl");
+
+    let mut w = BufferedWriter::new(File::create(&path).unwrap());
+
+    writeln!(&mut w, "# kordom obj export").unwrap();
+    writeln!(&mut w, "mtllib {}", mtlpath.filename_str().unwrap()).unwrap();
+
+    // The indices in an obj file are global to the file (and start at one) so
+    // we have to keep track of how many of each we have written so far.
+    let mut voff: usize = 1;
+    let mut noff: usize = 1;
+    let mut toff: usize = 1;
+
+    for object in objects.iter() {
+        let object = object.lock().unwrap();
+
+        if object.vertices.len() == 0 {
+            continue;
+        }
+
+        let has_normals = options.normals && object.normals.len() == object.vertices.len();
+        let has_uvs = options.uvs && object.uvs.len() == object.vertices.len();
+
+        writeln!(&mut w, "o {}", object.name).unwrap();
+
+        for v in transformed_positions(&*object, options.bake_transforms).iter() {
+            writeln!(&mut w, "v {} {} {}", v.x, v.y, v.z).unwrap();
+        }
+
+        if has_uvs {
+            for t in object.uvs.iter() {
+                writeln!(&mut w, "vt {} {}", t.x, t.y).unwrap();
+            }
+        }
+
+        if has_normals {
+            for n in transformed_normals(&*object, options.bake_transforms).iter() {
+                writeln!(&mut w, "vn {} {} {}", n.x, n.y, n.z).unwrap();
+            }
+        }
+
+        if object.groups.len() > 0 {
+            writeln!(&mut w, "g {}", object.groups.connect(" ")).unwrap();
+        }
+
+        match object.material {
+            Some(ref material) => writeln!(&mut w, "usemtl {}", material).unwrap(),
+            None => writeln!(&mut w, "usemtl default").unwrap(),
+        }
+
+        for t in object.triangles.iter() {
+            write_face(&mut w, t.as_slice(), voff, toff, noff, has_uvs, has_normals);
+        }
+
+        for q in object.quads.iter() {
+            write_face(&mut w, q.as_slice(), voff, toff, noff, has_uvs, has_normals);
+        }
+
+        voff += object.vertices.len();
+        if has_uvs {
+            toff += object.uvs.len();
+        }
+        if has_normals {
+            noff += object.normals.len();
+        }
+    }
+
+    w.flush().unwrap();
+
+    let mut w = BufferedWriter::new(File::create(&mtlpath).unwrap());
+    writeln!(&mut w, "# kordom mtl export").unwrap();
+    write_material(&mut w, &SimpleSceneMaterial::new("default"));
+    for material in materials.iter() {
+        write_material(&mut w, material);
+    }
+    w.flush().unwrap();
+}
+
+fn write_material<W: Writer>(w: &mut W, material: &SimpleSceneMaterial) {
+    let d = material.diffuse;
+    writeln!(w, "newmtl {}", material.name).unwrap();
+    writeln!(w, "Kd {} {} {}", d[0], d[1], d[2]).unwrap();
+    writeln!(w, "Ka 0.0 0.0 0.0").unwrap();
+    writeln!(w, "Ks 0.0 0.0 0.0").unwrap();
+    writeln!(w, "d {}", d[3]).unwrap();
+    // the physically based extension understood by Blender and most other tools
+    writeln!(w, "Pr {}", material.roughness).unwrap();
+    writeln!(w, "Pm {}", material.metallic).unwrap();
+    if let Some(ref texture) = material.texture {
+        writeln!(w, "map_Kd {}", texture).unwrap();
+    }
+    writeln!(w, "illum 1").unwrap();
+}
+
+fn write_face<W: Writer>(w: &mut W, face: &[u16], voff: usize, toff: usize, noff: usize, uvs: bool, normals: bool) {
+    write!(w, "f").unwrap();
+    for i in face.iter() {
+        let i = *i as usize;
+        match (uvs, normals) {
+            (true, true) => write!(w, " {}/{}/{}", i + voff, i + toff, i + noff).unwrap(),
+            (true, false) => write!(w, " {}/{}", i + voff, i + toff).unwrap(),
+            (false, true) => write!(w, " {}//{}", i + voff, i + noff).unwrap(),
+            (false, false) => write!(w, " {}", i + voff).unwrap(),
+        }
+    }
+    write!(w, "\n").unwrap();
+}
+
+fn transformed_positions(object: &SimpleSceneObject, bake: bool) -> Vec<cgmath::Vector3<f32>> {
+    use cgmath::Matrix;
+
+    if !bake {
+        return object.vertices.clone();
+    }
+
+    let m = object.world_matrix();
+    object.vertices.iter().map(|v| {
+        m.mul_v(&cgmath::Vector4::new(v.x, v.y, v.z, 1.0)).truncate()
+    }).collect()
+}
+
+fn transformed_normals(object: &SimpleSceneObject, bake: bool) -> Vec<cgmath::Vector3<f32>> {
+    use cgmath::Matrix;
+    use cgmath::EuclideanVector;
+
+    if !bake {
+        return object.normals.clone();
+    }
+
+    // Normals need the inverse transpose so that non-uniform scaling does not
+    // skew them away from the surface.
+    let m = match object.world_matrix().invert() {
+        Some(m) => m.transpose(),
+        None => return object.normals.clone(),
+    };
+
+    object.normals.iter().map(|n| {
+        m.mul_v(&cgmath::Vector4::new(n.x, n.y, n.z, 0.0)).truncate().normalize()
+    }).collect()
+}
@@ -0,0 +1,287 @@
+//! Reads and writes PLY files in the ASCII and both binary flavors. Apart from the positions and
+//! faces the per-vertex normals (`nx ny nz`), colors (`red green blue`) and texture coordinates
+//! (`s t` or `u v`) are understood. Any other properties and elements are skipped over on import.
+//!
+//! Colors stored as bytes are scaled into the zero to one range that `SimpleSceneObject` uses and
+//! are written back out as bytes.
+
+use std::old_io::{File, BufReader, BufferedWriter, Reader, Writer};
+
+use cgmath;
+
+use simplescene::SimpleSceneObject;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittle,
+    BinaryBig,
+}
+
+struct Property {
+    name:           String,
+    typ:            String,
+    /// the type of the count for list properties
+    count:          Option<String>,
+}
+
+struct Element {
+    name:           String,
+    count:          usize,
+    properties:     Vec<Property>,
+}
+
+/// Hands out the values of the body one at a time no matter how they are stored.
+struct Values<'a> {
+    format:         Format,
+    words:          Vec<&'a str>,
+    next:           usize,
+    reader:         BufReader<'a>,
+}
+
+impl<'a> Values<'a> {
+    fn scalar(&mut self, typ: &str) -> f64 {
+        if self.format == Format::Ascii {
+            self.next += 1;
+            return self.words[self.next - 1].parse::<f64>().unwrap();
+        }
+
+        let big = self.format == Format::BinaryBig;
+        let r = &mut self.reader;
+        match typ {
+            "char" | "int8" => r.read_i8().unwrap() as f64,
+            "uchar" | "uint8" => r.read_u8().unwrap() as f64,
+            "short" | "int16" => (if big { r.read_be_i16() } else { r.read_le_i16() }).unwrap() as f64,
+            "ushort" | "uint16" => (if big { r.read_be_u16() } else { r.read_le_u16() }).unwrap() as f64,
+            "int" | "int32" => (if big { r.read_be_i32() } else { r.read_le_i32() }).unwrap() as f64,
+            "uint" | "uint32" => (if big { r.read_be_u32() } else { r.read_le_u32() }).unwrap() as f64,
+            "float" | "float32" => (if big { r.read_be_f32() } else { r.read_le_f32() }).unwrap() as f64,
+            "double" | "float64" => (if big { r.read_be_f64() } else { r.read_le_f64() }).unwrap(),
+            _ => panic!("ply: unknown property type {}", typ),
+        }
+    }
+}
+
+/// Return an object built from the vertices and faces of a PLY file.
+pub fn read(source: &str) -> SimpleSceneObject {
+    let path = Path::new(source);
+    let data = File::open(&path).unwrap().read_to_end().unwrap();
+
+    let mut object = SimpleSceneObject::new();
+    object.typ = String::from_str("MESH");
+    object.name = String::from_str(path.filestem_str().unwrap_or("ply"));
+
+    // The header is always text and ends with an `end_header` line.
+    let end = match data.windows(10).position(|w| w == &b"end_header"[..]) {
+        Some(end) => end,
+        None => panic!("ply: file has no end_header"),
+    };
+    let body = match data[end..].iter().position(|b| *b == b'\n') {
+        Some(nl) => end + nl + 1,
+        None => data.len(),
+    };
+    let header = ::std::str::from_utf8(&data[..end]).unwrap();
+
+    let mut format = Format::Ascii;
+    let mut elements: Vec<Element> = Vec::new();
+
+    for (n, line) in header.lines().enumerate() {
+        let parts: Vec<&str> = line.words().collect();
+        if n == 0 {
+            if parts != vec!["ply"] {
+                panic!("ply: not a ply file");
+            }
+            continue;
+        }
+        if parts.len() < 2 {
+            continue;
+        }
+        match parts[0] {
+            "format" => format = match parts[1] {
+                "ascii" => Format::Ascii,
+                "binary_little_endian" => Format::BinaryLittle,
+                "binary_big_endian" => Format::BinaryBig,
+                other => panic!("ply: unknown format {}", other),
+            },
+            "element" => elements.push(Element {
+                name:       String::from_str(parts[1]),
+                count:      parts[2].parse::<usize>().unwrap(),
+                properties: Vec::new(),
+            }),
+            "property" if parts[1] == "list" => elements.last_mut().unwrap().properties.push(Property {
+                name:       String::from_str(parts[4]),
+                typ:        String::from_str(parts[3]),
+                count:      Option::Some(String::from_str(parts[2])),
+            }),
+            "property" => elements.last_mut().unwrap().properties.push(Property {
+                name:       String::from_str(parts[2]),
+                typ:        String::from_str(parts[1]),
+                count:      Option::None,
+            }),
+            _ => continue,
+        }
+    }
+
+    let text = if format == Format::Ascii { ::std::str::from_utf8(&data[body..]).unwrap() } else { "" };
+    let mut values = Values {
+        format:     format,
+        words:      text.words().collect(),
+        next:       0,
+        reader:     BufReader::new(&data[body..]),
+    };
+
+    for element in elements.iter() {
+        for _ in 0..element.count {
+            let mut row: Vec<(&str, f64)> = Vec::new();
+            let mut face: Vec<u16> = Vec::new();
+
+            for p in element.properties.iter() {
+                match p.count {
+                    Some(ref count) => {
+                        let n = values.scalar(count.as_slice()) as usize;
+                        let list: Vec<f64> = (0..n).map(|_| values.scalar(p.typ.as_slice())).collect();
+                        if p.name.as_slice() == "vertex_indices" || p.name.as_slice() == "vertex_index" {
+                            face = list.iter().map(|i| *i as u16).collect();
+                        }
+                    },
+                    None => row.push((p.name.as_slice(), values.scalar(p.typ.as_slice()))),
+                }
+            }
+
+            match element.name.as_slice() {
+                "vertex" => add_vertex(&mut object, row.as_slice(), element),
+                "face" => add_face(&mut object, face.as_slice()),
+                _ => continue,
+            }
+        }
+    }
+
+    object.update_bounds();
+    object
+}
+
+fn add_vertex(object: &mut SimpleSceneObject, row: &[(&str, f64)], element: &Element) {
+    let get = |name: &str| row.iter().find(|p| p.0 == name).map(|p| p.1 as f32);
+
+    if object.vertices.len() >= 65536 {
+        panic!("ply: too many vertices for 16-bit indices");
+    }
+
+    object.vertices.push(cgmath::Vector3::new(
+        get("x").unwrap_or(0.0), get("y").unwrap_or(0.0), get("z").unwrap_or(0.0)
+    ));
+
+    if let (Some(x), Some(y), Some(z)) = (get("nx"), get("ny"), get("nz")) {
+        object.normals.push(cgmath::Vector3::new(x, y, z));
+    }
+
+    if let (Some(r), Some(g), Some(b)) = (get("red"), get("green"), get("blue")) {
+        // colors stored as bytes have to be scaled down
+        let byte = element.properties.iter().find(|p| p.name.as_slice() == "red")
+            .map(|p| p.typ.as_slice() == "uchar" || p.typ.as_slice() == "uint8").unwrap_or(false);
+        let scale = if byte { 1.0 / 255.0 } else { 1.0 };
+        object.colors.push(cgmath::Vector3::new(r * scale, g * scale, b * scale));
+    }
+
+    match (get("s").or(get("u")), get("t").or(get("v"))) {
+        (Some(s), Some(t)) => object.uvs.push(cgmath::Vector2::new(s, t)),
+        _ => {},
+    }
+}
+
+fn add_face(object: &mut SimpleSceneObject, face: &[u16]) {
+    match face.len() {
+        3 => object.triangles.push([face[0], face[1], face[2]]),
+        4 => object.quads.push([face[0], face[1], face[2], face[3]]),
+        n if n > 4 => {
+            for i in 1..n - 1 {
+                object.triangles.push([face[0], face[i], face[i + 1]]);
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Write the object as a PLY file, as little endian binary if `binary` is set. Normals, colors
+/// and texture coordinates are written when the object has one for every vertex.
+pub fn write(object: &SimpleSceneObject, path: &str, binary: bool) {
+    let count = object.vertices.len();
+    let normals = object.normals.len() == count;
+    let colors = object.colors.len() == count;
+    let uvs = object.uvs.len() == count;
+
+    let mut w = BufferedWriter::new(File::create(&Path::new(path)).unwrap());
+
+    writeln!(&mut w, "ply").unwrap();
+    writeln!(&mut w, "format {} 1.0", if binary { "binary_little_endian" } else { "ascii" }).unwrap();
+    writeln!(&mut w, "comment kordom ply export of {}", object.name).unwrap();
+    writeln!(&mut w, "element vertex {}", count).unwrap();
+    writeln!(&mut w, "property float x\nproperty float y\nproperty float z").unwrap();
+    if normals {
+        writeln!(&mut w, "property float nx\nproperty float ny\nproperty float nz").unwrap();
+    }
+    if colors {
+        writeln!(&mut w, "property uchar red\nproperty uchar green\nproperty uchar blue").unwrap();
+    }
+    if uvs {
+        writeln!(&mut w, "property float s\nproperty float t").unwrap();
+    }
+    writeln!(&mut w, "element face {}", object.triangles.len() + object.quads.len()).unwrap();
+    writeln!(&mut w, "property list uchar int vertex_indices").unwrap();
+    writeln!(&mut w, "end_header").unwrap();
+
+    let byte = |c: f32| -> u8 {
+        let c = c * 255.0 + 0.5;
+        if c < 0.0 { 0 } else if c > 255.0 { 255 } else { c as u8 }
+    };
+
+    for i in 0..count {
+        let v = object.vertices[i];
+        let mut floats = vec![v.x, v.y, v.z];
+        if normals {
+            let n = object.normals[i];
+            floats.push_all(&[n.x, n.y, n.z]);
+        }
+
+        if binary {
+            for f in floats.iter() {
+                w.write_le_f32(*f).unwrap();
+            }
+            if colors {
+                let c = object.colors[i];
+                w.write_all(&[byte(c.x), byte(c.y), byte(c.z)]).unwrap();
+            }
+            if uvs {
+                w.write_le_f32(object.uvs[i].x).unwrap();
+                w.write_le_f32(object.uvs[i].y).unwrap();
+            }
+        } else {
+            let mut line: Vec<String> = floats.iter().map(|f| format!("{}", f)).collect();
+            if colors {
+                let c = object.colors[i];
+                line.push(format!("{} {} {}", byte(c.x), byte(c.y), byte(c.z)));
+            }
+            if uvs {
+                line.push(format!("{} {}", object.uvs[i].x, object.uvs[i].y));
+            }
+            writeln!(&mut w, "{}", line.connect(" ")).unwrap();
+        }
+    }
+
+    let mut faces: Vec<&[u16]> = object.triangles.iter().map(|t| t.as_slice()).collect();
+    faces.extend(object.quads.iter().map(|q| q.as_slice()));
+
+    for face in faces.iter() {
+        if binary {
+            w.write_u8(face.len() as u8).unwrap();
+            for i in face.iter() {
+                w.write_le_i32(*i as i32).unwrap();
+            }
+        } else {
+            let line: Vec<String> = face.iter().map(|i| format!("{}", i)).collect();
+            writeln!(&mut w, "{} {}", face.len(), line.connect(" ")).unwrap();
+        }
+    }
+
+    w.flush().unwrap();
+}
@@ -0,0 +1,251 @@
+//! Generators for the usual primitive shapes, handy for testing and debug drawing. They give a
+//! `Mesh` with normals and texture coordinates which can be uploaded with
+//! `DrawableObject::from_mesh` or put into a scene with `insert`.
+//!
+//! Like the scenes exported from Blender everything is Z up and centered on the origin, and the
+//! faces wind counter-clockwise seen from the outside. The shapes use quads wherever they can and
+//! only use triangles at the poles and in the caps. Vertices are split along the texture seam
+//! and at hard edges so that the normals and texture coordinates are right everywhere.
+
+use std::num::Float;
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use cgmath;
+use cgmath::{Vector, EuclideanVector};
+
+use mesh::Mesh;
+use simplescene::{SimpleSceneFile, SimpleSceneObject};
+
+fn vertex(mesh: &mut Mesh, p: cgmath::Vector3<f32>, n: cgmath::Vector3<f32>, u: f32, v: f32) -> u16 {
+    if mesh.positions.len() >= 65536 {
+        panic!("primitives: too many vertices for 16-bit indices, use fewer segments");
+    }
+    mesh.positions.push(p);
+    mesh.normals.push(n);
+    mesh.uvs.push(cgmath::Vector2::new(u, v));
+    (mesh.positions.len() - 1) as u16
+}
+
+/// Return a cube with sides of length `size`, every side mapped onto the whole texture.
+pub fn cube(size: f32) -> Mesh {
+    let mut mesh = Mesh::new();
+    let h = size * 0.5;
+
+    let sides = [
+        (cgmath::Vector3::new(1.0, 0.0, 0.0), cgmath::Vector3::new(0.0, 1.0, 0.0)),
+        (cgmath::Vector3::new(-1.0, 0.0, 0.0), cgmath::Vector3::new(0.0, -1.0, 0.0)),
+        (cgmath::Vector3::new(0.0, 1.0, 0.0), cgmath::Vector3::new(-1.0, 0.0, 0.0)),
+        (cgmath::Vector3::new(0.0, -1.0, 0.0), cgmath::Vector3::new(1.0, 0.0, 0.0)),
+        (cgmath::Vector3::new(0.0, 0.0, 1.0), cgmath::Vector3::new(1.0, 0.0, 0.0)),
+        (cgmath::Vector3::new(0.0, 0.0, -1.0), cgmath::Vector3::new(-1.0, 0.0, 0.0)),
+    ];
+
+    for &(n, u) in sides.iter() {
+        let v = n.cross(&u);
+        let corner = |su: f32, sv: f32| n.add_v(&u.mul_s(su)).add_v(&v.mul_s(sv)).mul_s(h);
+        let a = vertex(&mut mesh, corner(-1.0, -1.0), n, 0.0, 0.0);
+        let b = vertex(&mut mesh, corner(1.0, -1.0), n, 1.0, 0.0);
+        let c = vertex(&mut mesh, corner(1.0, 1.0), n, 1.0, 1.0);
+        let d = vertex(&mut mesh, corner(-1.0, 1.0), n, 0.0, 1.0);
+        mesh.quads.push([a, b, c, d]);
+    }
+
+    mesh
+}
+
+/// Return a square in the XY plane facing up with sides of length `size`, split into
+/// `segments` by `segments` quads.
+pub fn plane(size: f32, segments: usize) -> Mesh {
+    let mut mesh = Mesh::new();
+    let segments = segments.max(1);
+    let n = cgmath::Vector3::new(0.0, 0.0, 1.0);
+
+    for y in 0..segments + 1 {
+        for x in 0..segments + 1 {
+            let u = x as f32 / segments as f32;
+            let v = y as f32 / segments as f32;
+            vertex(&mut mesh, cgmath::Vector3::new((u - 0.5) * size, (v - 0.5) * size, 0.0), n, u, v);
+        }
+    }
+
+    let row = segments + 1;
+    for y in 0..segments {
+        for x in 0..segments {
+            let a = (y * row + x) as u16;
+            mesh.quads.push([a, a + 1, a + 1 + row as u16, a + row as u16]);
+        }
+    }
+
+    mesh
+}
+
+/// Build the rows of a shape that goes around the Z axis from the top pole to the bottom one.
+/// Every row is `(theta, z offset)` where `theta` is the angle down from the top. Rows at a pole
+/// give triangles, the others quads.
+fn rings(rows: &[(f32, f32)], radius: f32, segments: usize) -> Mesh {
+    let mut mesh = Mesh::new();
+    let segments = segments.max(3);
+    let row = segments + 1;
+
+    for (r, &(theta, offset)) in rows.iter().enumerate() {
+        for s in 0..segments + 1 {
+            let phi = 2.0 * PI * s as f32 / segments as f32;
+            let n = cgmath::Vector3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+            let p = n.mul_s(radius).add_v(&cgmath::Vector3::new(0.0, 0.0, offset));
+            vertex(&mut mesh, p, n, s as f32 / segments as f32, 1.0 - r as f32 / (rows.len() - 1) as f32);
+        }
+    }
+
+    for r in 0..rows.len() - 1 {
+        for s in 0..segments {
+            let a = (r * row + s) as u16;
+            let b = a + row as u16;
+            let c = b + 1;
+            let d = a + 1;
+            if r == 0 {
+                mesh.triangles.push([a, b, c]);
+            } else if r == rows.len() - 2 {
+                mesh.triangles.push([a, b, d]);
+            } else {
+                mesh.quads.push([a, b, c, d]);
+            }
+        }
+    }
+
+    mesh
+}
+
+/// Return a UV sphere with `segments` around and `rings` from pole to pole.
+pub fn sphere(radius: f32, segments: usize, rings: usize) -> Mesh {
+    let count = rings.max(2);
+    let rows: Vec<(f32, f32)> = (0..count + 1).map(|r| (PI * r as f32 / count as f32, 0.0)).collect();
+    self::rings(rows.as_slice(), radius, segments)
+}
+
+/// Return a capsule along the Z axis: a cylinder of `length` capped with half spheres, so the
+/// whole thing is `length + 2 * radius` long. `rings` is the number of rings in each half sphere.
+pub fn capsule(radius: f32, length: f32, segments: usize, rings: usize) -> Mesh {
+    let half = rings.max(1);
+    let mut rows: Vec<(f32, f32)> = Vec::new();
+
+    for r in 0..half + 1 {
+        rows.push((PI * 0.5 * r as f32 / half as f32, length * 0.5));
+    }
+    for r in 0..half + 1 {
+        rows.push((PI * 0.5 + PI * 0.5 * r as f32 / half as f32, -length * 0.5));
+    }
+
+    self::rings(rows.as_slice(), radius, segments)
+}
+
+/// Add a flat disc at height `z` facing up or down, as a fan around a center vertex.
+fn cap(mesh: &mut Mesh, radius: f32, z: f32, segments: usize, up: bool) {
+    let n = cgmath::Vector3::new(0.0, 0.0, if up { 1.0 } else { -1.0 });
+    let center = vertex(mesh, cgmath::Vector3::new(0.0, 0.0, z), n, 0.5, 0.5);
+    let first = mesh.positions.len() as u16;
+
+    for s in 0..segments {
+        let phi = 2.0 * PI * s as f32 / segments as f32;
+        let (x, y) = (phi.cos(), phi.sin());
+        vertex(mesh, cgmath::Vector3::new(x * radius, y * radius, z), n, 0.5 + x * 0.5, 0.5 + y * 0.5);
+    }
+
+    for s in 0..segments as u16 {
+        let a = first + s;
+        let b = first + (s + 1) % segments as u16;
+        mesh.triangles.push(if up { [center, a, b] } else { [center, b, a] });
+    }
+}
+
+/// Return a cylinder along the Z axis that is `depth` long, with closed ends.
+pub fn cylinder(radius: f32, depth: f32, segments: usize) -> Mesh {
+    let mut mesh = Mesh::new();
+    let segments = segments.max(3);
+    let h = depth * 0.5;
+
+    for s in 0..segments + 1 {
+        let phi = 2.0 * PI * s as f32 / segments as f32;
+        let n = cgmath::Vector3::new(phi.cos(), phi.sin(), 0.0);
+        let u = s as f32 / segments as f32;
+        vertex(&mut mesh, cgmath::Vector3::new(n.x * radius, n.y * radius, -h), n, u, 0.0);
+        vertex(&mut mesh, cgmath::Vector3::new(n.x * radius, n.y * radius, h), n, u, 1.0);
+    }
+
+    for s in 0..segments as u16 {
+        let a = s * 2;
+        mesh.quads.push([a, a + 2, a + 3, a + 1]);
+    }
+
+    cap(&mut mesh, radius, h, segments, true);
+    cap(&mut mesh, radius, -h, segments, false);
+    mesh
+}
+
+/// Return a cone along the Z axis that is `depth` long with its tip at the top and a closed
+/// base.
+pub fn cone(radius: f32, depth: f32, segments: usize) -> Mesh {
+    let mut mesh = Mesh::new();
+    let segments = segments.max(3);
+    let h = depth * 0.5;
+    let normal = |phi: f32| cgmath::Vector3::new(phi.cos() * depth, phi.sin() * depth, radius).normalize();
+
+    for s in 0..segments + 1 {
+        let phi = 2.0 * PI * s as f32 / segments as f32;
+        let u = s as f32 / segments as f32;
+        vertex(&mut mesh, cgmath::Vector3::new(phi.cos() * radius, phi.sin() * radius, -h), normal(phi), u, 0.0);
+    }
+
+    // every side gets its own tip so the normal there points the right way
+    for s in 0..segments as u16 {
+        let phi = 2.0 * PI * (s as f32 + 0.5) / segments as f32;
+        let u = (s as f32 + 0.5) / segments as f32;
+        let tip = vertex(&mut mesh, cgmath::Vector3::new(0.0, 0.0, h), normal(phi), u, 1.0);
+        mesh.triangles.push([s, s + 1, tip]);
+    }
+
+    cap(&mut mesh, radius, -h, segments, false);
+    mesh
+}
+
+/// Return a torus around the Z axis. `major` is the radius of the ring and `minor` the radius of
+/// the tube, `segments` go around the ring and `sides` around the tube.
+pub fn torus(major: f32, minor: f32, segments: usize, sides: usize) -> Mesh {
+    let mut mesh = Mesh::new();
+    let segments = segments.max(3);
+    let sides = sides.max(3);
+    let row = sides + 1;
+
+    for i in 0..segments + 1 {
+        let u = 2.0 * PI * i as f32 / segments as f32;
+        for j in 0..sides + 1 {
+            let v = 2.0 * PI * j as f32 / sides as f32;
+            let n = cgmath::Vector3::new(v.cos() * u.cos(), v.cos() * u.sin(), v.sin());
+            let p = cgmath::Vector3::new((major + minor * v.cos()) * u.cos(), (major + minor * v.cos()) * u.sin(), minor * v.sin());
+            vertex(&mut mesh, p, n, i as f32 / segments as f32, j as f32 / sides as f32);
+        }
+    }
+
+    for i in 0..segments {
+        for j in 0..sides {
+            let a = (i * row + j) as u16;
+            let b = a + row as u16;
+            mesh.quads.push([a, b, b + 1, a + 1]);
+        }
+    }
+
+    mesh
+}
+
+/// Put a mesh into a scene as a new `MESH` object at the origin and return the object.
+pub fn insert(scene: &mut SimpleSceneFile, name: &str, mesh: &Mesh) -> Arc<Mutex<SimpleSceneObject>> {
+    let mut object = SimpleSceneObject::new();
+    object.name = String::from_str(name);
+    object.typ = String::from_str("MESH");
+    mesh.to_simplescene(&mut object);
+
+    let object = Arc::new(Mutex::new(object));
+    scene.objects.push(object.clone());
+    object
+}
@@ -0,0 +1,363 @@
+//! Casting rays against the geometry of scene objects, mostly for picking objects with the
+//! mouse. Every mesh gets a bounding volume hierarchy over its triangles so that a ray only has
+//! to be tested against the few triangles near it.
+//!
+//! Triangles are numbered like `SimpleSceneObject::triangulate` would leave them: the triangles
+//! first and then two for every quad, `[0, 1, 2]` and `[0, 2, 3]`.
+
+use std::num::Float;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use cgmath;
+use cgmath::{Vector, EuclideanVector, Matrix};
+
+use bounds::Aabb;
+use simplescene::{SimpleSceneFile, SimpleSceneObject};
+
+/// The most triangles a leaf of the hierarchy holds.
+const LEAF_SIZE: usize = 4;
+
+#[derive(Copy, Clone)]
+pub struct Ray {
+    pub origin:         cgmath::Vector3<f32>,
+    pub direction:      cgmath::Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: cgmath::Vector3<f32>, direction: cgmath::Vector3<f32>) -> Ray {
+        Ray { origin: origin, direction: direction }
+    }
+
+    /// Return the point `t` along the ray, in units of the direction's length.
+    pub fn at(&self, t: f32) -> cgmath::Vector3<f32> {
+        self.origin.add_v(&self.direction.mul_s(t))
+    }
+
+    /// Return the ray after a transformation, the direction is not normalized again.
+    pub fn transform(&self, m: &cgmath::Matrix4<f32>) -> Ray {
+        let o = self.origin;
+        let d = self.direction;
+        Ray {
+            origin:     m.mul_v(&cgmath::Vector4::new(o.x, o.y, o.z, 1.0)).truncate(),
+            direction:  m.mul_v(&cgmath::Vector4::new(d.x, d.y, d.z, 0.0)).truncate(),
+        }
+    }
+}
+
+/// Where a ray hit a triangle of a mesh.
+#[derive(Copy, Clone)]
+pub struct MeshHit {
+    pub triangle:       usize,
+    /// the weights of the three corners at the hit
+    pub barycentric:    cgmath::Vector3<f32>,
+    /// how far along the ray, in units of the direction's length
+    pub t:              f32,
+}
+
+/// Where a ray hit an object of a scene.
+pub struct RayHit {
+    pub name:           String,
+    pub triangle:       usize,
+    /// the weights of the three corners at the hit
+    pub barycentric:    cgmath::Vector3<f32>,
+    /// distance from the origin of the ray in world space
+    pub distance:       f32,
+}
+
+/// Return where a ray hits a triangle using the method of Moller and Trumbore. Both sides of
+/// the triangle count. The result is `t` along the ray and the weights `u` and `v` of the
+/// second and third corners.
+pub fn intersect_triangle(ray: &Ray, a: &cgmath::Vector3<f32>, b: &cgmath::Vector3<f32>, c: &cgmath::Vector3<f32>) -> Option<(f32, f32, f32)> {
+    let e1 = b.sub_v(a);
+    let e2 = c.sub_v(a);
+    let p = ray.direction.cross(&e2);
+    let det = e1.dot(&p);
+
+    if det.abs() < 1e-12 {
+        return Option::None;
+    }
+
+    let s = ray.origin.sub_v(a);
+    let u = s.dot(&p) / det;
+    if u < 0.0 || u > 1.0 {
+        return Option::None;
+    }
+
+    let q = s.cross(&e1);
+    let v = ray.direction.dot(&q) / det;
+    if v < 0.0 || u + v > 1.0 {
+        return Option::None;
+    }
+
+    let t = e2.dot(&q) / det;
+    if t < 0.0 {
+        return Option::None;
+    }
+
+    Option::Some((t, u, v))
+}
+
+/// Return the range of `t` along the ray that is inside the box, if any, using the slab method.
+pub fn intersect_aabb(ray: &Ray, b: &Aabb) -> Option<(f32, f32)> {
+    let mut near = 0.0f32;
+    let mut far: f32 = Float::max_value();
+
+    for axis in 0..3 {
+        let (o, d, min, max) = match axis {
+            0 => (ray.origin.x, ray.direction.x, b.min.x, b.max.x),
+            1 => (ray.origin.y, ray.direction.y, b.min.y, b.max.y),
+            _ => (ray.origin.z, ray.direction.z, b.min.z, b.max.z),
+        };
+
+        if d == 0.0 {
+            if o < min || o > max {
+                return Option::None;
+            }
+            continue;
+        }
+
+        let t1 = (min - o) / d;
+        let t2 = (max - o) / d;
+        near = near.max(t1.min(t2));
+        far = far.min(t1.max(t2));
+
+        if near > far {
+            return Option::None;
+        }
+    }
+
+    Option::Some((near, far))
+}
+
+struct Node {
+    bounds:         Aabb,
+    /// for a leaf the range of `order`, otherwise the first child with the second after it
+    first:          usize,
+    count:          usize,
+    leaf:           bool,
+}
+
+/// A bounding volume hierarchy over the triangles of one mesh, in object space.
+pub struct MeshBvh {
+    positions:      Vec<cgmath::Vector3<f32>>,
+    triangles:      Vec<[u16;3]>,
+    nodes:          Vec<Node>,
+    /// triangle numbers sorted so every leaf covers a range of them
+    order:          Vec<usize>,
+}
+
+impl MeshBvh {
+    /// Build the hierarchy for the triangles and quads of an object.
+    pub fn new(object: &SimpleSceneObject) -> MeshBvh {
+        let mut triangles = object.triangles.clone();
+        for q in object.quads.iter() {
+            triangles.push([q[0], q[1], q[2]]);
+            triangles.push([q[0], q[2], q[3]]);
+        }
+
+        let mut bvh = MeshBvh {
+            positions:  object.vertices.clone(),
+            order:      (0..triangles.len()).collect(),
+            triangles:  triangles,
+            nodes:      Vec::new(),
+        };
+
+        if bvh.triangles.len() > 0 {
+            bvh.nodes.push(Node { bounds: Aabb::empty(), first: 0, count: 0, leaf: true });
+            let count = bvh.order.len();
+            bvh.build(0, 0, count);
+        }
+
+        bvh
+    }
+
+    fn corners(&self, t: usize) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+        let t = self.triangles[t];
+        (self.positions[t[0] as usize], self.positions[t[1] as usize], self.positions[t[2] as usize])
+    }
+
+    fn centroid(&self, t: usize) -> cgmath::Vector3<f32> {
+        let (a, b, c) = self.corners(t);
+        a.add_v(&b).add_v(&c).div_s(3.0)
+    }
+
+    /// Fill in a node covering `order[first..first + count]`, splitting it in half along the
+    /// longest axis of the triangle centers until the leaves are small enough.
+    fn build(&mut self, node: usize, first: usize, count: usize) {
+        let mut bounds = Aabb::empty();
+        let mut centers = Aabb::empty();
+        for i in first..first + count {
+            let (a, b, c) = self.corners(self.order[i]);
+            bounds.add_point(&a);
+            bounds.add_point(&b);
+            bounds.add_point(&c);
+            centers.add_point(&self.centroid(self.order[i]));
+        }
+
+        self.nodes[node] = Node { bounds: bounds, first: first, count: count, leaf: true };
+
+        if count <= LEAF_SIZE {
+            return;
+        }
+
+        let e = centers.extent();
+        let axis = if e.x >= e.y && e.x >= e.z { 0 } else if e.y >= e.z { 1 } else { 2 };
+        let key = |v: cgmath::Vector3<f32>| match axis { 0 => v.x, 1 => v.y, _ => v.z };
+
+        let mut range: Vec<(f32, usize)> = self.order[first..first + count].iter().map(|t| {
+            (key(self.centroid(*t)), *t)
+        }).collect();
+        range.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+        for (i, r) in range.iter().enumerate() {
+            self.order[first + i] = r.1;
+        }
+
+        let children = self.nodes.len();
+        self.nodes.push(Node { bounds: Aabb::empty(), first: 0, count: 0, leaf: true });
+        self.nodes.push(Node { bounds: Aabb::empty(), first: 0, count: 0, leaf: true });
+        self.nodes[node].first = children;
+        self.nodes[node].leaf = false;
+
+        let half = count / 2;
+        self.build(children, first, half);
+        self.build(children + 1, first + half, count - half);
+    }
+
+    /// Return the closest hit of the ray, which has to be in the same space as the mesh.
+    pub fn raycast(&self, ray: &Ray) -> Option<MeshHit> {
+        let mut best: Option<MeshHit> = Option::None;
+        let mut stack: Vec<usize> = Vec::new();
+
+        if self.nodes.len() > 0 {
+            stack.push(0);
+        }
+
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+
+            match intersect_aabb(ray, &node.bounds) {
+                Some((near, _)) => {
+                    if best.map(|b| near > b.t).unwrap_or(false) {
+                        continue;
+                    }
+                },
+                None => continue,
+            }
+
+            if !node.leaf {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+                continue;
+            }
+
+            for i in node.first..node.first + node.count {
+                let t = self.order[i];
+                let (a, b, c) = self.corners(t);
+                if let Some((d, u, v)) = intersect_triangle(ray, &a, &b, &c) {
+                    if best.map(|b| d < b.t).unwrap_or(true) {
+                        best = Option::Some(MeshHit {
+                            triangle:       t,
+                            barycentric:    cgmath::Vector3::new(1.0 - u - v, u, v),
+                            t:              d,
+                        });
+                    }
+                }
+            }
+        }
+
+        best
+    }
+}
+
+/// Casts rays against every mesh of a scene. The hierarchies are built once when it is made,
+/// so make a new one when the geometry changes. Moving objects around is fine since the world
+/// transforms are looked up for every ray.
+pub struct Raycaster {
+    meshes:         Vec<(Arc<Mutex<SimpleSceneObject>>, MeshBvh)>,
+}
+
+impl Raycaster {
+    pub fn new(scene: &SimpleSceneFile) -> Raycaster {
+        Raycaster {
+            meshes: scene.objects.iter().filter_map(|o| {
+                let bvh = {
+                    let object = o.lock().unwrap();
+                    if object.triangles.len() == 0 && object.quads.len() == 0 {
+                        return Option::None;
+                    }
+                    MeshBvh::new(&*object)
+                };
+                Option::Some((o.clone(), bvh))
+            }).collect(),
+        }
+    }
+
+    /// Return the closest object hit by the ray starting at `origin` going along `direction`,
+    /// both in world space.
+    pub fn raycast(&self, origin: &cgmath::Vector3<f32>, direction: &cgmath::Vector3<f32>) -> Option<RayHit> {
+        let ray = Ray::new(*origin, direction.normalize());
+        let mut best: Option<RayHit> = Option::None;
+
+        for &(ref object, ref bvh) in self.meshes.iter() {
+            let object = object.lock().unwrap();
+            let world = object.world_matrix();
+
+            // quick test against the world space sphere before going into the mesh
+            let sphere = object.world_sphere();
+            if !sphere.is_empty() {
+                let to = sphere.center.sub_v(&ray.origin);
+                let along = to.dot(&ray.direction);
+                let miss = to.length2() - along * along;
+                if miss > sphere.radius * sphere.radius || along < -sphere.radius {
+                    continue;
+                }
+            }
+
+            let inverse = match world.invert() {
+                Some(inverse) => inverse,
+                None => continue,
+            };
+
+            if let Some(hit) = bvh.raycast(&ray.transform(&inverse)) {
+                // the object space t is not a world distance when the object is scaled
+                let local = ray.transform(&inverse).at(hit.t);
+                let point = world.mul_v(&cgmath::Vector4::new(local.x, local.y, local.z, 1.0)).truncate();
+                let distance = point.sub_v(&ray.origin).length();
+
+                if best.as_ref().map(|b| distance < b.distance).unwrap_or(true) {
+                    best = Option::Some(RayHit {
+                        name:           object.name.clone(),
+                        triangle:       hit.triangle,
+                        barycentric:    hit.barycentric,
+                        distance:       distance,
+                    });
+                }
+            }
+        }
+
+        best
+    }
+}
+
+/// Return the world space ray under a point of the screen, in pixels from the top left corner,
+/// for a camera with the given projection times view matrix.
+pub fn screen_ray(x: f32, y: f32, width: f32, height: f32, camera: &cgmath::Matrix4<f32>) -> Ray {
+    let inverse = match camera.invert() {
+        Some(inverse) => inverse,
+        None => panic!("screen_ray: camera matrix can not be inverted"),
+    };
+
+    let nx = x / width * 2.0 - 1.0;
+    let ny = 1.0 - y / height * 2.0;
+
+    let unproject = |z: f32| {
+        let p = inverse.mul_v(&cgmath::Vector4::new(nx, ny, z, 1.0));
+        p.truncate().div_s(p.w)
+    };
+
+    let near = unproject(-1.0);
+    let far = unproject(1.0);
+
+    Ray::new(near, far.sub_v(&near).normalize())
+}
@@ -0,0 +1,292 @@
+//! A library of shader programs loaded from files, so shaders can be changed without building the
+//! engine again and even while it runs.
+//!
+//! A program called `name` is made from `name.vert` and `name.frag` in the library's directory,
+//! and `name.geom` when there is one. The sources can pull in other files with
+//! `#include "file"`, looked up next to the file doing the including. Each file is only included
+//! once per shader.
+//!
+//! Defines can be set for the whole library or given when asking for a program, they are put in
+//! right after the `#version` line. The same program asked for with different defines is compiled
+//! once for each set. `#line` directives keep the line numbers in compile errors right (counted
+//! the way GLSL 1.10 does): source string 0 is the shader's own file and the included files are
+//! numbered in the order they were first included, which the error message lists.
+
+use std::collections::HashMap;
+use std::old_io::{File, IoResult};
+use std::old_io::fs;
+use std::sync::Arc;
+
+use glium::Display;
+use glium::program::Program;
+
+use glium;
+
+/// The stages a program can have, with the extension of their files.
+const STAGES: [(&'static str, &'static str); 3] = [
+    ("vertex", "vert"),
+    ("fragment", "frag"),
+    ("geometry", "geom"),
+];
+
+/// A compiled program and what it was built from.
+struct Entry {
+    name:           String,
+    defines:        Vec<(String, String)>,
+    program:        Arc<Program>,
+    /// every file read for the program with its modification time, missing files (like an
+    /// optional geometry shader) have a time of zero
+    files:          Vec<(Path, u64)>,
+}
+
+/// Loads, caches and reloads shader programs.
+pub struct ShaderLibrary {
+    display:        Display,
+    root:           Path,
+    defines:        Vec<(String, String)>,
+    programs:       HashMap<String, Entry>,
+}
+
+impl ShaderLibrary {
+    /// Return a library loading the shaders from the directory `root`.
+    pub fn new(display: &Display, root: &str) -> ShaderLibrary {
+        ShaderLibrary {
+            display:        display.clone(),
+            root:           Path::new(root),
+            defines:        Vec::new(),
+            programs:       HashMap::new(),
+        }
+    }
+
+    /// Set a define for every program compiled from now on. Programs already compiled keep the
+    /// old value until they are reloaded.
+    pub fn define(&mut self, name: &str, value: &str) {
+        self.defines.retain(|d| d.0.as_slice() != name);
+        self.defines.push((String::from_str(name), String::from_str(value)));
+    }
+
+    /// Return the program called `name`, compiling it if it is not in the cache.
+    pub fn get(&mut self, name: &str) -> Result<Arc<Program>, String> {
+        self.get_with(name, &[])
+    }
+
+    /// Return the program called `name` compiled with extra defines, which win over the ones
+    /// of the library.
+    pub fn get_with(&mut self, name: &str, defines: &[(&str, &str)]) -> Result<Arc<Program>, String> {
+        let mut all: Vec<(String, String)> = self.defines.iter().filter(|d| {
+            !defines.iter().any(|e| e.0 == d.0.as_slice())
+        }).map(|d| d.clone()).collect();
+        for d in defines.iter() {
+            all.push((String::from_str(d.0), String::from_str(d.1)));
+        }
+
+        let key = cache_key(name, all.as_slice());
+        if let Some(entry) = self.programs.get(&key) {
+            return Ok(entry.program.clone());
+        }
+
+        let entry = try!(self.compile(name, all));
+        let program = entry.program.clone();
+        self.programs.insert(key, entry);
+        Ok(program)
+    }
+
+    /// Compile the programs again whose files changed since they were compiled and return the
+    /// name of each along with the new program, or the error that kept it from compiling. A
+    /// program that fails keeps its old version in the cache, so a mistake while editing does
+    /// not take anything off the screen. Objects holding a program need to be given the new one.
+    pub fn reload_changed(&mut self) -> Vec<(String, Result<Arc<Program>, String>)> {
+        let mut changed: Vec<String> = Vec::new();
+        for (key, entry) in self.programs.iter() {
+            if entry.files.iter().any(|f| modified(&f.0) != f.1) {
+                changed.push(key.clone());
+            }
+        }
+
+        let mut results: Vec<(String, Result<Arc<Program>, String>)> = Vec::new();
+        for key in changed.into_iter() {
+            let (name, defines) = {
+                let entry = self.programs.get(&key).unwrap();
+                (entry.name.clone(), entry.defines.clone())
+            };
+
+            match self.compile(name.as_slice(), defines) {
+                Ok(entry) => {
+                    results.push((name, Ok(entry.program.clone())));
+                    self.programs.insert(key, entry);
+                },
+                Err(e) => {
+                    // take the new times so the error is only reported once per change
+                    let entry = self.programs.get_mut(&key).unwrap();
+                    for f in entry.files.iter_mut() {
+                        f.1 = modified(&f.0);
+                    }
+                    results.push((name, Err(e)));
+                },
+            }
+        }
+
+        results
+    }
+
+    /// Forget every compiled program, so they are compiled again the next time they are asked
+    /// for.
+    pub fn clear(&mut self) {
+        self.programs.clear();
+    }
+
+    fn compile(&self, name: &str, defines: Vec<(String, String)>) -> Result<Entry, String> {
+        let mut files: Vec<(Path, u64)> = Vec::new();
+        let mut sources: Vec<Option<String>> = Vec::new();
+        let mut listing = String::new();
+
+        for &(stage, extension) in STAGES.iter() {
+            let path = self.root.join(format!("{}.{}", name, extension));
+            let time = modified(&path);
+            files.push((path.clone(), time));
+
+            if time == 0 && stage == "geometry" {
+                sources.push(Option::None);
+                continue;
+            }
+
+            let mut included: Vec<Path> = Vec::new();
+            let source = try!(preprocess(&path, defines.as_slice(), &mut included));
+            if included.len() > 0 {
+                listing.push_str(format!("\n{} shader sources: 0 {}", stage, path.display()).as_slice());
+                for (i, p) in included.iter().enumerate() {
+                    listing.push_str(format!(", {} {}", i + 1, p.display()).as_slice());
+                }
+            }
+            for p in included.into_iter() {
+                let time = modified(&p);
+                files.push((p, time));
+            }
+            sources.push(Option::Some(source));
+        }
+
+        let program = glium::Program::from_source(&self.display,
+            sources[0].as_ref().unwrap().as_slice(),
+            sources[1].as_ref().unwrap().as_slice(),
+            sources[2].as_ref().map(|s| s.as_slice())
+        );
+
+        match program {
+            Ok(program) => Ok(Entry {
+                name:       String::from_str(name),
+                defines:    defines,
+                program:    Arc::new(program),
+                files:      files,
+            }),
+            Err(e) => Err(format!("{}: could not compile: {:?}{}", name, e, listing)),
+        }
+    }
+}
+
+/// Return the source of a shader with the defines put in and the includes replaced by the files
+/// they name. The included files are added to `included`.
+fn preprocess(path: &Path, defines: &[(String, String)], included: &mut Vec<Path>) -> Result<String, String> {
+    let text = try!(read(path));
+    let mut out = String::new();
+    let mut version = false;
+
+    // the defines go after the #version line, which has to come first, or at the top without one
+    let has_version = text.lines().any(|l| l.trim_left().starts_with("#version"));
+    if !has_version {
+        push_defines(&mut out, defines);
+        out.push_str("#line 0 0\n");
+    }
+
+    for (n, line) in text.lines().enumerate() {
+        let trimmed = line.trim_left();
+
+        if trimmed.starts_with("#version") && !version {
+            version = true;
+            out.push_str(line);
+            out.push_str("\n");
+            push_defines(&mut out, defines);
+            out.push_str(format!("#line {} 0\n", n + 1).as_slice());
+            continue;
+        }
+
+        if trimmed.starts_with("#include") {
+            let file = try!(include_name(trimmed).ok_or_else(|| {
+                format!("{}:{}: expected #include \"file\"", path.display(), n + 1)
+            }));
+            try!(include(&path.dir_path().join(file), &mut out, included, 1));
+            out.push_str(format!("#line {} 0\n", n + 1).as_slice());
+            continue;
+        }
+
+        out.push_str(line);
+        out.push_str("\n");
+    }
+
+    Ok(out)
+}
+
+/// Put the text of an included file into `out`, following its own includes. A file already
+/// included is skipped, which also stops files from including each other forever.
+fn include(path: &Path, out: &mut String, included: &mut Vec<Path>, depth: usize) -> Result<(), String> {
+    if included.contains(path) {
+        return Ok(());
+    }
+    if depth > 32 {
+        return Err(format!("{}: includes nested too deep", path.display()));
+    }
+
+    let text = try!(read(path));
+    included.push(path.clone());
+    let number = included.len();
+
+    out.push_str(format!("#line 0 {}\n", number).as_slice());
+    for (n, line) in text.lines().enumerate() {
+        let trimmed = line.trim_left();
+        if trimmed.starts_with("#include") {
+            let file = try!(include_name(trimmed).ok_or_else(|| {
+                format!("{}:{}: expected #include \"file\"", path.display(), n + 1)
+            }));
+            try!(include(&path.dir_path().join(file), out, included, depth + 1));
+            out.push_str(format!("#line {} {}\n", n + 1, number).as_slice());
+            continue;
+        }
+        out.push_str(line);
+        out.push_str("\n");
+    }
+
+    Ok(())
+}
+
+/// Return the file name of an `#include "file"` line.
+fn include_name(line: &str) -> Option<&str> {
+    let rest = (&line["#include".len()..]).trim();
+    if rest.len() >= 2 && rest.starts_with("\"") && rest.ends_with("\"") {
+        Option::Some(&rest[1..rest.len() - 1])
+    } else {
+        Option::None
+    }
+}
+
+fn push_defines(out: &mut String, defines: &[(String, String)]) {
+    for d in defines.iter() {
+        out.push_str(format!("#define {} {}\n", d.0, d.1).as_slice());
+    }
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    let result: IoResult<String> = File::open(path).and_then(|mut f| f.read_to_string());
+    result.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Return the modification time of a file, or zero if it can not be found.
+fn modified(path: &Path) -> u64 {
+    fs::stat(path).map(|s| s.modified).unwrap_or(0)
+}
+
+fn cache_key(name: &str, defines: &[(String, String)]) -> String {
+    let mut key = String::from_str(name);
+    for d in defines.iter() {
+        key.push_str(format!(" {}={}", d.0, d.1).as_slice());
+    }
+    key
+}
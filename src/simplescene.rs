@@ -1,5 +1,6 @@
 //! It is called simple scene because I envisioned it being easy to work with, but still powerful 
-//! enough for my needs. It supports loading from the simple scene format. 
+//! enough for my needs. It supports loading from the simple scene format. Scenes in the glTF
+//! format can be loaded into the same structures using the `gltf` module.
 //!
 //! At this time I create the simple scene format for my custom Blender export addon. It takes
 //! the blender scenes and does a rough export of everything. This gives me the power to easily
@@ -8,7 +9,8 @@
 //! at some point support converting the simple scene format into a binary format instead of its
 //! current textual format (you can open, read, and edit using a text editor). The binary format
 //! would be capable of being loaded faster and may support partial loading (only loading what
-//! is needed), but that is something for the future.
+//! is needed), but that is something for the future. For now `write_binary` and `from_binary`
+//! give a binary form that loads fast but has to be loaded as a whole.
 //!
 //! If you want to get something loaded from the simple scene into the engine you should checkout
 //! the `drawableobject` module and specifically the `DrawableObject` implementation. It currently
@@ -22,28 +24,253 @@ use cgmath;
 use std::sync::Arc;
 use std::sync::Mutex;
 
+use mesh::Mesh;
+use bounds::{Aabb, BoundingSphere};
+use raycast::{Raycaster, RayHit};
+
 /// Represents a single object. The object may or may not have data depending on its type.
 pub struct SimpleSceneObject {
     pub vertices:       Vec<cgmath::Vector3<f32>>,
+    /// per-vertex normals, empty if the export did not include them
+    pub normals:        Vec<cgmath::Vector3<f32>>,
+    /// per-vertex texture coordinates, empty if the export did not include them
+    pub uvs:            Vec<cgmath::Vector2<f32>>,
+    /// per-vertex colors in the zero to one range, empty if the source did not have them
+    pub colors:         Vec<cgmath::Vector3<f32>>,
     pub scale:          cgmath::Vector3<f32>,
     pub triangles:      Vec<[u16;3]>,
     pub quads:          Vec<[u16;4]>,
     pub name:           String,
     pub typ:            String,
     pub groups:         Vec<String>,
+    /// name of the material in `SimpleSceneFile::materials` used by the object
+    pub material:       Option<String>,
+    /// per-vertex joint indices into the joints of `skin`
+    pub joints:         Vec<[u16;4]>,
+    /// per-vertex joint weights that go with `joints`
+    pub weights:        Vec<[f32;4]>,
+    pub skin:           Option<SimpleSceneSkin>,
     pub location:       cgmath::Vector3<f32>,
     pub rotation:       cgmath::Quaternion<f32>,
     pub parent:         Option<Arc<Mutex<SimpleSceneObject>>>,
     pub child:          Vec<Arc<Mutex<SimpleSceneObject>>>,
+    /// box around the vertices in object space, kept up to date by `update_bounds`
+    pub bounds:         Aabb,
+    /// sphere around the vertices in object space, kept up to date by `update_bounds`
+    pub sphere:         BoundingSphere,
 }
 
-/// Represents any data, and currently only objects, loaded from the scene.
+impl SimpleSceneObject {
+    /// Return an empty object with an identity transform.
+    pub fn new() -> SimpleSceneObject {
+        SimpleSceneObject {
+            vertices:   Vec::new(),
+            normals:    Vec::new(),
+            uvs:        Vec::new(),
+            colors:     Vec::new(),
+            triangles:  Vec::new(),
+            quads:      Vec::new(),
+            name:       String::new(),
+            typ:        String::new(),
+            groups:     Vec::new(),
+            material:   Option::None,
+            joints:     Vec::new(),
+            weights:    Vec::new(),
+            skin:       Option::None,
+            scale:      cgmath::Vector3::new(1.0, 1.0, 1.0),
+            location:   cgmath::Vector3::new(0.0, 0.0, 0.0),
+            rotation:   cgmath::Quaternion::from_sv(1.0, cgmath::Vector3::new(0.0, 0.0, 0.0)),
+            parent:     Option::None,
+            child:      Vec::new(),
+            bounds:     Aabb::empty(),
+            sphere:     BoundingSphere::empty(),
+        }
+    }
+
+    /// Return the transformation from object space into the parent's space.
+    pub fn local_matrix(&self) -> cgmath::Matrix4<f32> {
+        use cgmath::ToMatrix4;
+
+        let s = cgmath::Matrix4::new(
+            self.scale.x, 0.0, 0.0, 0.0,
+            0.0, self.scale.y, 0.0, 0.0,
+            0.0, 0.0, self.scale.z, 0.0,
+            0.0, 0.0, 0.0, 1.0
+        );
+
+        cgmath::Matrix4::from_translation(&self.location) * self.rotation.to_matrix4() * s
+    }
+
+    /// Return the transformation from object space into world space by walking
+    /// up the parent chain.
+    pub fn world_matrix(&self) -> cgmath::Matrix4<f32> {
+        match self.parent {
+            Some(ref parent) => parent.lock().unwrap().world_matrix() * self.local_matrix(),
+            None => self.local_matrix(),
+        }
+    }
+
+    /// Work out `bounds` and `sphere` again, which has to be done after changing the vertices.
+    /// The loaders do this for you.
+    pub fn update_bounds(&mut self) {
+        self.bounds = Aabb::from_points(self.vertices.as_slice());
+        self.sphere = BoundingSphere::from_points(self.vertices.as_slice());
+    }
+
+    /// Return the box around the object in world space.
+    pub fn world_bounds(&self) -> Aabb {
+        self.bounds.transform(&self.world_matrix())
+    }
+
+    /// Return the sphere around the object in world space.
+    pub fn world_sphere(&self) -> BoundingSphere {
+        self.sphere.transform(&self.world_matrix())
+    }
+
+    /// Set the location, rotation and scale from a transformation made of only those three (no
+    /// shear or projection).
+    pub fn set_matrix(&mut self, m: &cgmath::Matrix4<f32>) {
+        use cgmath::EuclideanVector;
+
+        let x = m.x.truncate();
+        let y = m.y.truncate();
+        let z = m.z.truncate();
+        self.location = m.w.truncate();
+        self.scale = cgmath::Vector3::new(x.length(), y.length(), z.length());
+        self.rotation = quaternion_from_axes(x.normalize(), y.normalize(), z.normalize());
+    }
+
+    /// Split every quad into two triangles.
+    pub fn triangulate(&mut self) {
+        for q in self.quads.iter() {
+            self.triangles.push([q[0], q[1], q[2]]);
+            self.triangles.push([q[0], q[2], q[3]]);
+        }
+        self.quads.clear();
+    }
+
+    /// Weld the vertices within `epsilon`, remove degenerate faces and drop the unused vertices,
+    /// see `Mesh::cleanup`.
+    pub fn cleanup(&mut self, epsilon: f32) {
+        let mut mesh = Mesh::from_simplescene(self);
+        mesh.cleanup(epsilon);
+        mesh.to_simplescene(self);
+    }
+}
+
+/// Return the rotation that takes the unit axes onto `x`, `y` and `z`.
+pub fn quaternion_from_axes(x: cgmath::Vector3<f32>, y: cgmath::Vector3<f32>, z: cgmath::Vector3<f32>) -> cgmath::Quaternion<f32> {
+    use std::num::Float;
+
+    let trace = x.x + y.y + z.z;
+
+    if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        cgmath::Quaternion::new(0.25 * s, (y.z - z.y) / s, (z.x - x.z) / s, (x.y - y.x) / s)
+    } else if x.x > y.y && x.x > z.z {
+        let s = (1.0 + x.x - y.y - z.z).sqrt() * 2.0;
+        cgmath::Quaternion::new((y.z - z.y) / s, 0.25 * s, (y.x + x.y) / s, (z.x + x.z) / s)
+    } else if y.y > z.z {
+        let s = (1.0 + y.y - x.x - z.z).sqrt() * 2.0;
+        cgmath::Quaternion::new((z.x - x.z) / s, (y.x + x.y) / s, 0.25 * s, (z.y + y.z) / s)
+    } else {
+        let s = (1.0 + z.z - x.x - y.y).sqrt() * 2.0;
+        cgmath::Quaternion::new((x.y - y.x) / s, (z.x + x.z) / s, (z.y + y.z) / s, 0.25 * s)
+    }
+}
+
+/// The surface properties of an object. These follow the metallic-roughness model because that
+/// is what both Blender and glTF hand us.
+#[derive(Clone)]
+pub struct SimpleSceneMaterial {
+    pub name:           String,
+    pub diffuse:        [f32;4],
+    pub metallic:       f32,
+    pub roughness:      f32,
+    /// path of the diffuse (base color) texture relative to the scene file
+    pub texture:        Option<String>,
+}
+
+impl SimpleSceneMaterial {
+    /// Return a plain white material.
+    pub fn new(name: &str) -> SimpleSceneMaterial {
+        SimpleSceneMaterial {
+            name:       String::from_str(name),
+            diffuse:    [1.0, 1.0, 1.0, 1.0],
+            metallic:   0.0,
+            roughness:  1.0,
+            texture:    Option::None,
+        }
+    }
+}
+
+/// Binds the vertices of an object to a set of joint objects.
+#[derive(Clone)]
+pub struct SimpleSceneSkin {
+    /// names of the joint objects, `SimpleSceneObject::joints` index into this
+    pub joints:         Vec<String>,
+    /// per joint matrix taking the mesh into the joint's space at bind time
+    pub inverse_bind:   Vec<cgmath::Matrix4<f32>>,
+}
+
+/// The property of an object that an animation channel drives.
+#[derive(Copy, Clone, PartialEq)]
+pub enum SimpleSceneProperty {
+    Location,
+    Rotation,
+    Scale,
+}
+
+/// How the values between two keyframes are found.
+#[derive(Copy, Clone, PartialEq)]
+pub enum SimpleSceneInterpolation {
+    Step,
+    Linear,
+    /// each key has an in tangent, a value and an out tangent stored one after another
+    CubicSpline,
+}
+
+/// Keyframes for a single property of a single object.
+pub struct SimpleSceneChannel {
+    /// name of the object being animated
+    pub target:         String,
+    pub property:       SimpleSceneProperty,
+    pub interpolation:  SimpleSceneInterpolation,
+    /// key times in seconds
+    pub times:          Vec<f32>,
+    /// key values, locations and scales only use the first three components and rotations
+    /// are stored as `[x, y, z, w]`
+    pub values:         Vec<[f32;4]>,
+}
+
+/// A named set of channels that play together.
+pub struct SimpleSceneAnimation {
+    pub name:           String,
+    pub channels:       Vec<SimpleSceneChannel>,
+}
+
+/// Represents any data loaded from the scene.
 pub struct SimpleSceneFile {
     pub path:           Path,
     pub objects:        Vec<Arc<Mutex<SimpleSceneObject>>>,
+    pub materials:      Vec<SimpleSceneMaterial>,
+    pub animations:     Vec<SimpleSceneAnimation>,
 }
 
 impl SimpleSceneFile {
+    /// Return the closest object hit by a ray in world space, see `raycast::Raycaster`. This
+    /// builds the search structures for every call, keep a `Raycaster` around to cast many rays.
+    pub fn raycast(&self, origin: &cgmath::Vector3<f32>, direction: &cgmath::Vector3<f32>) -> Option<RayHit> {
+        Raycaster::new(self).raycast(origin, direction)
+    }
+
+    /// Work out the bounds of every object again, see `SimpleSceneObject::update_bounds`.
+    pub fn update_bounds(&self) {
+        for object in self.objects.iter() {
+            object.lock().unwrap().update_bounds();
+        }
+    }
+
     /// Return a reference to a object by it's name. This is the actual object name set in Blender.
     pub fn find(&self, name: &str) -> Option<&Arc<Mutex<SimpleSceneObject>>> {
         for obj in self.objects.iter() {
@@ -55,6 +282,11 @@ impl SimpleSceneFile {
         Option::None
     }
 
+    /// Return a reference to a material by it's name.
+    pub fn find_material(&self, name: &str) -> Option<&SimpleSceneMaterial> {
+        self.materials.iter().find(|m| m.name.as_slice().eq(name))
+    }
+
     /// Return a simple scene instance by loading it from a file source.
     pub fn from_file(source: &str) -> SimpleSceneFile {
         use cgmath::ToRad;
@@ -72,25 +304,14 @@ impl SimpleSceneFile {
 
         let mut objects: Vec<Arc<Mutex<SimpleSceneObject>>> = Vec::new();
         let mut relocs: Vec<(Arc<Mutex<SimpleSceneObject>>, String)> = Vec::new();
+        let mut materials: Vec<SimpleSceneMaterial> = Vec::new();
 
         for line in *lines_a {
             let mut parts = line.split_str(" ");
             match parts.next().unwrap() {
                 "start" => match parts.next().unwrap() {
                     "object" => {
-                        let mut _object = Arc::new(Mutex::new(SimpleSceneObject {
-                            vertices:   Vec::new(),
-                            triangles:  Vec::new(),
-                            quads:      Vec::new(),
-                            name:       String::new(),
-                            typ:        String::new(),
-                            groups:     Vec::new(),
-                            scale:      cgmath::Vector3::new(0.0, 0.0, 0.0),
-                            location:   cgmath::Vector3::new(0.0, 0.0, 0.0),
-                            rotation:   cgmath::Quaternion::from_sv(0.0, cgmath::Vector3::new(0.0, 0.0, 0.0)),
-                            parent:     Option::None,
-                            child:      Vec::new(),
-                        }));
+                        let mut _object = Arc::new(Mutex::new(SimpleSceneObject::new()));
                         {
                             let mut object = _object.lock().unwrap();
                             for line in *lines_b {
@@ -124,12 +345,14 @@ impl SimpleSceneFile {
                                                 if line.eq("end vertex") {
                                                     break;
                                                 }
-                                                let mut parts = line.split_str(" ");
-                                                object.vertices.push(cgmath::Vector3::new(
-                                                    parts.next().unwrap().parse::<f32>().unwrap(),
-                                                    parts.next().unwrap().parse::<f32>().unwrap(),
-                                                    parts.next().unwrap().parse::<f32>().unwrap()
-                                                ));
+                                                let parts: Vec<f32> = line.split_str(" ").map(
+                                                    |p| p.parse::<f32>().unwrap()
+                                                ).collect();
+                                                object.vertices.push(cgmath::Vector3::new(parts[0], parts[1], parts[2]));
+                                                // newer exports follow the position with the vertex normal
+                                                if parts.len() >= 6 {
+                                                    object.normals.push(cgmath::Vector3::new(parts[3], parts[4], parts[5]));
+                                                }
                                             }
                                         },
                                         "polygon" => {
@@ -137,19 +360,12 @@ impl SimpleSceneFile {
                                                 if line.eq("end polygon") {
                                                     break;
                                                 }
-                                                let mut parts = line.split_str(" ");
-                                                match parts.size_hint().0 {
-                                                    3 => object.triangles.push([
-                                                        parts.next().unwrap().parse::<u16>().unwrap(),
-                                                        parts.next().unwrap().parse::<u16>().unwrap(),
-                                                        parts.next().unwrap().parse::<u16>().unwrap(),
-                                                    ]),
-                                                    4 => object.quads.push([
-                                                        parts.next().unwrap().parse::<u16>().unwrap(),
-                                                        parts.next().unwrap().parse::<u16>().unwrap(),
-                                                        parts.next().unwrap().parse::<u16>().unwrap(),
-                                                        parts.next().unwrap().parse::<u16>().unwrap(),
-                                                    ]),
+                                                let parts: Vec<u16> = line.split_str(" ").map(
+                                                    |p| p.parse::<u16>().unwrap()
+                                                ).collect();
+                                                match parts.len() {
+                                                    3 => object.triangles.push([parts[0], parts[1], parts[2]]),
+                                                    4 => object.quads.push([parts[0], parts[1], parts[2], parts[3]]),
                                                     _ => panic!("unsupported polygon type"),
                                                 }
                                             }
@@ -163,14 +379,55 @@ impl SimpleSceneFile {
                                                 object.groups.push(String::from_str(parts.next().unwrap()));
                                             }
                                         },
+                                        "uv" => {
+                                            for line in *lines_c {
+                                                if line.eq("end uv") {
+                                                    break;
+                                                }
+                                                let parts: Vec<f32> = line.split_str(" ").map(
+                                                    |p| p.parse::<f32>().unwrap()
+                                                ).collect();
+                                                object.uvs.push(cgmath::Vector2::new(parts[0], parts[1]));
+                                            }
+                                        },
+                                        "color" => {
+                                            for line in *lines_c {
+                                                if line.eq("end color") {
+                                                    break;
+                                                }
+                                                let parts: Vec<f32> = line.split_str(" ").map(
+                                                    |p| p.parse::<f32>().unwrap()
+                                                ).collect();
+                                                object.colors.push(cgmath::Vector3::new(parts[0], parts[1], parts[2]));
+                                            }
+                                        },
                                         _ => continue,
                                     },
+                                    "material" => object.material = Option::Some(String::from_str(parts.next().unwrap())),
                                     _ => continue,
                                 }
                             }
                         }
                         objects.push(_object);
                     },
+                    "material" => {
+                        let mut material = SimpleSceneMaterial::new("");
+                        for line in *lines_b {
+                            let parts: Vec<&str> = line.split_str(" ").collect();
+                            match parts[0] {
+                                "name" => material.name = String::from_str(parts[1]),
+                                "diffuse" => for i in 0..4 {
+                                    material.diffuse[i] = parts[i + 1].parse::<f32>().unwrap();
+                                },
+                                "metallic" => material.metallic = parts[1].parse::<f32>().unwrap(),
+                                "roughness" => material.roughness = parts[1].parse::<f32>().unwrap(),
+                                "texture" => material.texture = Option::Some(String::from_str(parts[1])),
+                                "end" => break,
+                                _ => continue,
+                            }
+                        }
+                        materials.push(material);
+                    },
                     _ => continue,
                 },
                 _ => continue,
@@ -180,8 +437,12 @@ impl SimpleSceneFile {
         let scene = SimpleSceneFile {
             path:           path,
             objects:        objects,
+            materials:      materials,
+            animations:     Vec::new(),
         };
 
+        scene.update_bounds();
+
         // Go through and add parent and children objects. For each
         // unresolved parent we resolve it by finding that object.
         // Then we add it as a parent, and add the object needing the
@@ -200,4 +461,369 @@ impl SimpleSceneFile {
 
         scene
     }
+
+    /// Write the scene out in the simple scene text format so that `from_file` can load it again.
+    /// Skins and animations have no place in the text format and are not written.
+    pub fn write_file(&self, path: &str) {
+        use std::num::Float;
+        use std::old_io::{File, BufferedWriter, Writer};
+
+        let mut w = BufferedWriter::new(File::create(&Path::new(path)).unwrap());
+
+        for m in self.materials.iter() {
+            let d = m.diffuse;
+            writeln!(&mut w, "start material").unwrap();
+            writeln!(&mut w, "name {}", m.name).unwrap();
+            writeln!(&mut w, "diffuse {} {} {} {}", d[0], d[1], d[2], d[3]).unwrap();
+            writeln!(&mut w, "metallic {}", m.metallic).unwrap();
+            writeln!(&mut w, "roughness {}", m.roughness).unwrap();
+            if let Some(ref texture) = m.texture {
+                writeln!(&mut w, "texture {}", texture).unwrap();
+            }
+            writeln!(&mut w, "end material").unwrap();
+        }
+
+        for o in self.objects.iter() {
+            let o = o.lock().unwrap();
+
+            // The rotation is stored as an axis and an angle in degrees.
+            let q = o.rotation;
+            let qs = if q.s > 1.0 { 1.0 } else if q.s < -1.0 { -1.0 } else { q.s };
+            let angle = 2.0 * qs.acos();
+            let sin = (1.0 - qs * qs).sqrt();
+            let (ax, ay, az) = if sin < 1e-6 { (1.0, 0.0, 0.0) } else { (q.v.x / sin, q.v.y / sin, q.v.z / sin) };
+
+            writeln!(&mut w, "start object").unwrap();
+            writeln!(&mut w, "location {} {} {}", o.location.x, o.location.y, o.location.z).unwrap();
+            writeln!(&mut w, "scale {} {} {}", o.scale.x, o.scale.y, o.scale.z).unwrap();
+            writeln!(&mut w, "rotation {} {} {} {}", ax, ay, az, angle.to_degrees()).unwrap();
+
+            if o.vertices.len() > 0 {
+                let normals = o.normals.len() == o.vertices.len();
+                writeln!(&mut w, "start vertex").unwrap();
+                for (i, v) in o.vertices.iter().enumerate() {
+                    if normals {
+                        let n = o.normals[i];
+                        writeln!(&mut w, "{} {} {} {} {} {}", v.x, v.y, v.z, n.x, n.y, n.z).unwrap();
+                    } else {
+                        writeln!(&mut w, "{} {} {}", v.x, v.y, v.z).unwrap();
+                    }
+                }
+                writeln!(&mut w, "end vertex").unwrap();
+            }
+
+            if o.uvs.len() > 0 {
+                writeln!(&mut w, "start uv").unwrap();
+                for t in o.uvs.iter() {
+                    writeln!(&mut w, "{} {}", t.x, t.y).unwrap();
+                }
+                writeln!(&mut w, "end uv").unwrap();
+            }
+
+            if o.colors.len() > 0 {
+                writeln!(&mut w, "start color").unwrap();
+                for c in o.colors.iter() {
+                    writeln!(&mut w, "{} {} {}", c.x, c.y, c.z).unwrap();
+                }
+                writeln!(&mut w, "end color").unwrap();
+            }
+
+            if o.triangles.len() > 0 || o.quads.len() > 0 {
+                writeln!(&mut w, "start polygon").unwrap();
+                for t in o.triangles.iter() {
+                    writeln!(&mut w, "{} {} {}", t[0], t[1], t[2]).unwrap();
+                }
+                for q in o.quads.iter() {
+                    writeln!(&mut w, "{} {} {} {}", q[0], q[1], q[2], q[3]).unwrap();
+                }
+                writeln!(&mut w, "end polygon").unwrap();
+            }
+
+            writeln!(&mut w, "start group").unwrap();
+            for g in o.groups.iter() {
+                writeln!(&mut w, "{}", g).unwrap();
+            }
+            writeln!(&mut w, "end group").unwrap();
+
+            if let Some(ref parent) = o.parent {
+                writeln!(&mut w, "parent {}", parent.lock().unwrap().name).unwrap();
+            }
+            if let Some(ref material) = o.material {
+                writeln!(&mut w, "material {}", material).unwrap();
+            }
+            writeln!(&mut w, "type {}", o.typ).unwrap();
+            writeln!(&mut w, "name {}", o.name).unwrap();
+            writeln!(&mut w, "end object").unwrap();
+        }
+
+        w.flush().unwrap();
+    }
+
+    /// Write the scene in the binary form of the simple scene format. It holds the same data as
+    /// the text format but loads a lot faster since nothing has to be parsed.
+    pub fn write_binary(&self, path: &str) {
+        use std::old_io::{File, BufferedWriter, Writer};
+
+        let mut w = BufferedWriter::new(File::create(&Path::new(path)).unwrap());
+
+        w.write_all(BINARY_MAGIC).unwrap();
+        w.write_le_u32(BINARY_VERSION).unwrap();
+
+        w.write_le_u32(self.materials.len() as u32).unwrap();
+        for m in self.materials.iter() {
+            write_str(&mut w, m.name.as_slice());
+            for d in m.diffuse.iter() {
+                w.write_le_f32(*d).unwrap();
+            }
+            w.write_le_f32(m.metallic).unwrap();
+            w.write_le_f32(m.roughness).unwrap();
+            write_str(&mut w, m.texture.as_ref().map(|t| t.as_slice()).unwrap_or(""));
+        }
+
+        w.write_le_u32(self.objects.len() as u32).unwrap();
+        for o in self.objects.iter() {
+            let o = o.lock().unwrap();
+
+            write_str(&mut w, o.name.as_slice());
+            write_str(&mut w, o.typ.as_slice());
+            match o.parent {
+                Some(ref parent) => write_str(&mut w, parent.lock().unwrap().name.as_slice()),
+                None => write_str(&mut w, ""),
+            }
+            write_str(&mut w, o.material.as_ref().map(|m| m.as_slice()).unwrap_or(""));
+
+            let r = o.rotation;
+            for f in [o.location.x, o.location.y, o.location.z, r.s, r.v.x, r.v.y, r.v.z, o.scale.x, o.scale.y, o.scale.z].iter() {
+                w.write_le_f32(*f).unwrap();
+            }
+
+            for list in [&o.vertices, &o.normals, &o.colors].iter() {
+                w.write_le_u32(list.len() as u32).unwrap();
+                for v in list.iter() {
+                    w.write_le_f32(v.x).unwrap();
+                    w.write_le_f32(v.y).unwrap();
+                    w.write_le_f32(v.z).unwrap();
+                }
+            }
+
+            w.write_le_u32(o.uvs.len() as u32).unwrap();
+            for t in o.uvs.iter() {
+                w.write_le_f32(t.x).unwrap();
+                w.write_le_f32(t.y).unwrap();
+            }
+
+            w.write_le_u32(o.triangles.len() as u32).unwrap();
+            for t in o.triangles.iter() {
+                for i in t.iter() {
+                    w.write_le_u16(*i).unwrap();
+                }
+            }
+
+            w.write_le_u32(o.quads.len() as u32).unwrap();
+            for q in o.quads.iter() {
+                for i in q.iter() {
+                    w.write_le_u16(*i).unwrap();
+                }
+            }
+
+            w.write_le_u32(o.groups.len() as u32).unwrap();
+            for g in o.groups.iter() {
+                write_str(&mut w, g.as_slice());
+            }
+        }
+
+        w.flush().unwrap();
+    }
+
+    /// Return a simple scene instance by loading it from a file written by `write_binary`.
+    pub fn from_binary(source: &str) -> SimpleSceneFile {
+        use std::old_io::{File, BufferedReader, Reader};
+
+        let path = Path::new(source);
+        let mut r = BufferedReader::new(File::open(&path).unwrap());
+
+        if r.read_exact(4).unwrap().as_slice() != BINARY_MAGIC {
+            panic!("not a binary simple scene file");
+        }
+
+        let version = r.read_le_u32().unwrap();
+        if version != BINARY_VERSION {
+            panic!("unsupported binary simple scene version {}", version);
+        }
+
+        let mut materials: Vec<SimpleSceneMaterial> = Vec::new();
+        for _ in 0..r.read_le_u32().unwrap() {
+            let mut m = SimpleSceneMaterial::new(read_str(&mut r).as_slice());
+            for i in 0..4 {
+                m.diffuse[i] = r.read_le_f32().unwrap();
+            }
+            m.metallic = r.read_le_f32().unwrap();
+            m.roughness = r.read_le_f32().unwrap();
+            let texture = read_str(&mut r);
+            m.texture = if texture.len() > 0 { Option::Some(texture) } else { Option::None };
+            materials.push(m);
+        }
+
+        let mut objects: Vec<Arc<Mutex<SimpleSceneObject>>> = Vec::new();
+        let mut relocs: Vec<(Arc<Mutex<SimpleSceneObject>>, String)> = Vec::new();
+
+        for _ in 0..r.read_le_u32().unwrap() {
+            let mut o = SimpleSceneObject::new();
+
+            o.name = read_str(&mut r);
+            o.typ = read_str(&mut r);
+            let parent = read_str(&mut r);
+            let material = read_str(&mut r);
+            if material.len() > 0 {
+                o.material = Option::Some(material);
+            }
+
+            let f: Vec<f32> = (0..10).map(|_| r.read_le_f32().unwrap()).collect();
+            o.location = cgmath::Vector3::new(f[0], f[1], f[2]);
+            o.rotation = cgmath::Quaternion::new(f[3], f[4], f[5], f[6]);
+            o.scale = cgmath::Vector3::new(f[7], f[8], f[9]);
+
+            for list in [&mut o.vertices, &mut o.normals, &mut o.colors].iter_mut() {
+                for _ in 0..r.read_le_u32().unwrap() {
+                    let x = r.read_le_f32().unwrap();
+                    let y = r.read_le_f32().unwrap();
+                    let z = r.read_le_f32().unwrap();
+                    list.push(cgmath::Vector3::new(x, y, z));
+                }
+            }
+
+            for _ in 0..r.read_le_u32().unwrap() {
+                let x = r.read_le_f32().unwrap();
+                let y = r.read_le_f32().unwrap();
+                o.uvs.push(cgmath::Vector2::new(x, y));
+            }
+
+            for _ in 0..r.read_le_u32().unwrap() {
+                let a = r.read_le_u16().unwrap();
+                let b = r.read_le_u16().unwrap();
+                let c = r.read_le_u16().unwrap();
+                o.triangles.push([a, b, c]);
+            }
+
+            for _ in 0..r.read_le_u32().unwrap() {
+                let a = r.read_le_u16().unwrap();
+                let b = r.read_le_u16().unwrap();
+                let c = r.read_le_u16().unwrap();
+                let d = r.read_le_u16().unwrap();
+                o.quads.push([a, b, c, d]);
+            }
+
+            for _ in 0..r.read_le_u32().unwrap() {
+                o.groups.push(read_str(&mut r));
+            }
+
+            let o = Arc::new(Mutex::new(o));
+            if parent.len() > 0 {
+                relocs.push((o.clone(), parent));
+            }
+            objects.push(o);
+        }
+
+        let scene = SimpleSceneFile {
+            path:           path,
+            objects:        objects,
+            materials:      materials,
+            animations:     Vec::new(),
+        };
+
+        scene.update_bounds();
+
+        for pair in relocs.iter() {
+            let ref object = pair.0;
+            match scene.find(pair.1.as_slice()) {
+                Some(pobject) => {
+                    object.lock().unwrap().parent = Option::Some(pobject.clone());
+                    pobject.lock().unwrap().child.push(object.clone());
+                }
+                None => panic!("could not find parent"),
+            }
+        }
+
+        scene
+    }
+
+    /// Check the scene for data that would trip up the rest of the engine and return a
+    /// description of every problem found. An empty list means the scene is fine.
+    pub fn validate(&self) -> Vec<String> {
+        use std::num::Float;
+
+        let mut problems: Vec<String> = Vec::new();
+        let mut names: Vec<String> = Vec::new();
+
+        for o in self.objects.iter() {
+            let o = o.lock().unwrap();
+            let count = o.vertices.len();
+
+            if names.contains(&o.name) {
+                problems.push(format!("{}: more than one object has this name", o.name));
+            }
+            names.push(o.name.clone());
+
+            let finite = |v: &cgmath::Vector3<f32>| v.x.is_finite() && v.y.is_finite() && v.z.is_finite();
+            if !o.vertices.iter().all(|v| finite(v)) || !o.normals.iter().all(|v| finite(v)) {
+                problems.push(format!("{}: has vertices that are not finite", o.name));
+            }
+
+            if !finite(&o.location) || !finite(&o.scale) {
+                problems.push(format!("{}: has a transform that is not finite", o.name));
+            }
+
+            if o.normals.len() != 0 && o.normals.len() != count {
+                problems.push(format!("{}: has {} normals for {} vertices", o.name, o.normals.len(), count));
+            }
+
+            if o.uvs.len() != 0 && o.uvs.len() != count {
+                problems.push(format!("{}: has {} uvs for {} vertices", o.name, o.uvs.len(), count));
+            }
+
+            if o.colors.len() != 0 && o.colors.len() != count {
+                problems.push(format!("{}: has {} colors for {} vertices", o.name, o.colors.len(), count));
+            }
+
+            if o.weights.len() != o.joints.len() || (o.joints.len() != 0 && o.joints.len() != count) {
+                problems.push(format!("{}: has joints or weights that do not match the vertices", o.name));
+            }
+
+            let bad = o.triangles.iter().any(|t| t.iter().any(|i| *i as usize >= count)) ||
+                      o.quads.iter().any(|q| q.iter().any(|i| *i as usize >= count));
+            if bad {
+                problems.push(format!("{}: has polygons using vertices that do not exist", o.name));
+            }
+
+            if let Some(ref material) = o.material {
+                if self.find_material(material.as_slice()).is_none() {
+                    problems.push(format!("{}: uses the missing material {}", o.name, material));
+                }
+            }
+
+            if let Some(ref parent) = o.parent {
+                let found = self.objects.iter().any(|other| {
+                    &**other as *const Mutex<SimpleSceneObject> == &**parent as *const Mutex<SimpleSceneObject>
+                });
+                if !found {
+                    problems.push(format!("{}: has a parent that is not part of the scene", o.name));
+                }
+            }
+        }
+
+        problems
+    }
+}
+
+const BINARY_MAGIC: &'static [u8] = b"KSSB";
+const BINARY_VERSION: u32 = 1;
+
+fn write_str<W: ::std::old_io::Writer>(w: &mut W, s: &str) {
+    w.write_le_u32(s.len() as u32).unwrap();
+    w.write_str(s).unwrap();
+}
+
+fn read_str<R: ::std::old_io::Reader>(r: &mut R) -> String {
+    let len = r.read_le_u32().unwrap() as usize;
+    String::from_utf8(r.read_exact(len).unwrap()).unwrap()
 }
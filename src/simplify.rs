@@ -0,0 +1,508 @@
+//! Reduces the number of faces of a mesh with edge collapses ordered by the quadric error metric
+//! of Garland and Heckbert. Every vertex carries a quadric, the sum of the squared distances to
+//! the planes of the faces around it, and an edge collapse puts the remaining vertex where the
+//! summed quadric of both ends is lowest (out of the two ends and the middle of the edge). The
+//! per-vertex data is interpolated along the edge in the same way so it follows the position.
+//!
+//! Vertices on the boundary of the mesh and on seams (where the vertex is split because of the
+//! normals, texture coordinates and so on) are locked: an edge can collapse into them but they
+//! never move. That keeps the outline of open meshes and keeps the seams from tearing open. Run
+//! `Mesh::cleanup` first so that vertices that only look split get welded.
+//!
+//! The result is made of triangles, quads are split before simplifying.
+
+use std::collections::HashMap;
+
+use cgmath;
+use cgmath::{Vector, EuclideanVector};
+
+use mesh::{Mesh, position_key};
+use simplescene::SimpleSceneObject;
+
+/// A symmetric 4x4 matrix stored as its upper triangle.
+#[derive(Copy, Clone)]
+struct Quadric {
+    m:              [f64;10],
+}
+
+impl Quadric {
+    fn zero() -> Quadric {
+        Quadric { m: [0.0;10] }
+    }
+
+    /// Return the quadric of the plane `n . p + d = 0` scaled by `weight`.
+    fn plane(n: &cgmath::Vector3<f32>, d: f32, weight: f32) -> Quadric {
+        let (a, b, c, d, w) = (n.x as f64, n.y as f64, n.z as f64, d as f64, weight as f64);
+        Quadric { m: [
+            a * a * w, a * b * w, a * c * w, a * d * w,
+                       b * b * w, b * c * w, b * d * w,
+                                  c * c * w, c * d * w,
+                                             d * d * w,
+        ] }
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut m = self.m;
+        for i in 0..10 {
+            m[i] += other.m[i];
+        }
+        Quadric { m: m }
+    }
+
+    fn error(&self, v: &cgmath::Vector3<f32>) -> f64 {
+        let (x, y, z) = (v.x as f64, v.y as f64, v.z as f64);
+        let m = &self.m;
+        m[0] * x * x + 2.0 * m[1] * x * y + 2.0 * m[2] * x * z + 2.0 * m[3] * x
+            + m[4] * y * y + 2.0 * m[5] * y * z + 2.0 * m[6] * y
+            + m[7] * z * z + 2.0 * m[8] * z
+            + m[9]
+    }
+}
+
+/// A possible collapse of vertex `b` into vertex `a`, with `a` moving `t` of the way to `b`.
+struct Collapse {
+    cost:           f64,
+    a:              usize,
+    b:              usize,
+    t:              f32,
+}
+
+/// Working state of the simplification.
+struct Simplifier {
+    mesh:           Mesh,
+    triangles:      Vec<[usize;3]>,
+    removed:        Vec<bool>,
+    /// the triangles around every vertex, may include removed ones
+    around:         Vec<Vec<usize>>,
+    quadrics:       Vec<Quadric>,
+    locked:         Vec<bool>,
+    live:           usize,
+}
+
+/// Return a copy of the mesh simplified down to about `target` triangles. It stops early when
+/// no edge can be collapsed without folding the surface over, pinching it or moving a locked
+/// vertex.
+pub fn simplify(mesh: &Mesh, target: usize) -> Mesh {
+    let mut s = Simplifier::new(mesh);
+
+    while s.live > target {
+        if !s.pass(target) {
+            break;
+        }
+    }
+
+    s.finish()
+}
+
+/// Return simplified copies of an object, one for every ratio of the original triangle count
+/// (like `[0.5, 0.25, 0.125]`). Each one is simplified from the one before so the chain is
+/// consistent. They are named `<name>.lod<n>` starting at one and share the transform, parent
+/// and material of the object but are not added to the parent's children.
+pub fn lod_chain(object: &SimpleSceneObject, ratios: &[f32]) -> Vec<SimpleSceneObject> {
+    let mut mesh = Mesh::from_simplescene(object);
+    let faces = mesh.triangles.len() + mesh.quads.len() * 2;
+    let mut chain: Vec<SimpleSceneObject> = Vec::with_capacity(ratios.len());
+
+    for (n, ratio) in ratios.iter().enumerate() {
+        mesh = simplify(&mesh, (faces as f32 * *ratio) as usize);
+
+        let mut lod = SimpleSceneObject::new();
+        lod.name = format!("{}.lod{}", object.name, n + 1);
+        lod.typ = object.typ.clone();
+        lod.groups = object.groups.clone();
+        lod.material = object.material.clone();
+        lod.skin = object.skin.clone();
+        lod.scale = object.scale;
+        lod.location = object.location;
+        lod.rotation = object.rotation;
+        lod.parent = object.parent.clone();
+        mesh.to_simplescene(&mut lod);
+
+        chain.push(lod);
+    }
+
+    chain
+}
+
+impl Simplifier {
+    fn new(source: &Mesh) -> Simplifier {
+        let mut mesh = Mesh::new();
+        mesh.positions = source.positions.clone();
+        mesh.normals = source.normals.clone();
+        mesh.uvs = source.uvs.clone();
+        mesh.tangents = source.tangents.clone();
+        mesh.colors = source.colors.clone();
+        mesh.joints = source.joints.clone();
+        mesh.weights = source.weights.clone();
+
+        let mut triangles: Vec<[usize;3]> = source.triangles.iter().map(|t| {
+            [t[0] as usize, t[1] as usize, t[2] as usize]
+        }).collect();
+        for q in source.quads.iter() {
+            triangles.push([q[0] as usize, q[1] as usize, q[2] as usize]);
+            triangles.push([q[0] as usize, q[2] as usize, q[3] as usize]);
+        }
+
+        let count = mesh.positions.len();
+        let mut around: Vec<Vec<usize>> = (0..count).map(|_| Vec::new()).collect();
+        let mut quadrics: Vec<Quadric> = (0..count).map(|_| Quadric::zero()).collect();
+        let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+
+        for (i, t) in triangles.iter().enumerate() {
+            let p = |c: usize| mesh.positions[t[c]];
+            let n = p(1).sub_v(&p(0)).cross(&p(2).sub_v(&p(0)));
+            let area = n.length();
+
+            if area > 0.0 {
+                let n = n.div_s(area);
+                let q = Quadric::plane(&n, -n.dot(&p(0)), area);
+                for c in 0..3 {
+                    quadrics[t[c]] = quadrics[t[c]].add(&q);
+                }
+            }
+
+            for c in 0..3 {
+                around[t[c]].push(i);
+                let key = edge_key(t[c], t[(c + 1) % 3]);
+                let uses = edges.get(&key).map(|n| *n).unwrap_or(0);
+                edges.insert(key, uses + 1);
+            }
+        }
+
+        let mut locked: Vec<bool> = (0..count).map(|_| false).collect();
+
+        for (edge, uses) in edges.iter() {
+            if *uses == 1 {
+                locked[edge.0] = true;
+                locked[edge.1] = true;
+            }
+        }
+
+        // Vertices that share a position with another vertex are on a seam.
+        let mut places: HashMap<(u32, u32, u32), usize> = HashMap::new();
+        for v in 0..count {
+            let key = position_key(&mesh.positions[v]);
+            if let Some(other) = places.get(&key).map(|o| *o) {
+                locked[other] = true;
+                locked[v] = true;
+            }
+            places.insert(key, v);
+        }
+
+        Simplifier {
+            mesh:       mesh,
+            live:       triangles.len(),
+            removed:    (0..triangles.len()).map(|_| false).collect(),
+            triangles:  triangles,
+            around:     around,
+            quadrics:   quadrics,
+            locked:     locked,
+        }
+    }
+
+    /// Work out the cheapest way to collapse the edge between `a` and `b`.
+    fn plan(&self, a: usize, b: usize) -> Option<Collapse> {
+        let (a, b) = match (self.locked[a], self.locked[b]) {
+            (true, true) => return Option::None,
+            (false, true) => (b, a),
+            _ => (a, b),
+        };
+
+        let q = self.quadrics[a].add(&self.quadrics[b]);
+        let pa = self.mesh.positions[a];
+        let pb = self.mesh.positions[b];
+
+        let choices: &[f32] = if self.locked[a] { &[0.0] } else { &[0.0, 0.5, 1.0] };
+        let mut best: Option<Collapse> = Option::None;
+
+        for t in choices.iter() {
+            let p = pa.add_v(&pb.sub_v(&pa).mul_s(*t));
+            let cost = q.error(&p);
+            if best.as_ref().map(|c| cost < c.cost).unwrap_or(true) {
+                best = Option::Some(Collapse { cost: cost, a: a, b: b, t: *t });
+            }
+        }
+
+        best
+    }
+
+    /// Return false if moving `a` and `b` to `p` would fold any remaining triangle over.
+    fn keeps_orientation(&self, a: usize, b: usize, p: &cgmath::Vector3<f32>) -> bool {
+        for v in [a, b].iter() {
+            for t in self.around[*v].iter() {
+                let tri = self.triangles[*t];
+                if self.removed[*t] || (tri.contains(&a) && tri.contains(&b)) {
+                    continue;
+                }
+
+                let corner = |c: usize| self.mesh.positions[tri[c]];
+                let moved = |c: usize| if tri[c] == a || tri[c] == b { *p } else { corner(c) };
+
+                let before = corner(1).sub_v(&corner(0)).cross(&corner(2).sub_v(&corner(0)));
+                let after = moved(1).sub_v(&moved(0)).cross(&moved(2).sub_v(&moved(0)));
+
+                if after.length() == 0.0 || before.dot(&after) <= 0.0 {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Return false if collapsing the edge between `a` and `b` would pinch the surface: every
+    /// vertex joined to both ends has to be a far corner of a triangle on the edge, as any other
+    /// one would end up with two edges to the same vertex. Collapsing an edge of a closed
+    /// tetrahedron would leave two triangles back to back, so that is refused too.
+    fn keeps_manifold(&self, a: usize, b: usize) -> bool {
+        let neighbors = |v: usize| -> Vec<usize> {
+            let mut list: Vec<usize> = Vec::new();
+            for t in self.around[v].iter() {
+                if self.removed[*t] {
+                    continue;
+                }
+                for c in self.triangles[*t].iter() {
+                    if *c != a && *c != b && !list.contains(c) {
+                        list.push(*c);
+                    }
+                }
+            }
+            list
+        };
+
+        let mut opposite: Vec<usize> = Vec::new();
+        for t in self.around[a].iter() {
+            let tri = self.triangles[*t];
+            if self.removed[*t] || !tri.contains(&b) {
+                continue;
+            }
+            for c in tri.iter() {
+                if *c != a && *c != b {
+                    opposite.push(*c);
+                }
+            }
+        }
+
+        let (around_a, around_b) = (neighbors(a), neighbors(b));
+        if !around_a.iter().filter(|v| around_b.contains(*v)).all(|v| opposite.contains(v)) {
+            return false;
+        }
+
+        let mut all = around_a;
+        for v in around_b.into_iter() {
+            if !all.contains(&v) {
+                all.push(v);
+            }
+        }
+        !(opposite.len() == 2 && all.len() == 2)
+    }
+
+    /// Collapse the cheapest edges, each vertex at most once, and return whether anything was
+    /// collapsed.
+    fn pass(&mut self, target: usize) -> bool {
+        let mut plans: Vec<Collapse> = Vec::new();
+
+        for (i, t) in self.triangles.iter().enumerate() {
+            if self.removed[i] {
+                continue;
+            }
+            for c in 0..3 {
+                // every inner edge is seen from both sides, only plan it once
+                let (a, b) = (t[c], t[(c + 1) % 3]);
+                if a < b || !self.is_inner(b, a) {
+                    if let Some(plan) = self.plan(a, b) {
+                        plans.push(plan);
+                    }
+                }
+            }
+        }
+
+        plans.sort_by(|x, y| x.cost.partial_cmp(&y.cost).unwrap_or(::std::cmp::Ordering::Equal));
+
+        let mut touched: Vec<bool> = (0..self.mesh.positions.len()).map(|_| false).collect();
+        let mut collapsed = false;
+
+        for plan in plans.iter() {
+            if self.live <= target {
+                break;
+            }
+            if touched[plan.a] || touched[plan.b] {
+                continue;
+            }
+
+            let pa = self.mesh.positions[plan.a];
+            let p = pa.add_v(&self.mesh.positions[plan.b].sub_v(&pa).mul_s(plan.t));
+            if !self.keeps_manifold(plan.a, plan.b) || !self.keeps_orientation(plan.a, plan.b, &p) {
+                continue;
+            }
+
+            // everything around both ends changes, so their neighbours have to wait
+            for v in [plan.a, plan.b].iter() {
+                for t in self.around[*v].iter() {
+                    for c in self.triangles[*t].iter() {
+                        touched[*c] = true;
+                    }
+                }
+            }
+
+            self.collapse(plan, &p);
+            collapsed = true;
+        }
+
+        collapsed
+    }
+
+    /// Return whether the edge from `a` to `b` is used by a live triangle in that direction.
+    fn is_inner(&self, a: usize, b: usize) -> bool {
+        self.around[a].iter().any(|t| {
+            let tri = self.triangles[*t];
+            !self.removed[*t] && (0..3).any(|c| tri[c] == a && tri[(c + 1) % 3] == b)
+        })
+    }
+
+    fn collapse(&mut self, plan: &Collapse, p: &cgmath::Vector3<f32>) {
+        let (a, b, t) = (plan.a, plan.b, plan.t);
+
+        self.mesh.positions[a] = *p;
+        self.interpolate(a, b, t);
+        self.quadrics[a] = self.quadrics[a].add(&self.quadrics[b]);
+        if self.locked[b] {
+            self.locked[a] = true;
+        }
+
+        let moved = ::std::mem::replace(&mut self.around[b], Vec::new());
+        for i in moved.into_iter() {
+            if self.removed[i] {
+                continue;
+            }
+            if self.triangles[i].contains(&a) {
+                self.removed[i] = true;
+                self.live -= 1;
+                continue;
+            }
+            for c in 0..3 {
+                if self.triangles[i][c] == b {
+                    self.triangles[i][c] = a;
+                }
+            }
+            self.around[a].push(i);
+        }
+    }
+
+    /// Move the per-vertex data of `a` the fraction `t` towards that of `b`.
+    fn interpolate(&mut self, a: usize, b: usize, t: f32) {
+        let m = &mut self.mesh;
+        let count = m.positions.len();
+        let lerp = |x: f32, y: f32| x + (y - x) * t;
+
+        if m.normals.len() == count {
+            let n = m.normals[a].add_v(&m.normals[b].sub_v(&m.normals[a]).mul_s(t));
+            if n.length() > 0.0 {
+                m.normals[a] = n.normalize();
+            }
+        }
+        if m.uvs.len() == count {
+            m.uvs[a] = cgmath::Vector2::new(lerp(m.uvs[a].x, m.uvs[b].x), lerp(m.uvs[a].y, m.uvs[b].y));
+        }
+        if m.colors.len() == count {
+            m.colors[a] = m.colors[a].add_v(&m.colors[b].sub_v(&m.colors[a]).mul_s(t));
+        }
+        if m.tangents.len() == count {
+            let (ta, tb) = (m.tangents[a], m.tangents[b]);
+            let v = cgmath::Vector3::new(lerp(ta[0], tb[0]), lerp(ta[1], tb[1]), lerp(ta[2], tb[2]));
+            if v.length() > 0.0 {
+                let v = v.normalize();
+                m.tangents[a] = [v.x, v.y, v.z, ta[3]];
+            }
+        }
+        if m.weights.len() == count && m.joints.len() == count {
+            // joint indices can not be blended, so take the ones of the closer end
+            if t > 0.5 {
+                m.joints[a] = m.joints[b];
+                m.weights[a] = m.weights[b];
+            }
+        }
+    }
+
+    fn finish(self) -> Mesh {
+        let mut mesh = self.mesh;
+        let removed = self.removed;
+
+        mesh.triangles = self.triangles.iter().zip(removed.iter()).filter(|p| !*p.1).map(|p| {
+            let t = p.0;
+            [t[0] as u16, t[1] as u16, t[2] as u16]
+        }).collect();
+
+        mesh.compact();
+        mesh
+    }
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath;
+
+    use mesh::Mesh;
+    use simplescene::SimpleSceneObject;
+    use subdivide;
+    use topology;
+
+    use super::{simplify, Simplifier};
+
+    /// Return a unit cube with its quads facing out and the corners shared between them.
+    fn cube() -> SimpleSceneObject {
+        let mut object = SimpleSceneObject::new();
+        object.name = String::from_str("Cube");
+        object.typ = String::from_str("MESH");
+        for i in 0..8 {
+            object.vertices.push(cgmath::Vector3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2 & 1) as f32));
+        }
+        object.quads = vec![
+            [0, 2, 3, 1], [4, 5, 7, 6],
+            [0, 1, 5, 4], [2, 6, 7, 3],
+            [0, 4, 6, 2], [1, 3, 7, 5],
+        ];
+        object
+    }
+
+    #[test]
+    fn closed_mesh_stays_manifold() {
+        let mesh = Mesh::from_simplescene(&subdivide::catmull_clark(&cube(), 2, &[]).unwrap());
+        assert_eq!(mesh.quads.len(), 96);
+
+        // far lower than a closed mesh can go, it has to stop at a tetrahedron
+        let simple = simplify(&mesh, 2);
+        assert!(simple.triangles.len() >= 4 && simple.triangles.len() <= 16);
+
+        let mut object = cube();
+        simple.to_simplescene(&mut object);
+        let report = topology::analyze(&object);
+        assert_eq!(report.non_manifold_edges.len(), 0);
+        assert_eq!(report.boundary_edges.len(), 0);
+    }
+
+    #[test]
+    fn link_condition() {
+        let mut mesh = Mesh::new();
+        for i in 0..7 {
+            mesh.positions.push(cgmath::Vector3::new(i as f32, (i * i) as f32, 0.0));
+        }
+        // 0 and 1 are both joined to 4 without it being on a triangle with the edge between them
+        mesh.triangles = vec![[0, 1, 2], [1, 0, 3], [0, 5, 4], [1, 4, 6]];
+        let s = Simplifier::new(&mesh);
+        assert!(!s.keeps_manifold(0, 1));
+        assert!(s.keeps_manifold(0, 2));
+
+        let mut tetrahedron = Mesh::new();
+        tetrahedron.positions = vec![
+            cgmath::Vector3::new(0.0, 0.0, 0.0), cgmath::Vector3::new(1.0, 0.0, 0.0),
+            cgmath::Vector3::new(0.0, 1.0, 0.0), cgmath::Vector3::new(0.0, 0.0, 1.0),
+        ];
+        tetrahedron.triangles = vec![[0, 2, 1], [0, 1, 3], [1, 2, 3], [2, 0, 3]];
+        assert!(!Simplifier::new(&tetrahedron).keeps_manifold(0, 1));
+    }
+}
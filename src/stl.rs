@@ -0,0 +1,175 @@
+//! Reads and writes STL files, both the binary and the ASCII flavor, for prototyping and 3D
+//! printing. STL is nothing but a list of separate triangles, so on import the corners that sit
+//! at the same place are welded back together into shared vertices. Otherwise every triangle
+//! would have its own three vertices and things like normal smoothing would not work.
+//!
+//! STL has no notion of quads or per-vertex data so quads are split into two triangles on export
+//! and the facet normals are worked out from the positions.
+
+use std::collections::HashMap;
+use std::old_io::{File, BufReader, BufferedWriter, Reader, Writer};
+
+use cgmath;
+
+use mesh::{Mesh, position_key};
+use simplescene::SimpleSceneObject;
+
+/// Return an object built from the triangles of an STL file. Corners closer than `epsilon` to
+/// each other are welded into one vertex by `Mesh::weld`, an `epsilon` of zero only welds exact
+/// matches.
+pub fn read(source: &str, epsilon: f32) -> SimpleSceneObject {
+    let path = Path::new(source);
+    let data = File::open(&path).unwrap().read_to_end().unwrap();
+
+    let mut object = SimpleSceneObject::new();
+    object.typ = String::from_str("MESH");
+    object.name = String::from_str(path.filestem_str().unwrap_or("stl"));
+
+    // Some binary files start with `solid` too, so the size is the better test.
+    let binary = data.len() >= 84 && {
+        let count = BufReader::new(&data[80..84]).read_le_u32().unwrap() as usize;
+        84 + count * 50 == data.len()
+    };
+
+    let corners = if binary {
+        read_binary(data.as_slice())
+    } else {
+        read_ascii(::std::str::from_utf8(data.as_slice()).unwrap(), &mut object)
+    };
+
+    // Corners in exactly the same place are shared right away, which keeps the vertex count
+    // within 16-bit indices, and the mesh then welds the ones close to each other.
+    let mut shared: HashMap<(u32, u32, u32), u16> = HashMap::new();
+    let mut mesh = Mesh::new();
+
+    for t in corners.chunks(3) {
+        let mut triangle = [0u16; 3];
+        for (c, v) in t.iter().enumerate() {
+            let key = position_key(v);
+            triangle[c] = match shared.get(&key) {
+                Some(i) => *i,
+                None => {
+                    if mesh.positions.len() >= 65536 {
+                        panic!("stl: too many vertices for 16-bit indices");
+                    }
+                    mesh.positions.push(*v);
+                    (mesh.positions.len() - 1) as u16
+                },
+            };
+            shared.insert(key, triangle[c]);
+        }
+        mesh.triangles.push(triangle);
+    }
+
+    // welding can collapse tiny triangles, which cleanup removes along with unused vertices
+    mesh.cleanup(epsilon);
+    mesh.to_simplescene(&mut object);
+    object
+}
+
+/// Return the corners of every triangle in a binary file, three at a time.
+fn read_binary(data: &[u8]) -> Vec<cgmath::Vector3<f32>> {
+    let mut r = BufReader::new(&data[80..]);
+    let count = r.read_le_u32().unwrap() as usize;
+    let mut corners: Vec<cgmath::Vector3<f32>> = Vec::with_capacity(count * 3);
+
+    for _ in 0..count {
+        // facet normal, which we do not need
+        for _ in 0..3 {
+            r.read_le_f32().unwrap();
+        }
+        for _ in 0..3 {
+            corners.push(cgmath::Vector3::new(
+                r.read_le_f32().unwrap(),
+                r.read_le_f32().unwrap(),
+                r.read_le_f32().unwrap()
+            ));
+        }
+        // attribute byte count
+        r.read_le_u16().unwrap();
+    }
+
+    corners
+}
+
+/// Return the corners of every triangle in an ASCII file, three at a time.
+fn read_ascii(data: &str, object: &mut SimpleSceneObject) -> Vec<cgmath::Vector3<f32>> {
+    let mut corners: Vec<cgmath::Vector3<f32>> = Vec::new();
+
+    for line in data.lines() {
+        let mut parts = line.words();
+        match parts.next() {
+            Some("solid") => {
+                if let Some(name) = parts.next() {
+                    object.name = String::from_str(name);
+                }
+            },
+            Some("vertex") => corners.push(cgmath::Vector3::new(
+                parts.next().unwrap().parse::<f32>().unwrap(),
+                parts.next().unwrap().parse::<f32>().unwrap(),
+                parts.next().unwrap().parse::<f32>().unwrap()
+            )),
+            _ => continue,
+        }
+    }
+
+    corners
+}
+
+/// Write the object as an STL file, in the binary flavor if `binary` is set.
+pub fn write(object: &SimpleSceneObject, path: &str, binary: bool) {
+    let mut triangles: Vec<[u16;3]> = object.triangles.clone();
+    for q in object.quads.iter() {
+        triangles.push([q[0], q[1], q[2]]);
+        triangles.push([q[0], q[2], q[3]]);
+    }
+
+    let mut w = BufferedWriter::new(File::create(&Path::new(path)).unwrap());
+
+    if binary {
+        let mut header = [0u8; 80];
+        for (i, b) in object.name.as_bytes().iter().take(80).enumerate() {
+            header[i] = *b;
+        }
+        w.write_all(&header).unwrap();
+        w.write_le_u32(triangles.len() as u32).unwrap();
+
+        for t in triangles.iter() {
+            let mut corners = vec![facet_normal(object, t)];
+            corners.extend(t.iter().map(|i| object.vertices[*i as usize]));
+            for v in corners.iter() {
+                w.write_le_f32(v.x).unwrap();
+                w.write_le_f32(v.y).unwrap();
+                w.write_le_f32(v.z).unwrap();
+            }
+            w.write_le_u16(0).unwrap();
+        }
+    } else {
+        writeln!(&mut w, "solid {}", object.name).unwrap();
+        for t in triangles.iter() {
+            let n = facet_normal(object, t);
+            writeln!(&mut w, "facet normal {} {} {}", n.x, n.y, n.z).unwrap();
+            writeln!(&mut w, "outer loop").unwrap();
+            for i in t.iter() {
+                let v = object.vertices[*i as usize];
+                writeln!(&mut w, "vertex {} {} {}", v.x, v.y, v.z).unwrap();
+            }
+            writeln!(&mut w, "endloop").unwrap();
+            writeln!(&mut w, "endfacet").unwrap();
+        }
+        writeln!(&mut w, "endsolid {}", object.name).unwrap();
+    }
+
+    w.flush().unwrap();
+}
+
+fn facet_normal(object: &SimpleSceneObject, t: &[u16;3]) -> cgmath::Vector3<f32> {
+    use cgmath::{Vector, EuclideanVector};
+
+    let a = object.vertices[t[0] as usize];
+    let b = object.vertices[t[1] as usize];
+    let c = object.vertices[t[2] as usize];
+    let n = b.sub_v(&a).cross(&c.sub_v(&a));
+
+    if n.length() > 0.0 { n.normalize() } else { n }
+}
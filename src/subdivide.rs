@@ -0,0 +1,339 @@
+//! Catmull-Clark subdivision. Every level turns each face with `n` corners into `n` quads by
+//! adding a point in the middle of every face and every edge, and moves the old vertices towards
+//! the smooth limit surface. Quads stay quads, triangles become three quads each.
+//!
+//! Edges can be given a crease sharpness like in Blender. An edge with a sharpness of one or more
+//! is kept sharp for this level and the sharpness of its halves goes down by one for the next, so
+//! a sharpness of two keeps it sharp for two levels. Fractions blend between smooth and sharp.
+//! The edges on the boundary of an open mesh are always sharp and the corners of the boundary
+//! (a vertex on only one face) stay where they are, which is what Blender does by default.
+//!
+//! The shape is worked out on the positions alone, so vertices that are only split because of
+//! their texture coordinates or colors still move together. Those are interpolated linearly.
+//! Normals are generated again for the result if the object had them, skinning data is not kept.
+
+use std::collections::{HashMap, HashSet};
+
+use cgmath;
+use cgmath::Vector;
+
+use mesh::Mesh;
+use simplescene::SimpleSceneObject;
+
+/// The crease sharpness of the edge between two vertices of an object.
+#[derive(Copy, Clone)]
+pub struct Crease {
+    pub a:              u16,
+    pub b:              u16,
+    pub sharpness:      f32,
+}
+
+/// Return a new object made by subdividing the faces of `object` `levels` times. The new
+/// object has the name, transform, parent and material of the original one but is not added to
+/// the parent's children. Fails if a level would need more vertices than 16-bit indices can
+/// address.
+pub fn catmull_clark(object: &SimpleSceneObject, levels: usize, creases: &[Crease]) -> Result<SimpleSceneObject, String> {
+    let mut mesh = Mesh::from_simplescene(object);
+    let normals = mesh.normals.len() == mesh.positions.len() && mesh.positions.len() > 0;
+
+    mesh.normals.clear();
+    mesh.tangents.clear();
+    mesh.joints.clear();
+    mesh.weights.clear();
+
+    let mut sharp: HashMap<(u16, u16), f32> = HashMap::new();
+    for c in creases.iter() {
+        sharp.insert(edge_key(c.a, c.b), c.sharpness);
+    }
+
+    for level in 0..levels {
+        let count = vertex_count(&mesh);
+        if count > 65536 {
+            return Err(format!("subdivide: level {} of {} needs {} vertices, more than 16-bit indices can address",
+                               level + 1, object.name, count));
+        }
+
+        let (next, next_sharp) = subdivide(&mesh, &sharp);
+        mesh = next;
+        sharp = next_sharp;
+    }
+
+    if normals {
+        mesh.smooth_normals();
+    }
+
+    let mut result = SimpleSceneObject::new();
+    result.name = object.name.clone();
+    result.typ = object.typ.clone();
+    result.groups = object.groups.clone();
+    result.material = object.material.clone();
+    result.scale = object.scale;
+    result.location = object.location;
+    result.rotation = object.rotation;
+    result.parent = object.parent.clone();
+    mesh.to_simplescene(&mut result);
+    Ok(result)
+}
+
+/// Return the number of vertices one level of subdivision turns `mesh` into: the old ones and
+/// one for every face and every edge.
+fn vertex_count(mesh: &Mesh) -> usize {
+    let mut edges: HashSet<(u16, u16)> = HashSet::new();
+    for f in 0..mesh.face_count() {
+        let face = mesh.face(f);
+        for i in 0..face.len() {
+            edges.insert(edge_key(face[i], face[(i + 1) % face.len()]));
+        }
+    }
+    mesh.positions.len() + mesh.face_count() + edges.len()
+}
+
+/// An edge between two places (vertex positions).
+struct Edge {
+    faces:          Vec<usize>,
+    sharpness:      f32,
+}
+
+impl Edge {
+    fn boundary(&self) -> bool {
+        self.faces.len() != 2
+    }
+}
+
+/// Do one level of subdivision, returning the new mesh and the crease sharpness of its edges.
+fn subdivide(mesh: &Mesh, sharp: &HashMap<(u16, u16), f32>) -> (Mesh, HashMap<(u16, u16), f32>) {
+    let count = mesh.positions.len();
+
+    // the topology is worked out on the places rather than the vertices
+    let (place, places) = mesh.places();
+
+    let faces: Vec<Vec<u16>> = (0..mesh.face_count()).map(|f| mesh.face(f).to_vec()).collect();
+
+    let mut edges: HashMap<(usize, usize), Edge> = HashMap::new();
+    let mut place_edges: Vec<Vec<(usize, usize)>> = (0..places.len()).map(|_| Vec::new()).collect();
+    let mut place_faces: Vec<Vec<usize>> = (0..places.len()).map(|_| Vec::new()).collect();
+
+    for (f, face) in faces.iter().enumerate() {
+        for i in 0..face.len() {
+            let (a, b) = (face[i], face[(i + 1) % face.len()]);
+            let key = place_key(place[a as usize], place[b as usize]);
+            let s = sharp.get(&edge_key(a, b)).map(|s| *s).unwrap_or(0.0);
+
+            if !edges.contains_key(&key) {
+                edges.insert(key, Edge { faces: Vec::new(), sharpness: 0.0 });
+                place_edges[key.0].push(key);
+                place_edges[key.1].push(key);
+            }
+            let edge = edges.get_mut(&key).unwrap();
+            edge.faces.push(f);
+            if s > edge.sharpness {
+                edge.sharpness = s;
+            }
+
+            place_faces[place[a as usize]].push(f);
+        }
+    }
+
+    let face_points: Vec<cgmath::Vector3<f32>> = faces.iter().map(|face| {
+        average(face.iter().map(|v| places[place[*v as usize]]))
+    }).collect();
+
+    let mut edge_points: HashMap<(usize, usize), cgmath::Vector3<f32>> = HashMap::new();
+    for (key, edge) in edges.iter() {
+        let mid = places[key.0].add_v(&places[key.1]).mul_s(0.5);
+        let point = if edge.boundary() || edge.sharpness >= 1.0 {
+            mid
+        } else {
+            let smooth = average(vec![places[key.0], places[key.1], face_points[edge.faces[0]], face_points[edge.faces[1]]].into_iter());
+            lerp(&smooth, &mid, edge.sharpness)
+        };
+        edge_points.insert(*key, point);
+    }
+
+    let vertex_points: Vec<cgmath::Vector3<f32>> = (0..places.len()).map(|p| {
+        let position = places[p];
+        let around = &place_edges[p];
+        let other = |key: &(usize, usize)| if key.0 == p { key.1 } else { key.0 };
+
+        // the creased edges through the vertex, boundary edges count as infinitely sharp
+        let creased: Vec<&(usize, usize)> = around.iter().filter(|k| {
+            let e = edges.get(*k).unwrap();
+            e.boundary() || e.sharpness > 0.0
+        }).collect();
+
+        let n = around.len() as f32;
+        let smooth = if n < 3.0 || creased.iter().any(|k| edges.get(*k).unwrap().boundary()) {
+            position
+        } else {
+            let f = average(place_faces[p].iter().map(|f| face_points[*f]));
+            let r = average(around.iter().map(|k| places[k.0].add_v(&places[k.1]).mul_s(0.5)));
+            f.add_v(&r.mul_s(2.0)).add_v(&position.mul_s(n - 3.0)).div_s(n)
+        };
+
+        let sharp = match creased.len() {
+            0 | 1 => return smooth,
+            // the corner of an open mesh
+            2 if place_faces[p].len() == 1 => position,
+            2 => position.mul_s(6.0).add_v(&places[other(creased[0])]).add_v(&places[other(creased[1])]).div_s(8.0),
+            _ => position,
+        };
+
+        let sharpness = creased.iter().map(|k| {
+            let e = edges.get(*k).unwrap();
+            if e.boundary() { 1.0 } else { e.sharpness }
+        }).fold(0.0, |a, b| a + b) / creased.len() as f32;
+
+        if sharpness >= 1.0 { sharp } else { lerp(&smooth, &sharp, sharpness) }
+    }).collect();
+
+    // The old vertices keep their numbers, the edge and face points come after them.
+    let mut result = Mesh::new();
+    let uvs = mesh.uvs.len() == count;
+    let colors = mesh.colors.len() == count;
+
+    for v in 0..count {
+        result.positions.push(vertex_points[place[v]]);
+    }
+    if uvs {
+        result.uvs = mesh.uvs.clone();
+    }
+    if colors {
+        result.colors = mesh.colors.clone();
+    }
+
+    let mut edge_vertices: HashMap<(u16, u16), u16> = HashMap::new();
+    let mut next_sharp: HashMap<(u16, u16), f32> = HashMap::new();
+
+    for (f, face) in faces.iter().enumerate() {
+        let n = face.len();
+
+        let middle = add_vertex(&mut result, face_points[f], mesh, face.as_slice());
+
+        let mut edge_vertex = |result: &mut Mesh, a: u16, b: u16| -> u16 {
+            let key = edge_key(a, b);
+            if let Some(v) = edge_vertices.get(&key) {
+                return *v;
+            }
+            let point = *edge_points.get(&place_key(place[a as usize], place[b as usize])).unwrap();
+            let v = add_vertex(result, point, mesh, &[a, b]);
+            edge_vertices.insert(key, v);
+            v
+        };
+
+        let splits: Vec<u16> = (0..n).map(|i| edge_vertex(&mut result, face[i], face[(i + 1) % n])).collect();
+
+        for i in 0..n {
+            result.quads.push([face[i], splits[i], middle, splits[(i + n - 1) % n]]);
+
+            let (a, b) = (face[i], face[(i + 1) % n]);
+            if let Some(s) = sharp.get(&edge_key(a, b)) {
+                if *s > 1.0 {
+                    next_sharp.insert(edge_key(a, splits[i]), *s - 1.0);
+                    next_sharp.insert(edge_key(splits[i], b), *s - 1.0);
+                }
+            }
+        }
+    }
+
+    (result, next_sharp)
+}
+
+/// Add a vertex at `position` with the other per-vertex data averaged from `from` in `source`.
+fn add_vertex(result: &mut Mesh, position: cgmath::Vector3<f32>, source: &Mesh, from: &[u16]) -> u16 {
+    if result.positions.len() >= 65536 {
+        panic!("subdivide: too many vertices for 16-bit indices");
+    }
+
+    if result.uvs.len() == result.positions.len() && source.uvs.len() > 0 {
+        let sum = from.iter().fold(cgmath::Vector2::new(0.0, 0.0), |a, v| a.add_v(&source.uvs[*v as usize]));
+        result.uvs.push(sum.div_s(from.len() as f32));
+    }
+    if result.colors.len() == result.positions.len() && source.colors.len() > 0 {
+        result.colors.push(average(from.iter().map(|v| source.colors[*v as usize])));
+    }
+
+    result.positions.push(position);
+    (result.positions.len() - 1) as u16
+}
+
+fn average<I: Iterator<Item = cgmath::Vector3<f32>>>(points: I) -> cgmath::Vector3<f32> {
+    let mut sum = cgmath::Vector3::new(0.0, 0.0, 0.0);
+    let mut n = 0.0;
+    for p in points {
+        sum = sum.add_v(&p);
+        n += 1.0;
+    }
+    if n > 0.0 { sum.div_s(n) } else { sum }
+}
+
+fn lerp(a: &cgmath::Vector3<f32>, b: &cgmath::Vector3<f32>, t: f32) -> cgmath::Vector3<f32> {
+    a.add_v(&b.sub_v(a).mul_s(t))
+}
+
+fn edge_key(a: u16, b: u16) -> (u16, u16) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+fn place_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath;
+
+    use simplescene::SimpleSceneObject;
+
+    use super::{catmull_clark, Crease};
+
+    /// Return a unit cube with its quads facing out and the corners shared between them.
+    fn cube() -> SimpleSceneObject {
+        let mut object = SimpleSceneObject::new();
+        object.name = String::from_str("Cube");
+        object.typ = String::from_str("MESH");
+        for i in 0..8 {
+            object.vertices.push(cgmath::Vector3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2 & 1) as f32));
+        }
+        object.quads = vec![
+            [0, 2, 3, 1], [4, 5, 7, 6],
+            [0, 1, 5, 4], [2, 6, 7, 3],
+            [0, 4, 6, 2], [1, 3, 7, 5],
+        ];
+        object
+    }
+
+    #[test]
+    fn cube_level_one() {
+        let result = catmull_clark(&cube(), 1, &[]).unwrap();
+        assert_eq!(result.quads.len(), 24);
+        assert_eq!(result.triangles.len(), 0);
+        // the 8 corners, a vertex in the middle of each of the 6 faces and 12 edges
+        assert_eq!(result.vertices.len(), 26);
+        // the corners move in towards the middle
+        assert!(result.vertices[0].z > 0.0);
+    }
+
+    #[test]
+    fn creased_loop_stays_flat() {
+        // the edges around the bottom face
+        let creases = [
+            Crease { a: 0, b: 2, sharpness: 1.0 }, Crease { a: 2, b: 3, sharpness: 1.0 },
+            Crease { a: 3, b: 1, sharpness: 1.0 }, Crease { a: 1, b: 0, sharpness: 1.0 },
+        ];
+        let result = catmull_clark(&cube(), 1, &creases).unwrap();
+
+        // the four quads the bottom face turned into
+        for q in result.quads[0..4].iter() {
+            for v in q.iter() {
+                assert_eq!(result.vertices[*v as usize].z, 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn too_many_vertices() {
+        // the seventh level would need 98306 vertices
+        assert!(catmull_clark(&cube(), 7, &[]).is_err());
+        assert_eq!(catmull_clark(&cube(), 6, &[]).unwrap().vertices.len(), 24578);
+    }
+}
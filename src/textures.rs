@@ -0,0 +1,331 @@
+//! Loading textures and choosing how they are sampled.
+//!
+//! PNG files are decoded with the `image` crate and TGA files (uncompressed or run length encoded,
+//! gray, 24 or 32 bits) by `read_tga`. Either way the rows end up bottom first, which is where
+//! OpenGL and the texture coordinates of the scenes put the origin. glium builds the mipmaps when a
+//! texture is uploaded.
+//!
+//! A texture that can not be loaded is replaced by a magenta and black checkerboard, which is hard
+//! to miss in the game but keeps it running. The error is kept until the caller picks it up with
+//! `TextureManager::take_failures`.
+
+use std::collections::HashMap;
+use std::ascii::AsciiExt;
+use std::old_io::File;
+use std::sync::Arc;
+
+use glium::Display;
+use glium::texture::Texture2d;
+use glium::uniforms::{SamplerBehavior, SamplerWrapFunction, MinifySamplerFilter, MagnifySamplerFilter};
+
+use glium;
+use image;
+use image::GenericImage;
+
+use simplescene::SimpleSceneMaterial;
+
+/// Pixels in RGBA order, the bottom row first.
+pub struct Image {
+    pub width:          u32,
+    pub height:         u32,
+    pub pixels:         Vec<[u8;4]>,
+}
+
+impl Image {
+    /// Return the rows of the image as glium takes them.
+    fn rows(&self) -> Vec<Vec<(u8, u8, u8, u8)>> {
+        self.pixels.chunks(self.width as usize).map(|row| {
+            row.iter().map(|p| (p[0], p[1], p[2], p[3])).collect()
+        }).collect()
+    }
+}
+
+/// How a texture is sampled.
+#[derive(Copy, Clone)]
+pub struct SamplerState {
+    /// what happens outside of the zero to one range of the texture coordinates
+    pub wrap:           SamplerWrapFunction,
+    /// the filter used when the texture is drawn smaller than it is
+    pub minify:         MinifySamplerFilter,
+    /// the filter used when the texture is drawn larger than it is
+    pub magnify:        MagnifySamplerFilter,
+    /// one to turn off anisotropic filtering
+    pub anisotropy:     u16,
+}
+
+impl ::std::default::Default for SamplerState {
+    fn default() -> SamplerState {
+        SamplerState {
+            wrap:           SamplerWrapFunction::Repeat,
+            minify:         MinifySamplerFilter::LinearMipmapLinear,
+            magnify:        MagnifySamplerFilter::Linear,
+            anisotropy:     1,
+        }
+    }
+}
+
+impl SamplerState {
+    /// Return the state for pixel art and lookup tables: no filtering and no wrapping.
+    pub fn nearest() -> SamplerState {
+        SamplerState {
+            wrap:           SamplerWrapFunction::Clamp,
+            minify:         MinifySamplerFilter::Nearest,
+            magnify:        MagnifySamplerFilter::Nearest,
+            anisotropy:     1,
+        }
+    }
+
+    /// Return the glium sampler behavior for the state.
+    pub fn behavior(&self) -> SamplerBehavior {
+        use std::default::Default;
+
+        SamplerBehavior {
+            wrap_function:  (self.wrap, self.wrap, self.wrap),
+            minify_filter:  self.minify,
+            magnify_filter: self.magnify,
+            max_anisotropy: self.anisotropy,
+            .. Default::default()
+        }
+    }
+}
+
+/// A texture and how to sample it, ready to be given to a shader.
+#[derive(Clone)]
+pub struct BoundTexture {
+    pub texture:        Arc<Texture2d>,
+    pub sampler:        SamplerState,
+}
+
+impl BoundTexture {
+    pub fn sampled(&self) -> glium::uniforms::Sampler<Texture2d> {
+        glium::uniforms::Sampler(&*self.texture, self.sampler.behavior())
+    }
+}
+
+/// Loads textures once and hands out shared references to them.
+pub struct TextureManager {
+    display:        Display,
+    /// the directory the paths are relative to, usually the one the scene is in
+    root:           Path,
+    textures:       HashMap<String, Arc<Texture2d>>,
+    samplers:       HashMap<String, SamplerState>,
+    /// the sampler state of textures without one of their own
+    pub sampler:    SamplerState,
+    /// stands in for textures that could not be loaded
+    missing:        Arc<Texture2d>,
+    /// used by materials without a texture, so that shaders can always sample one
+    white:          Arc<Texture2d>,
+    /// why textures could not be loaded, since `take_failures` was last called
+    failures:       Vec<String>,
+}
+
+impl TextureManager {
+    pub fn new(display: &Display, root: &str) -> TextureManager {
+        use std::default::Default;
+
+        let white = Image { width: 1, height: 1, pixels: vec![[255, 255, 255, 255]] };
+
+        TextureManager {
+            display:        display.clone(),
+            root:           Path::new(root),
+            textures:       HashMap::new(),
+            samplers:       HashMap::new(),
+            sampler:        Default::default(),
+            missing:        Arc::new(upload(display, &checkerboard(64, 8))),
+            white:          Arc::new(upload(display, &white)),
+            failures:       Vec::new(),
+        }
+    }
+
+    /// Return the texture at `path`, loading it the first time, or the checkerboard if it can
+    /// not be loaded. The error is added to the failures in that case.
+    pub fn get(&mut self, path: &str) -> Arc<Texture2d> {
+        if let Some(texture) = self.textures.get(path) {
+            return texture.clone();
+        }
+
+        let texture = match load_image(&self.root.join(path)) {
+            Ok(image) => Arc::new(upload(&self.display, &image)),
+            Err(e) => {
+                self.failures.push(e);
+                self.missing.clone()
+            },
+        };
+        self.textures.insert(String::from_str(path), texture.clone());
+        texture
+    }
+
+    /// Return why textures could not be loaded since the last call, and forget about it. Each
+    /// texture is only tried once until `clear` is called, so each error only shows up once.
+    pub fn take_failures(&mut self) -> Vec<String> {
+        ::std::mem::replace(&mut self.failures, Vec::new())
+    }
+
+    /// Set how the texture at `path` is sampled.
+    pub fn set_sampler(&mut self, path: &str, sampler: SamplerState) {
+        self.samplers.insert(String::from_str(path), sampler);
+    }
+
+    /// Return how the texture at `path` is sampled.
+    pub fn get_sampler(&self, path: &str) -> SamplerState {
+        self.samplers.get(path).map(|s| *s).unwrap_or(self.sampler)
+    }
+
+    /// Return the texture to draw a material with, plain white if there is no material or it has
+    /// no texture.
+    pub fn bind(&mut self, material: Option<&SimpleSceneMaterial>) -> BoundTexture {
+        match material.and_then(|m| m.texture.as_ref()) {
+            Some(path) => BoundTexture {
+                texture:    self.get(path.as_slice()),
+                sampler:    self.get_sampler(path.as_slice()),
+            },
+            None => BoundTexture {
+                texture:    self.white.clone(),
+                sampler:    self.sampler,
+            },
+        }
+    }
+
+    /// Forget the loaded textures, so they are loaded again the next time they are asked for.
+    pub fn clear(&mut self) {
+        self.textures.clear();
+    }
+}
+
+fn upload(display: &Display, image: &Image) -> Texture2d {
+    Texture2d::new(display, image.rows())
+}
+
+/// Return a square image of `cells` by `cells` magenta and black squares.
+pub fn checkerboard(size: u32, cells: u32) -> Image {
+    let cell = (size / cells.max(1)).max(1);
+    let mut pixels: Vec<[u8;4]> = Vec::with_capacity((size * size) as usize);
+
+    for y in 0..size {
+        for x in 0..size {
+            pixels.push(if (x / cell + y / cell) % 2 == 0 { [255, 0, 255, 255] } else { [0, 0, 0, 255] });
+        }
+    }
+
+    Image { width: size, height: size, pixels: pixels }
+}
+
+/// Load a PNG or TGA image, picked by the extension of the file.
+pub fn load_image(path: &Path) -> Result<Image, String> {
+    match path.extension_str().map(|e| e.to_ascii_lowercase()) {
+        Some(ref e) if e.as_slice() == "png" => read_png(path),
+        Some(ref e) if e.as_slice() == "tga" => {
+            let result = File::open(path).and_then(|mut f| f.read_to_end());
+            let data = try!(result.map_err(|e| format!("{}: {}", path.display(), e)));
+            read_tga(data.as_slice()).map_err(|e| format!("{}: {}", path.display(), e))
+        },
+        _ => Err(format!("{}: only PNG and TGA images can be loaded", path.display())),
+    }
+}
+
+fn read_png(path: &Path) -> Result<Image, String> {
+    let decoded = try!(image::open(path).map_err(|e| format!("{}: {:?}", path.display(), e)));
+    let rgba = decoded.to_rgba();
+    let (width, height) = rgba.dimensions();
+    if width == 0 || height == 0 {
+        return Err(format!("{}: image is {} by {} pixels", path.display(), width, height));
+    }
+    let mut pixels: Vec<[u8;4]> = Vec::with_capacity((width * height) as usize);
+
+    // PNG rows are top first
+    for y in (0..height).rev() {
+        for x in 0..width {
+            pixels.push(rgba.get_pixel(x, y).data);
+        }
+    }
+
+    Ok(Image { width: width, height: height, pixels: pixels })
+}
+
+/// Decode a TGA image.
+pub fn read_tga(data: &[u8]) -> Result<Image, String> {
+    if data.len() < 18 {
+        return Err(String::from_str("not a TGA image"));
+    }
+
+    let u16_at = |i: usize| data[i] as u32 | (data[i + 1] as u32) << 8;
+    let id_length = data[0] as usize;
+    let colormap = data[1];
+    let kind = data[2];
+    let width = u16_at(12);
+    let height = u16_at(14);
+    let bits = data[16];
+    let top_first = data[17] & 0x20 != 0;
+
+    if width == 0 || height == 0 {
+        return Err(format!("TGA image is {} by {} pixels", width, height));
+    }
+
+    if colormap != 0 {
+        return Err(String::from_str("TGA images with a color map are not supported"));
+    }
+
+    let (gray, rle) = match kind {
+        2 => (false, false),
+        3 => (true, false),
+        10 => (false, true),
+        11 => (true, true),
+        _ => return Err(format!("TGA image type {} is not supported", kind)),
+    };
+
+    let size = match (gray, bits) {
+        (true, 8) => 1,
+        (false, 24) => 3,
+        (false, 32) => 4,
+        _ => return Err(format!("TGA images with {} bits per pixel are not supported", bits)),
+    };
+
+    let count = (width * height) as usize;
+    let mut pixels: Vec<[u8;4]> = Vec::with_capacity(count);
+    let mut i = 18 + id_length;
+
+    let pixel = |i: usize| -> [u8;4] {
+        match size {
+            1 => [data[i], data[i], data[i], 255],
+            3 => [data[i + 2], data[i + 1], data[i], 255],
+            _ => [data[i + 2], data[i + 1], data[i], data[i + 3]],
+        }
+    };
+
+    while pixels.len() < count {
+        if rle {
+            if i >= data.len() {
+                return Err(String::from_str("TGA image is cut short"));
+            }
+            let header = data[i] as usize;
+            i += 1;
+            let run = (header & 0x7f) + 1;
+            let packed = header & 0x80 != 0;
+            let needed = if packed { size } else { size * run };
+            if i + needed > data.len() || pixels.len() + run > count {
+                return Err(String::from_str("TGA image is cut short"));
+            }
+            for n in 0..run {
+                pixels.push(pixel(if packed { i } else { i + n * size }));
+            }
+            i += needed;
+        } else {
+            if i + size > data.len() {
+                return Err(String::from_str("TGA image is cut short"));
+            }
+            pixels.push(pixel(i));
+            i += size;
+        }
+    }
+
+    if top_first {
+        let w = width as usize;
+        let mut flipped: Vec<[u8;4]> = Vec::with_capacity(count);
+        for row in pixels.chunks(w).rev() {
+            flipped.push_all(row);
+        }
+        pixels = flipped;
+    }
+
+    Ok(Image { width: width, height: height, pixels: pixels })
+}
@@ -0,0 +1,434 @@
+//! Checks the topology of mesh objects so that broken assets are caught in the pipeline instead
+//! of showing up as holes, black faces or odd shading in the game.
+//!
+//! The faces are put into a half-edge structure built on the vertex positions rather than the
+//! vertex indices, because vertices are split along texture seams and hard edges without the
+//! surface having a hole there. Each edge normally has two half-edges going opposite ways, one
+//! from each of its faces. An edge with only one is on the boundary of a hole, one with more
+//! than two is non-manifold and two half-edges going the same way mean the faces on either side
+//! are wound differently.
+//!
+//! Faces are counted like `Mesh::face` does: triangles first and then quads.
+
+use std::collections::HashMap;
+
+use cgmath;
+use cgmath::{Vector, EuclideanVector};
+
+use mesh::Mesh;
+use simplescene::SimpleSceneObject;
+
+/// One side of an edge, going from corner `corner` of `face` to the next corner.
+#[derive(Copy, Clone)]
+pub struct HalfEdge {
+    /// the place (distinct position) the half-edge starts at
+    pub from:           usize,
+    /// the place the half-edge ends at
+    pub to:             usize,
+    pub face:           usize,
+    pub corner:         usize,
+    /// the next half-edge around the face
+    pub next:           usize,
+    /// the half-edge on the other side of the edge, `None` on boundary and non-manifold edges
+    pub twin:           Option<usize>,
+}
+
+/// The half-edges of a mesh.
+pub struct HalfEdgeMesh {
+    /// the place of every vertex
+    pub place:          Vec<usize>,
+    pub place_count:    usize,
+    pub half_edges:     Vec<HalfEdge>,
+    /// the first half-edge of every face
+    pub face_edges:     Vec<usize>,
+    /// the half-edges of every edge, keyed by the places at its ends with the smaller one first
+    pub edges:          HashMap<(usize, usize), Vec<usize>>,
+}
+
+impl HalfEdgeMesh {
+    pub fn new(mesh: &Mesh) -> HalfEdgeMesh {
+        let (place, places) = mesh.places();
+
+        let mut half_edges: Vec<HalfEdge> = Vec::new();
+        let mut face_edges: Vec<usize> = Vec::new();
+        let mut edges: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+
+        for f in 0..mesh.face_count() {
+            let face = mesh.face(f);
+            let first = half_edges.len();
+            face_edges.push(first);
+
+            for c in 0..face.len() {
+                let from = place[face[c] as usize];
+                let to = place[face[(c + 1) % face.len()] as usize];
+                let h = half_edges.len();
+                half_edges.push(HalfEdge {
+                    from:       from,
+                    to:         to,
+                    face:       f,
+                    corner:     c,
+                    next:       first + (c + 1) % face.len(),
+                    twin:       None,
+                });
+
+                let key = if from < to { (from, to) } else { (to, from) };
+                if !edges.contains_key(&key) {
+                    edges.insert(key, Vec::new());
+                }
+                edges.get_mut(&key).unwrap().push(h);
+            }
+        }
+
+        for (_, halves) in edges.iter() {
+            if halves.len() == 2 {
+                half_edges[halves[0]].twin = Some(halves[1]);
+                half_edges[halves[1]].twin = Some(halves[0]);
+            }
+        }
+
+        HalfEdgeMesh {
+            place:          place,
+            place_count:    places.len(),
+            half_edges:     half_edges,
+            face_edges:     face_edges,
+            edges:          edges,
+        }
+    }
+
+    /// Return true if the faces on both sides of a half-edge are wound the same way.
+    pub fn consistent(&self, h: usize) -> bool {
+        match self.half_edges[h].twin {
+            Some(t) => self.half_edges[t].from == self.half_edges[h].to,
+            None => true,
+        }
+    }
+}
+
+/// What `analyze` found out about an object. Edges are given as the vertex indices at their
+/// ends in the first face that uses them.
+pub struct TopologyReport {
+    pub vertices:               usize,
+    pub faces:                  usize,
+    pub edges:                  usize,
+    /// the number of separate pieces the faces make up
+    pub components:             usize,
+    /// the number of holes, each one a loop of boundary edges
+    pub holes:                  usize,
+    /// edges with only one face
+    pub boundary_edges:         Vec<[u16;2]>,
+    /// edges with more than two faces
+    pub non_manifold_edges:     Vec<[u16;2]>,
+    /// edges where the faces on either side are wound differently
+    pub inconsistent_edges:     Vec<[u16;2]>,
+    /// faces wound against the vertex normals
+    pub flipped_faces:          Vec<usize>,
+    /// faces without any area
+    pub degenerate_faces:       Vec<usize>,
+    /// vertices no face uses
+    pub isolated_vertices:      Vec<u16>,
+}
+
+impl TopologyReport {
+    /// Return true if the mesh is closed, manifold and consistently wound.
+    pub fn is_clean(&self) -> bool {
+        self.boundary_edges.len() == 0 && self.non_manifold_edges.len() == 0 &&
+        self.inconsistent_edges.len() == 0 && self.flipped_faces.len() == 0 &&
+        self.degenerate_faces.len() == 0 && self.isolated_vertices.len() == 0
+    }
+
+    /// Return a description of every kind of problem found, in the same form as
+    /// `SimpleSceneFile::validate`.
+    pub fn problems(&self, name: &str) -> Vec<String> {
+        let mut problems: Vec<String> = Vec::new();
+
+        if self.boundary_edges.len() > 0 {
+            problems.push(format!("{}: has {} holes with {} boundary edges", name, self.holes, self.boundary_edges.len()));
+        }
+        if self.non_manifold_edges.len() > 0 {
+            problems.push(format!("{}: has {} non-manifold edges", name, self.non_manifold_edges.len()));
+        }
+        if self.inconsistent_edges.len() > 0 {
+            problems.push(format!("{}: has {} edges between faces wound differently", name, self.inconsistent_edges.len()));
+        }
+        if self.flipped_faces.len() > 0 {
+            problems.push(format!("{}: has {} faces wound against their normals", name, self.flipped_faces.len()));
+        }
+        if self.degenerate_faces.len() > 0 {
+            problems.push(format!("{}: has {} faces without area", name, self.degenerate_faces.len()));
+        }
+        if self.isolated_vertices.len() > 0 {
+            problems.push(format!("{}: has {} vertices not used by any face", name, self.isolated_vertices.len()));
+        }
+
+        problems
+    }
+}
+
+/// Work out the topology of an object's faces.
+pub fn analyze(object: &SimpleSceneObject) -> TopologyReport {
+    let mesh = Mesh::from_simplescene(object);
+    let he = HalfEdgeMesh::new(&mesh);
+
+    let edge_of = |h: usize| -> [u16;2] {
+        let e = &he.half_edges[h];
+        let face = mesh.face(e.face);
+        [face[e.corner], face[(e.corner + 1) % face.len()]]
+    };
+
+    let mut boundary_edges: Vec<[u16;2]> = Vec::new();
+    let mut non_manifold_edges: Vec<[u16;2]> = Vec::new();
+    let mut inconsistent_edges: Vec<[u16;2]> = Vec::new();
+    let mut holes = UnionFind::new(he.place_count);
+    let mut boundary_places: Vec<usize> = Vec::new();
+
+    for (key, halves) in he.edges.iter() {
+        let first = *halves.iter().min().unwrap();
+        match halves.len() {
+            1 => {
+                boundary_edges.push(edge_of(first));
+                holes.union(key.0, key.1);
+                boundary_places.push(key.0);
+            },
+            2 => if !he.consistent(first) { inconsistent_edges.push(edge_of(first)) },
+            _ => non_manifold_edges.push(edge_of(first)),
+        }
+    }
+
+    let mut pieces = UnionFind::new(he.place_count);
+    let mut used: Vec<bool> = (0..mesh.positions.len()).map(|_| false).collect();
+    let mut flipped_faces: Vec<usize> = Vec::new();
+    let mut degenerate_faces: Vec<usize> = Vec::new();
+    let normals = mesh.normals.len() == mesh.positions.len();
+
+    for f in 0..mesh.face_count() {
+        let face = mesh.face(f);
+        for v in face.iter() {
+            used[*v as usize] = true;
+            pieces.union(he.place[face[0] as usize], he.place[*v as usize]);
+        }
+
+        let n = mesh.face_normal(face);
+        if n.length2() == 0.0 {
+            degenerate_faces.push(f);
+        } else if normals {
+            let sum = face.iter().fold(cgmath::Vector3::new(0.0, 0.0, 0.0), |a, v| a.add_v(&mesh.normals[*v as usize]));
+            if sum.dot(&n) < 0.0 {
+                flipped_faces.push(f);
+            }
+        }
+    }
+
+    let isolated_vertices: Vec<u16> = (0..used.len()).filter(|v| !used[*v]).map(|v| v as u16).collect();
+
+    let mut roots: Vec<usize> = (0..mesh.positions.len()).filter(|v| used[*v]).map(|v| pieces.find(he.place[v])).collect();
+    roots.sort();
+    roots.dedup();
+
+    let mut hole_roots: Vec<usize> = boundary_places.iter().map(|p| holes.find(*p)).collect();
+    hole_roots.sort();
+    hole_roots.dedup();
+
+    // hash map order is not stable, sort so that reports can be compared
+    boundary_edges.sort();
+    non_manifold_edges.sort();
+    inconsistent_edges.sort();
+
+    TopologyReport {
+        vertices:               mesh.positions.len(),
+        faces:                  mesh.face_count(),
+        edges:                  he.edges.len(),
+        components:             roots.len(),
+        holes:                  hole_roots.len(),
+        boundary_edges:         boundary_edges,
+        non_manifold_edges:     non_manifold_edges,
+        inconsistent_edges:     inconsistent_edges,
+        flipped_faces:          flipped_faces,
+        degenerate_faces:       degenerate_faces,
+        isolated_vertices:      isolated_vertices,
+    }
+}
+
+/// Make the winding of the faces consistent and return the number of faces that were turned
+/// around.
+///
+/// The faces of each piece are walked across their manifold edges and turned to match the face
+/// they were reached from. A closed piece is then turned so that its faces point out, an open
+/// one keeps the winding most of its faces already had. Pieces that cannot be wound
+/// consistently, like a Möbius strip, are left as they are after the walk gets back to where it
+/// started. Normals are not touched.
+pub fn fix_winding(object: &mut SimpleSceneObject) -> usize {
+    let mesh = Mesh::from_simplescene(object);
+    let he = HalfEdgeMesh::new(&mesh);
+    let count = mesh.face_count();
+
+    let mut flip: Vec<bool> = (0..count).map(|_| false).collect();
+    let mut visited: Vec<bool> = (0..count).map(|_| false).collect();
+    let mut turned = 0;
+
+    for start in 0..count {
+        if visited[start] {
+            continue;
+        }
+
+        let mut piece: Vec<usize> = vec![start];
+        let mut closed = true;
+        visited[start] = true;
+
+        let mut i = 0;
+        while i < piece.len() {
+            let f = piece[i];
+            i += 1;
+
+            let first = he.face_edges[f];
+            let mut h = first;
+            loop {
+                let key = {
+                    let e = &he.half_edges[h];
+                    if e.from < e.to { (e.from, e.to) } else { (e.to, e.from) }
+                };
+                if he.edges.get(&key).unwrap().len() == 1 {
+                    closed = false;
+                }
+
+                if let Some(t) = he.half_edges[h].twin {
+                    let g = he.half_edges[t].face;
+                    if !visited[g] {
+                        visited[g] = true;
+                        flip[g] = flip[f] != !he.consistent(h);
+                        piece.push(g);
+                    }
+                }
+
+                h = he.half_edges[h].next;
+                if h == first {
+                    break;
+                }
+            }
+        }
+
+        let flips = piece.iter().filter(|f| flip[**f]).count();
+        let invert = if closed {
+            // the signed volume of a closed piece is negative when it is inside out
+            let mut volume = 0.0;
+            for f in piece.iter() {
+                let face = mesh.face(*f);
+                let p = |c: usize| mesh.positions[face[c] as usize];
+                for c in 1..face.len() - 1 {
+                    let v = p(0).dot(&p(c).cross(&p(c + 1)));
+                    volume += if flip[*f] { -v } else { v };
+                }
+            }
+            volume < 0.0
+        } else {
+            flips * 2 > piece.len()
+        };
+
+        for f in piece.iter() {
+            if flip[*f] != invert {
+                turn(object, *f);
+                turned += 1;
+            }
+        }
+    }
+
+    turned
+}
+
+/// Reverse the winding of a face, keeping its first corner.
+fn turn(object: &mut SimpleSceneObject, f: usize) {
+    if f < object.triangles.len() {
+        let t = object.triangles[f];
+        object.triangles[f] = [t[0], t[2], t[1]];
+    } else {
+        let f = f - object.triangles.len();
+        let q = object.quads[f];
+        object.quads[f] = [q[0], q[3], q[2], q[1]];
+    }
+}
+
+struct UnionFind {
+    parent:         Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(count: usize) -> UnionFind {
+        UnionFind { parent: (0..count).collect() }
+    }
+
+    fn find(&mut self, i: usize) -> usize {
+        let mut root = i;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        let mut i = i;
+        while self.parent[i] != root {
+            let next = self.parent[i];
+            self.parent[i] = root;
+            i = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent[a] = b;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath;
+
+    use simplescene::SimpleSceneObject;
+
+    use super::{analyze, fix_winding};
+
+    /// Return a unit cube with its quads facing out and the corners shared between them.
+    fn cube() -> SimpleSceneObject {
+        let mut object = SimpleSceneObject::new();
+        object.name = String::from_str("Cube");
+        object.typ = String::from_str("MESH");
+        for i in 0..8 {
+            object.vertices.push(cgmath::Vector3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2 & 1) as f32));
+        }
+        object.quads = vec![
+            [0, 2, 3, 1], [4, 5, 7, 6],
+            [0, 1, 5, 4], [2, 6, 7, 3],
+            [0, 4, 6, 2], [1, 3, 7, 5],
+        ];
+        object
+    }
+
+    #[test]
+    fn closed_cube_is_clean() {
+        let report = analyze(&cube());
+        assert!(report.is_clean());
+        assert_eq!(report.faces, 6);
+        assert_eq!(report.edges, 12);
+        assert_eq!(report.components, 1);
+        assert_eq!(report.holes, 0);
+    }
+
+    #[test]
+    fn open_cube_has_a_hole() {
+        let mut object = cube();
+        object.quads.pop();
+        let report = analyze(&object);
+        assert!(!report.is_clean());
+        assert_eq!(report.holes, 1);
+        assert_eq!(report.boundary_edges.len(), 4);
+    }
+
+    #[test]
+    fn fix_winding_turns_the_flipped_quad() {
+        let mut object = cube();
+        let q = object.quads[2];
+        object.quads[2] = [q[0], q[3], q[2], q[1]];
+        assert_eq!(analyze(&object).inconsistent_edges.len(), 4);
+
+        assert_eq!(fix_winding(&mut object), 1);
+        assert!(analyze(&object).is_clean());
+    }
+}
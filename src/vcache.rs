@@ -0,0 +1,224 @@
+//! Orders the triangles of an index list so that the graphics card's post-transform vertex
+//! cache is used well, and then the vertices so that they are fetched from memory in order.
+//!
+//! The triangle order comes from Tipsify (Sander, Nehab and Barczak), which fans out around one
+//! vertex at a time and picks the next vertex to fan around from the ones still likely to be in
+//! the cache. It is fast and gets close to what the slower optimizers manage, and the fans keep
+//! neighbouring triangles together which also helps with overdraw.
+//!
+//! How well it worked is measured with the average cache miss ratio (ACMR), the number of
+//! vertices that have to be transformed per triangle with a FIFO cache. It is 3 at worst and
+//! approaches 0.5 for a large regular mesh.
+
+/// The number of cache entries to optimize for when the hardware is not known. Most cards have
+/// at least this many.
+pub const DEFAULT_CACHE_SIZE: usize = 16;
+
+/// Return the average number of vertex cache misses per triangle for a triangle list, with a
+/// FIFO cache of `cache_size` entries.
+pub fn acmr(indices: &[u16], cache_size: usize) -> f32 {
+    if indices.len() < 3 {
+        return 0.0;
+    }
+
+    let count = indices.iter().map(|i| *i as usize + 1).max().unwrap_or(0);
+    // when each vertex last went into the cache, zero meaning never
+    let mut stamps: Vec<usize> = (0..count).map(|_| 0).collect();
+    let mut time = cache_size + 1;
+    let mut misses = 0;
+
+    for i in indices.iter() {
+        let v = *i as usize;
+        if stamps[v] == 0 || time - stamps[v] > cache_size {
+            stamps[v] = time;
+            time += 1;
+            misses += 1;
+        }
+    }
+
+    misses as f32 / (indices.len() / 3) as f32
+}
+
+/// Return the triangles of a triangle list in an order that makes good use of a vertex cache of
+/// `cache_size` entries. The triangles themselves, and their winding, stay the same.
+pub fn optimize(indices: &[u16], vertex_count: usize, cache_size: usize) -> Vec<u16> {
+    let triangles = indices.len() / 3;
+
+    // the triangles around every vertex
+    let mut around: Vec<Vec<usize>> = (0..vertex_count).map(|_| Vec::new()).collect();
+    for t in 0..triangles {
+        for c in 0..3 {
+            around[indices[t * 3 + c] as usize].push(t);
+        }
+    }
+
+    let mut live: Vec<usize> = around.iter().map(|a| a.len()).collect();
+    let mut stamps: Vec<usize> = (0..vertex_count).map(|_| 0).collect();
+    let mut emitted: Vec<bool> = (0..triangles).map(|_| false).collect();
+    let mut dead_end: Vec<u16> = Vec::new();
+    let mut output: Vec<u16> = Vec::with_capacity(indices.len());
+    let mut time = cache_size + 1;
+    let mut cursor = 0;
+    let mut fan: Option<usize> = if vertex_count > 0 { Option::Some(0) } else { Option::None };
+
+    while let Some(f) = fan {
+        let mut candidates: Vec<usize> = Vec::new();
+
+        for t in around[f].iter() {
+            if emitted[*t] {
+                continue;
+            }
+            emitted[*t] = true;
+
+            for c in 0..3 {
+                let v = indices[*t * 3 + c];
+                output.push(v);
+                dead_end.push(v);
+                candidates.push(v as usize);
+
+                let v = v as usize;
+                live[v] -= 1;
+                if time - stamps[v] > cache_size {
+                    stamps[v] = time;
+                    time += 1;
+                }
+            }
+        }
+
+        // Pick the candidate that will still be in the cache after its remaining triangles are
+        // emitted and has been in there the longest.
+        let mut best: Option<usize> = Option::None;
+        let mut best_priority = 0;
+        for v in candidates.iter() {
+            let v = *v;
+            if live[v] == 0 {
+                continue;
+            }
+            let priority = if time - stamps[v] + 2 * live[v] <= cache_size { time - stamps[v] } else { 0 };
+            if best.is_none() || priority > best_priority {
+                best = Option::Some(v);
+                best_priority = priority;
+            }
+        }
+
+        fan = match best {
+            Some(v) => Option::Some(v),
+            None => {
+                // Fall back on a recently used vertex that still has triangles, and failing
+                // that the next one in input order.
+                let mut next: Option<usize> = Option::None;
+                while let Some(v) = dead_end.pop() {
+                    if live[v as usize] > 0 {
+                        next = Option::Some(v as usize);
+                        break;
+                    }
+                }
+                while next.is_none() && cursor < vertex_count {
+                    if live[cursor] > 0 {
+                        next = Option::Some(cursor);
+                    }
+                    cursor += 1;
+                }
+                next
+            },
+        };
+    }
+
+    output
+}
+
+/// Renumber the vertices in the order the triangle list first uses them so the vertex data is
+/// read from memory front to back. The indices are rewritten in place and the returned list
+/// gives the old index of every new vertex, vertices no triangle uses are left out of it.
+pub fn reorder_vertices(indices: &mut [u16], vertex_count: usize) -> Vec<u16> {
+    let mut map: Vec<Option<u16>> = (0..vertex_count).map(|_| Option::None).collect();
+    let mut order: Vec<u16> = Vec::with_capacity(vertex_count);
+
+    for i in indices.iter_mut() {
+        let old = *i as usize;
+        *i = match map[old] {
+            Some(new) => new,
+            None => {
+                let new = order.len() as u16;
+                map[old] = Option::Some(new);
+                order.push(old as u16);
+                new
+            },
+        };
+    }
+
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{acmr, optimize, reorder_vertices, DEFAULT_CACHE_SIZE};
+
+    /// Return a grid of `n` by `n` quads split into triangles, row by row.
+    fn grid(n: usize) -> (Vec<u16>, usize) {
+        let mut indices: Vec<u16> = Vec::new();
+        for y in 0..n {
+            for x in 0..n {
+                let a = (y * (n + 1) + x) as u16;
+                let b = a + 1;
+                let c = a + (n + 1) as u16 + 1;
+                let d = a + (n + 1) as u16;
+                indices.push_all(&[a, b, c, a, c, d]);
+            }
+        }
+        (indices, (n + 1) * (n + 1))
+    }
+
+    /// Return the triangles turned so they start at their smallest index, which keeps the
+    /// winding, in sorted order.
+    fn triangles(indices: &[u16]) -> Vec<(u16, u16, u16)> {
+        let mut out: Vec<(u16, u16, u16)> = indices.chunks(3).map(|t| {
+            if t[0] <= t[1] && t[0] <= t[2] {
+                (t[0], t[1], t[2])
+            } else if t[1] <= t[2] {
+                (t[1], t[2], t[0])
+            } else {
+                (t[2], t[0], t[1])
+            }
+        }).collect();
+        out.sort();
+        out
+    }
+
+    #[test]
+    fn optimize_keeps_the_triangles() {
+        let (indices, count) = grid(8);
+        let optimized = optimize(indices.as_slice(), count, DEFAULT_CACHE_SIZE);
+        assert_eq!(triangles(optimized.as_slice()), triangles(indices.as_slice()));
+    }
+
+    #[test]
+    fn optimize_does_not_make_the_acmr_worse() {
+        let (indices, count) = grid(32);
+        let optimized = optimize(indices.as_slice(), count, DEFAULT_CACHE_SIZE);
+        let before = acmr(indices.as_slice(), DEFAULT_CACHE_SIZE);
+        let after = acmr(optimized.as_slice(), DEFAULT_CACHE_SIZE);
+        assert!(after <= before, "acmr went from {} to {}", before, after);
+    }
+
+    #[test]
+    fn reorder_vertices_remaps_every_index() {
+        let (indices, count) = grid(4);
+        let optimized = optimize(indices.as_slice(), count, DEFAULT_CACHE_SIZE);
+        let mut reordered = optimized.clone();
+        let order = reorder_vertices(reordered.as_mut_slice(), count);
+
+        assert_eq!(order.len(), count);
+        for (new, old) in reordered.iter().zip(optimized.iter()) {
+            assert_eq!(order[*new as usize], *old);
+        }
+        // first use order
+        let mut next = 0;
+        for i in reordered.iter() {
+            assert!(*i <= next);
+            if *i == next {
+                next += 1;
+            }
+        }
+    }
+}